@@ -1,11 +1,13 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::Json,
-    routing::{get, post},
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, post},
     Router,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tracing::info;
@@ -13,6 +15,9 @@ use tracing::info;
 use crate::{
     config::Config,
     health_aggregation::{AggregatedHealth, HealthCheckManager},
+    middleware::cache::{CacheEntryInfo, CacheRegistry},
+    middleware::quota::{QuotaManager, QuotaPeriod},
+    routes::plan::{dry_run, route_plan, DryRunRequest},
 };
 
 /// Admin API state
@@ -20,6 +25,9 @@ use crate::{
 pub struct AdminState {
     pub config: Arc<RwLock<Config>>,
     pub health_manager: Arc<HealthCheckManager>,
+    pub caches: Arc<CacheRegistry>,
+    /// Consumption quotas, when configured
+    pub quotas: Option<Arc<QuotaManager>>,
 }
 
 /// Gateway information response
@@ -42,6 +50,8 @@ pub struct ReloadResponse {
 /// Route information
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RouteInfo {
+    /// Position of the route in the configuration, used by the per-route endpoints
+    pub id: usize,
     pub method: String,
     pub path: String,
     pub subrequests_count: usize,
@@ -56,8 +66,20 @@ pub fn create_admin_router(state: AdminState) -> Router {
         .route("/admin/config", get(get_current_config))
         .route("/admin/config/reload", post(reload_config))
         .route("/admin/routes", get(list_routes))
+        .route("/admin/routes/:id/plan", get(get_route_plan))
+        .route("/admin/routes/:id/dry-run", post(dry_run_route))
         .route("/admin/clients", get(list_clients))
         .route("/admin/client/:id", get(get_client_info))
+        .route("/admin/cache", delete(clear_cache))
+        .route("/admin/cache/tags/:tag", delete(invalidate_cache_tag))
+        .route(
+            "/admin/cache/keys",
+            get(list_cache_keys).delete(remove_cache_keys),
+        )
+        .route(
+            "/admin/quotas/:consumer",
+            get(get_quota_usage).delete(reset_quota_usage),
+        )
         .with_state(state)
 }
 
@@ -108,7 +130,9 @@ async fn list_routes(State(state): State<AdminState>) -> Json<Vec<RouteInfo>> {
     let routes: Vec<RouteInfo> = config
         .routes
         .iter()
-        .map(|r| RouteInfo {
+        .enumerate()
+        .map(|(id, r)| RouteInfo {
+            id,
             method: r.method.clone(),
             path: r.path.clone(),
             subrequests_count: r.subrequests.len(),
@@ -119,6 +143,180 @@ async fn list_routes(State(state): State<AdminState>) -> Json<Vec<RouteInfo>> {
     Json(routes)
 }
 
+/// Get the execution plan of a route as JSON, or as Graphviz DOT with `?format=dot`
+async fn get_route_plan(
+    State(state): State<AdminState>,
+    Path(id): Path<usize>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let config = state.config.read().await;
+    let Some(route) = config.routes.get(id) else {
+        return route_not_found(id);
+    };
+
+    match route_plan(route) {
+        Ok(plan) if params.get("format").map(String::as_str) == Some("dot") => {
+            ([(header::CONTENT_TYPE, "text/vnd.graphviz")], plan.dot).into_response()
+        }
+        Ok(plan) => Json(plan).into_response(),
+        Err(e) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+/// Evaluate a sample request against a route without calling any backend
+async fn dry_run_route(
+    State(state): State<AdminState>,
+    Path(id): Path<usize>,
+    Json(request): Json<DryRunRequest>,
+) -> Response {
+    let config = state.config.read().await;
+    let Some(route) = config.routes.get(id) else {
+        return route_not_found(id);
+    };
+
+    info!(
+        "Admin API: Dry run of route {} {}",
+        route.method, route.path
+    );
+
+    match dry_run(route, request) {
+        Ok(steps) => Json(json!({
+            "method": route.method,
+            "path": route.path,
+            "subrequests": steps,
+        }))
+        .into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+fn route_not_found(id: usize) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("Route {} not found", id) })),
+    )
+        .into_response()
+}
+
+/// Cache keys to purge
+#[derive(Debug, Deserialize)]
+pub struct CacheKeysRequest {
+    pub keys: Vec<String>,
+}
+
+/// Remove every cached response
+async fn clear_cache(State(state): State<AdminState>) -> StatusCode {
+    info!("Admin API: Clearing response caches");
+    state.caches.clear().await;
+    StatusCode::NO_CONTENT
+}
+
+/// Remove the cached responses carrying a tag
+async fn invalidate_cache_tag(
+    State(state): State<AdminState>,
+    Path(tag): Path<String>,
+) -> Json<serde_json::Value> {
+    info!("Admin API: Invalidating cache tag {}", tag);
+    let invalidated = state
+        .caches
+        .invalidate_tags(std::slice::from_ref(&tag))
+        .await;
+    Json(json!({ "tag": tag, "invalidated": invalidated }))
+}
+
+/// List the cached responses held by this instance
+async fn list_cache_keys(State(state): State<AdminState>) -> Json<Vec<CacheEntryInfo>> {
+    Json(state.caches.entries())
+}
+
+/// Remove cached responses by key
+async fn remove_cache_keys(
+    State(state): State<AdminState>,
+    Json(request): Json<CacheKeysRequest>,
+) -> StatusCode {
+    info!("Admin API: Removing {} cache keys", request.keys.len());
+    state.caches.remove_keys(&request.keys).await;
+    StatusCode::NO_CONTENT
+}
+
+/// Quota endpoint parameters
+#[derive(Debug, Deserialize)]
+pub struct QuotaQuery {
+    /// Tier whose limits are reported (default: the limits of consumers without a tier)
+    pub tier: Option<String>,
+    /// Period to reset (default: all of them)
+    pub period: Option<QuotaPeriod>,
+}
+
+/// Get a consumer's usage of the current quota periods, e.g. of `api_key:abc`
+/// with either the API key or its hash
+async fn get_quota_usage(
+    State(state): State<AdminState>,
+    Path(consumer): Path<String>,
+    Query(query): Query<QuotaQuery>,
+) -> Response {
+    let Some(quotas) = &state.quotas else {
+        return quotas_not_configured();
+    };
+    let consumer = quotas.resolve_consumer_id(&consumer);
+
+    match quotas.usage(&consumer, query.tier.as_deref()).await {
+        Ok(usage) => Json(json!({
+            "consumer": consumer,
+            "tier": query.tier,
+            "usage": usage,
+        }))
+        .into_response(),
+        Err(e) => quota_store_error(e),
+    }
+}
+
+/// Reset a consumer's usage of the current quota periods
+async fn reset_quota_usage(
+    State(state): State<AdminState>,
+    Path(consumer): Path<String>,
+    Query(query): Query<QuotaQuery>,
+) -> Response {
+    let Some(quotas) = &state.quotas else {
+        return quotas_not_configured();
+    };
+    let consumer = quotas.resolve_consumer_id(&consumer);
+
+    info!("Admin API: Resetting quota usage of {}", consumer);
+    let periods = match query.period {
+        Some(period) => vec![period],
+        None => QuotaPeriod::ALL.to_vec(),
+    };
+    match quotas.reset(&consumer, &periods).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => quota_store_error(e),
+    }
+}
+
+fn quotas_not_configured() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "Quotas are not configured" })),
+    )
+        .into_response()
+}
+
+fn quota_store_error(e: anyhow::Error) -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({ "error": format!("Quota store error: {}", e) })),
+    )
+        .into_response()
+}
+
 /// List all clients
 async fn list_clients(State(state): State<AdminState>) -> Json<Vec<String>> {
     let config = state.config.read().await;
@@ -145,7 +343,6 @@ async fn get_client_info(
 mod tests {
     use super::*;
     use crate::config::{ClientConfig, HttpClientConfig, RouteConfig, ServerConfig};
-    use std::collections::HashMap;
 
     #[tokio::test]
     async fn test_gateway_info() {
@@ -158,6 +355,8 @@ mod tests {
         let state = AdminState {
             config: Arc::new(RwLock::new(config)),
             health_manager: Arc::new(HealthCheckManager::new()),
+            caches: Default::default(),
+            quotas: None,
         };
 
         let info = get_gateway_info(State(state)).await;
@@ -181,6 +380,7 @@ mod tests {
                 timeout: 30,
                 retry: None,
                 circuit_breaker: None,
+                max_concurrent: None,
             }),
         );
 
@@ -194,6 +394,18 @@ mod tests {
                 execution_mode: crate::config::ExecutionMode::Parallel,
                 traffic_split: None,
                 traffic_mirror: None,
+                proxy: None,
+                response: None,
+                on_error: crate::config::ErrorPolicy::FailFast,
+                merge: None,
+                timeout_ms: None,
+                max_concurrency: None,
+                cache: None,
+                invalidate_tags: vec![],
+                rate_limit: None,
+                max_concurrent: None,
+                auth: None,
+                ip_filter: None,
             }],
             server: ServerConfig::default(),
         };
@@ -201,10 +413,271 @@ mod tests {
         let state = AdminState {
             config: Arc::new(RwLock::new(config)),
             health_manager: Arc::new(HealthCheckManager::new()),
+            caches: Default::default(),
+            quotas: None,
         };
 
         let routes = list_routes(State(state)).await;
         assert_eq!(routes.0.len(), 1);
         assert_eq!(routes.0[0].path, "/test");
     }
+
+    #[tokio::test]
+    async fn test_route_plan_and_dry_run_endpoints() {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::ServiceExt;
+
+        let yaml = r#"
+clients:
+  api:
+    type: http
+    base_url: "https://api.example.com"
+routes:
+  - method: GET
+    path: /users/:id
+    subrequests:
+      - name: user
+        client_id: api
+        type: http
+        uri: /users/${request.path.id}
+      - name: posts
+        client_id: api
+        type: http
+        uri: /posts?author=${subrequest.user.body.name}
+        depends_on: [user]
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let router = create_admin_router(AdminState {
+            config: Arc::new(RwLock::new(config)),
+            health_manager: Arc::new(HealthCheckManager::new()),
+            caches: Default::default(),
+            quotas: None,
+        });
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/routes/0/plan?format=dot")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let dot = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&dot).contains("\"user\" -> \"posts\""));
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/admin/routes/0/dry-run")
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        r#"{"path_params": {"id": "7"}, "results": {"user": {"body": {"name": "ada"}}}}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["subrequests"][0]["request"]["uri"], "/users/7");
+        assert_eq!(
+            body["subrequests"][1]["request"]["uri"],
+            "/posts?author=ada"
+        );
+
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/routes/5/plan")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_cache_endpoints() {
+        use crate::clients::ClientManager;
+        use crate::routes::{build_router, handler::AppState};
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::ServiceExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let backend = Router::new().route("/users/:id", get(|| async { "user" }));
+        tokio::spawn(async move { axum::serve(listener, backend).await.unwrap() });
+
+        let yaml = format!(
+            r#"
+clients:
+  api:
+    type: http
+    base_url: "{base_url}"
+routes:
+  - method: GET
+    path: /users/:id
+    cache:
+      tags: ["user:${{request.path.id}}", "users"]
+    subrequests:
+      - client_id: api
+        type: http
+        uri: /users/${{request.path.id}}
+"#
+        );
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        let caches = Arc::new(CacheRegistry::default());
+        let client_manager = ClientManager::from_config(&config).await.unwrap();
+        let gateway = build_router(AppState {
+            config: Arc::new(config.clone()),
+            client_manager: Arc::new(client_manager),
+            caches: caches.clone(),
+            quotas: None,
+        });
+        let admin = create_admin_router(AdminState {
+            config: Arc::new(RwLock::new(config)),
+            health_manager: Arc::new(HealthCheckManager::new()),
+            caches,
+            quotas: None,
+        });
+
+        let send = |router: &Router, method: &str, uri: &str| {
+            router.clone().oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+        let keys = |router: &Router| {
+            let response = send(router, "GET", "/admin/cache/keys");
+            async move {
+                let body = axum::body::to_bytes(response.await.unwrap().into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+
+        send(&gateway, "GET", "/users/1").await.unwrap();
+        send(&gateway, "GET", "/users/2").await.unwrap();
+
+        let entries = keys(&admin).await;
+        assert_eq!(entries.as_array().unwrap().len(), 2);
+        assert_eq!(entries[0]["route"], "GET /users/:id");
+        assert_eq!(entries[0]["tags"], json!(["user:1", "users"]));
+
+        let response = send(&admin, "DELETE", "/admin/cache/tags/user:1")
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["invalidated"], 1);
+        assert_eq!(keys(&admin).await.as_array().unwrap().len(), 1);
+
+        let response = send(&admin, "DELETE", "/admin/cache").await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(keys(&admin).await.as_array().unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_quota_endpoints() {
+        use crate::config::QuotaConfig;
+        use crate::middleware::consumer::hash_api_key;
+        use crate::middleware::quota_store::MemoryQuotaStore;
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::ServiceExt;
+
+        let yaml = r#"
+clients: {}
+routes: []
+server:
+  security:
+    api_keys:
+      keys: ["partner"]
+  quota:
+    monthly: 1000
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let quota_config: QuotaConfig = config.server.quota.clone().unwrap();
+        let quotas = Arc::new(QuotaManager::new(
+            &quota_config,
+            &config.server,
+            Arc::new(MemoryQuotaStore::default()),
+        ));
+        let partner = format!("api_key:{}", hash_api_key("partner"));
+        for _ in 0..3 {
+            quotas.consume(&partner, None).await.unwrap();
+        }
+
+        let admin = create_admin_router(AdminState {
+            config: Arc::new(RwLock::new(config)),
+            health_manager: Arc::new(HealthCheckManager::new()),
+            caches: Default::default(),
+            quotas: Some(quotas),
+        });
+        let send = |method: &str, uri: &str| {
+            admin.clone().oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+        let usage = || async {
+            let response = send("GET", &format!("/admin/quotas/{}", partner))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        let body = usage().await;
+        assert_eq!(body["consumer"], partner.as_str());
+        assert_eq!(body["usage"][1]["period"], "monthly");
+        assert_eq!(body["usage"][1]["used"], 3);
+        assert_eq!(body["usage"][1]["remaining"], 997);
+        assert_eq!(body["usage"][0]["limit"], serde_json::Value::Null);
+
+        // Operators may name the consumer by its API key
+        let response = send("GET", "/admin/quotas/api_key:partner").await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["consumer"], partner.as_str());
+        assert_eq!(body["usage"][1]["used"], 3);
+
+        let response = send("DELETE", "/admin/quotas/api_key:partner?period=monthly")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(usage().await["usage"][1]["used"], 0);
+
+        let response = send("DELETE", "/admin/quotas/api_key:partner?period=weekly")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
 }
@@ -1,7 +1,9 @@
+use crate::clients::retry::{self, RetryBudget};
 use crate::clients::LoadBalancer;
 use crate::config::{HttpClientConfig, LoadBalanceStrategy};
 use crate::middleware::{create_circuit_breaker, CircuitBreakerConfig, CircuitBreakerWrapper};
 use anyhow::Result;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use reqwest::{Client, Method};
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -15,6 +17,7 @@ pub struct HttpClient {
     client: Client,
     circuit_breaker: Option<Arc<CircuitBreakerWrapper>>,
     load_balancer: Option<LoadBalancer>,
+    retry_budget: Option<Arc<RetryBudget>>,
 }
 
 // Manual Debug implementation to handle CircuitBreaker
@@ -25,6 +28,7 @@ impl std::fmt::Debug for HttpClient {
             .field("client", &self.client)
             .field("circuit_breaker", &self.circuit_breaker.is_some())
             .field("load_balancer", &self.load_balancer.is_some())
+            .field("retry_budget", &self.retry_budget)
             .finish()
     }
 }
@@ -56,11 +60,19 @@ impl HttpClient {
             None
         };
 
+        // Retry budget shared by every clone of this client
+        let retry_budget = config
+            .retry
+            .as_ref()
+            .and_then(|retry| retry.budget.as_ref())
+            .map(|budget| Arc::new(RetryBudget::new(budget)));
+
         Ok(Self {
             config,
             client,
             circuit_breaker,
             load_balancer,
+            retry_budget,
         })
     }
 
@@ -74,20 +86,10 @@ impl HttpClient {
         query_params: HashMap<String, String>,
     ) -> Result<HttpResponse> {
         // Check circuit breaker before attempting request
-        if let Some(ref cb) = self.circuit_breaker {
-            if !cb.is_call_permitted() {
-                warn!("Circuit breaker is open, rejecting request to {}", uri);
-                return Err(anyhow::anyhow!("Circuit breaker is open"));
-            }
-        }
+        self.check_circuit_breaker(uri)?;
 
         // Select backend URL using load balancer if available
-        let base_url = if let Some(ref lb) = self.load_balancer {
-            lb.select_backend()
-                .ok_or_else(|| anyhow::anyhow!("No available backends"))?
-        } else {
-            self.config.base_url.clone()
-        };
+        let base_url = self.select_base_url(None)?;
 
         let url = format!("{}{}", base_url, uri);
         let method_obj = Method::from_bytes(method.as_bytes())?;
@@ -100,106 +102,256 @@ impl HttpClient {
             query_params.len()
         );
 
-        // Determine retry config
-        let max_retries = self
-            .config
-            .retry
-            .as_ref()
-            .map(|r| r.max_retries)
-            .unwrap_or(0);
-        let initial_backoff = self
-            .config
-            .retry
-            .as_ref()
-            .map(|r| r.initial_backoff_ms)
-            .unwrap_or(100);
-        let max_backoff = self
-            .config
-            .retry
-            .as_ref()
-            .map(|r| r.max_backoff_ms)
-            .unwrap_or(5000);
+        let mut request = self.client.request(method_obj, &url);
 
-        let mut attempt = 0;
-        #[allow(unused_assignments)]
-        let mut last_error: Option<reqwest::Error> = None;
+        // Add default headers from client config
+        for (key, value) in &self.config.headers {
+            request = request.header(key, value);
+        }
 
-        loop {
-            let mut request = self.client.request(method_obj.clone(), &url);
+        // Add request-specific headers (these override defaults)
+        for (key, value) in &headers {
+            request = request.header(key, value);
+        }
 
-            // Add default headers from client config
-            for (key, value) in &self.config.headers {
-                request = request.header(key, value);
-            }
+        // Add query parameters
+        for (key, value) in &query_params {
+            request = request.query(&[(key.clone(), value.clone())]);
+        }
 
-            // Add request-specific headers (these override defaults)
-            for (key, value) in &headers {
-                request = request.header(key, value);
-            }
+        // Add body if present
+        if let Some(body_content) = body {
+            request = request.body(body_content);
+        }
 
-            // Add query parameters
-            for (key, value) in &query_params {
-                request = request.query(&[(key.clone(), value.clone())]);
-            }
+        let response = self.send_with_retries(request.build()?, base_url).await?;
 
-            // Add body if present
-            if let Some(ref body_content) = body {
-                request = request.body(body_content.clone());
-            }
+        let status = response.status().as_u16();
+        let headers = collect_headers(response.headers());
+        let body = response.text().await?;
 
-            match request.send().await {
-                Ok(response) => {
-                    let status = response.status().as_u16();
-                    let headers = response
-                        .headers()
-                        .iter()
-                        .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("").to_string()))
-                        .collect();
+        debug!(
+            "HTTP response received: status={}, body_len={}",
+            status,
+            body.len()
+        );
+
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
 
-                    let body = response.text().await?;
+    /// Forward a request to the backend and return the raw, unbuffered response.
+    /// Requests with a streamed body cannot be replayed, so they are never retried.
+    pub async fn proxy_request(
+        &self,
+        method: Method,
+        path_and_query: &str,
+        headers: HeaderMap,
+        body: reqwest::Body,
+    ) -> Result<reqwest::Response> {
+        self.check_circuit_breaker(path_and_query)?;
+
+        let base_url = self.select_base_url(None)?;
+        let url = format!("{}{}", base_url, path_and_query);
+
+        debug!("Proxying HTTP request: {} {}", method, url);
+
+        // Client default headers apply unless the incoming request overrides them
+        let mut request_headers = HeaderMap::new();
+        for (key, value) in &self.config.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(key.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                request_headers.insert(name, value);
+            }
+        }
+        for (name, value) in &headers {
+            request_headers.insert(name.clone(), value.clone());
+        }
 
-                    debug!(
-                        "HTTP response received: status={}, body_len={}",
-                        status,
-                        body.len()
-                    );
+        let request = self
+            .client
+            .request(method, &url)
+            .headers(request_headers)
+            .body(body)
+            .build()?;
 
-                    // Record success with circuit breaker
-                    if let Some(ref cb) = self.circuit_breaker {
-                        let _ = cb.call(|| Ok::<(), ()>(()));
-                    }
+        self.send_with_retries(request, base_url).await
+    }
 
-                    return Ok(HttpResponse {
-                        status,
-                        headers,
-                        body,
-                    });
-                }
-                Err(e) => {
-                    last_error = Some(e);
-                    attempt += 1;
+    /// Send a request, retrying transport errors and retryable statuses according to
+    /// the retry policy, and record the final outcome with the circuit breaker.
+    /// Retries go to a different backend when a load balancer is configured.
+    async fn send_with_retries(
+        &self,
+        mut request: reqwest::Request,
+        mut base_url: String,
+    ) -> Result<reqwest::Response> {
+        if let Some(budget) = &self.retry_budget {
+            budget.record_request();
+        }
 
-                    if attempt > max_retries {
-                        break;
-                    }
+        let method = request.method().clone();
+        let mut attempt = 0;
+        let mut previous_delay = Duration::ZERO;
 
-                    // Calculate exponential backoff
-                    let backoff = (initial_backoff * 2_u64.pow(attempt - 1)).min(max_backoff);
-                    debug!(
-                        "Request failed, retrying in {}ms (attempt {})",
-                        backoff, attempt
-                    );
-                    tokio::time::sleep(Duration::from_millis(backoff)).await;
+        loop {
+            let retry_request = request.try_clone();
+            let outcome = self.client.execute(request).await;
+            attempt += 1;
+
+            let retry_after = match &outcome {
+                Ok(response) if !self.is_retryable_status(response.status().as_u16()) => None,
+                Ok(response) => Some(
+                    response
+                        .headers()
+                        .get(reqwest::header::RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(retry::parse_retry_after),
+                ),
+                Err(_) => Some(None),
+            };
+
+            let next = match (retry_after, retry_request) {
+                (Some(retry_after), Some(next_request)) => self
+                    .retry_delay(&method, attempt, previous_delay, retry_after)
+                    .map(|delay| (delay, next_request)),
+                _ => None,
+            };
+
+            let Some((delay, next_request)) = next else {
+                return match outcome {
+                    Ok(response) => {
+                        let status = response.status().as_u16();
+                        self.record_outcome(!self.is_failure_status(status));
+                        Ok(response)
+                    }
+                    Err(e) => {
+                        self.record_outcome(false);
+                        Err(e.into())
+                    }
+                };
+            };
+
+            match &outcome {
+                Ok(response) => debug!(
+                    "Upstream returned {}, retrying in {}ms (attempt {})",
+                    response.status(),
+                    delay.as_millis(),
+                    attempt
+                ),
+                Err(e) => debug!(
+                    "Request failed ({}), retrying in {}ms (attempt {})",
+                    e,
+                    delay.as_millis(),
+                    attempt
+                ),
+            }
+            drop(outcome);
+
+            tokio::time::sleep(delay).await;
+            previous_delay = delay;
+            request = next_request;
+
+            // Prefer another backend for the retry
+            if let Ok(next_base) = self.select_base_url(Some(&base_url)) {
+                if next_base != base_url {
+                    if let Some(url) = rebase_url(request.url(), &base_url, &next_base) {
+                        *request.url_mut() = url;
+                        base_url = next_base;
+                    }
                 }
             }
         }
+    }
 
-        // Record failure with circuit breaker
+    /// Reject the request early if the circuit breaker is open
+    fn check_circuit_breaker(&self, uri: &str) -> Result<()> {
         if let Some(ref cb) = self.circuit_breaker {
-            let _ = cb.call(|| Err::<(), ()>(()));
+            if !cb.is_call_permitted() {
+                warn!("Circuit breaker is open, rejecting request to {}", uri);
+                return Err(anyhow::anyhow!("Circuit breaker is open"));
+            }
         }
+        Ok(())
+    }
 
-        Err(last_error.unwrap().into())
+    /// Select the backend base URL, using the load balancer if available.
+    /// `avoid` is a backend to skip when another one is available.
+    fn select_base_url(&self, avoid: Option<&str>) -> Result<String> {
+        if let Some(ref lb) = self.load_balancer {
+            let selected = match avoid {
+                Some(avoid) => lb.select_backend_excluding(avoid),
+                None => lb.select_backend(),
+            };
+            selected.ok_or_else(|| anyhow::anyhow!("No available backends"))
+        } else {
+            Ok(self.config.base_url.clone())
+        }
+    }
+
+    /// Delay before the given retry attempt, or None when the request must not be retried:
+    /// retries exhausted, non-idempotent method, Retry-After beyond the maximum backoff,
+    /// or retry budget spent
+    fn retry_delay(
+        &self,
+        method: &Method,
+        attempt: u32,
+        previous: Duration,
+        retry_after: Option<Duration>,
+    ) -> Option<Duration> {
+        let retry = self.config.retry.as_ref()?;
+        if attempt > retry.max_retries {
+            return None;
+        }
+        if retry.idempotent_only && !retry::is_idempotent(method) {
+            return None;
+        }
+
+        let delay = match retry_after.filter(|_| retry.respect_retry_after) {
+            Some(delay) if delay > Duration::from_millis(retry.max_backoff_ms) => {
+                debug!(
+                    "Retry-After of {}s exceeds the maximum backoff, not retrying",
+                    delay.as_secs()
+                );
+                return None;
+            }
+            Some(delay) => delay,
+            None => retry::backoff(retry, attempt, previous),
+        };
+
+        if let Some(budget) = &self.retry_budget {
+            if !budget.try_withdraw() {
+                warn!("Retry budget exhausted for {}", self.config.base_url);
+                return None;
+            }
+        }
+
+        Some(delay)
+    }
+
+    /// Whether an upstream status should be retried
+    fn is_retryable_status(&self, status: u16) -> bool {
+        self.config
+            .retry
+            .as_ref()
+            .is_some_and(|retry| retry.retry_on_status.contains(&status))
+    }
+
+    /// Whether an upstream status counts as a failure for the circuit breaker
+    fn is_failure_status(&self, status: u16) -> bool {
+        status >= 500 || self.is_retryable_status(status)
+    }
+
+    /// Record the outcome of a request with the circuit breaker
+    fn record_outcome(&self, success: bool) {
+        if let Some(ref cb) = self.circuit_breaker {
+            let _ = cb.call(|| if success { Ok(()) } else { Err(()) });
+        }
     }
 
     #[allow(dead_code)]
@@ -208,6 +360,41 @@ impl HttpClient {
     }
 }
 
+/// Move a URL built on `old_base` onto `new_base`, keeping its path and query
+fn rebase_url(url: &reqwest::Url, old_base: &str, new_base: &str) -> Option<reqwest::Url> {
+    let old_base = reqwest::Url::parse(old_base).ok()?;
+    let rest = url
+        .as_str()
+        .strip_prefix(old_base.as_str().trim_end_matches('/'))?;
+    reqwest::Url::parse(&format!("{}{}", new_base.trim_end_matches('/'), rest)).ok()
+}
+
+/// Collect response headers into a map, combining repeated headers.
+/// Repeated values are joined with ", ", except Set-Cookie which cannot be
+/// combined that way and is joined with newlines instead.
+fn collect_headers(headers: &HeaderMap) -> HashMap<String, String> {
+    let mut collected: HashMap<String, String> = HashMap::new();
+
+    for (name, value) in headers {
+        let value = value.to_str().unwrap_or("");
+        let separator = if name == reqwest::header::SET_COOKIE {
+            "\n"
+        } else {
+            ", "
+        };
+
+        collected
+            .entry(name.to_string())
+            .and_modify(|existing| {
+                existing.push_str(separator);
+                existing.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+
+    collected
+}
+
 /// HTTP response structure
 #[derive(Debug, Clone)]
 pub struct HttpResponse {
@@ -240,6 +427,7 @@ mod tests {
             timeout: 30,
             retry: None,
             circuit_breaker: None,
+            max_concurrent: None,
         };
 
         let client = HttpClient::new(config);
@@ -261,6 +449,7 @@ mod tests {
             timeout: 30,
             retry: None,
             circuit_breaker: None,
+            max_concurrent: None,
         };
 
         let client = HttpClient::new(config);
@@ -268,6 +457,147 @@ mod tests {
         assert!(client.unwrap().load_balancer.is_some());
     }
 
+    #[test]
+    fn test_collect_headers_combines_values() {
+        let mut headers = HeaderMap::new();
+        headers.append("cache-control", "no-cache".parse().unwrap());
+        headers.append("cache-control", "no-store".parse().unwrap());
+        headers.append("set-cookie", "a=1".parse().unwrap());
+        headers.append("set-cookie", "b=2".parse().unwrap());
+
+        let collected = collect_headers(&headers);
+        assert_eq!(collected["cache-control"], "no-cache, no-store");
+        assert_eq!(collected["set-cookie"], "a=1\nb=2");
+    }
+
+    fn retry_client(base_url: &str, retry: crate::config::RetryConfig) -> HttpClient {
+        HttpClient::new(HttpClientConfig {
+            base_url: base_url.to_string(),
+            backends: vec![],
+            load_balance: None,
+            headers: HashMap::new(),
+            min_connections: 1,
+            max_connections: 10,
+            timeout: 30,
+            retry: Some(retry),
+            circuit_breaker: None,
+            max_concurrent: None,
+        })
+        .unwrap()
+    }
+
+    fn retry_config(max_retries: u32) -> crate::config::RetryConfig {
+        crate::config::RetryConfig {
+            max_retries,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 300,
+            retry_on_status: vec![503],
+            respect_retry_after: true,
+            jitter: crate::config::JitterStrategy::None,
+            idempotent_only: true,
+            budget: None,
+        }
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let client = retry_client("https://api.example.com", retry_config(3));
+        let delay = |attempt, retry_after| {
+            client.retry_delay(&Method::GET, attempt, Duration::ZERO, retry_after)
+        };
+
+        assert_eq!(delay(1, None), Some(Duration::from_millis(100)));
+        assert_eq!(delay(2, None), Some(Duration::from_millis(200)));
+        assert_eq!(delay(3, None), Some(Duration::from_millis(300)));
+        assert_eq!(delay(4, None), None);
+
+        // Retry-After replaces the backoff, unless it exceeds the maximum backoff
+        assert_eq!(delay(1, Some(Duration::ZERO)), Some(Duration::ZERO));
+        assert_eq!(delay(1, Some(Duration::from_secs(5))), None);
+
+        // Non-idempotent methods are not retried
+        assert_eq!(
+            client.retry_delay(&Method::POST, 1, Duration::ZERO, None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_retry_budget_limits_retries() {
+        let client = retry_client(
+            "https://api.example.com",
+            crate::config::RetryConfig {
+                budget: Some(crate::config::RetryBudgetConfig {
+                    ratio: 0.0,
+                    min_per_second: 0,
+                }),
+                ..retry_config(3)
+            },
+        );
+
+        assert!(client
+            .retry_delay(&Method::GET, 1, Duration::ZERO, None)
+            .is_some());
+        assert!(client
+            .retry_delay(&Method::GET, 1, Duration::ZERO, None)
+            .is_none());
+    }
+
+    #[test]
+    fn test_rebase_url() {
+        let url = reqwest::Url::parse("http://a.example.com/api/users?id=1").unwrap();
+        let rebased = rebase_url(&url, "http://a.example.com/api", "http://b.example.com/api");
+        assert_eq!(
+            rebased.unwrap().as_str(),
+            "http://b.example.com/api/users?id=1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retries_on_status_with_retry_after() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let backend = axum::Router::new().route(
+            "/flaky",
+            axum::routing::any(move || {
+                let counter = counter.clone();
+                async move {
+                    if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                        (
+                            axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                            [("retry-after", "0")],
+                            "busy",
+                        )
+                    } else {
+                        (axum::http::StatusCode::OK, [("retry-after", "0")], "ok")
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, backend).await.unwrap() });
+
+        let client = retry_client(&format!("http://{}", addr), retry_config(3));
+        let response = client
+            .execute_request("GET", "/flaky", HashMap::new(), None, HashMap::new())
+            .await
+            .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // POST is not idempotent: the 503 is returned as-is
+        calls.store(0, Ordering::SeqCst);
+        let response = client
+            .execute_request("POST", "/flaky", HashMap::new(), None, HashMap::new())
+            .await
+            .unwrap();
+        assert_eq!(response.status, 503);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
     #[test]
     fn test_http_response_is_success() {
         let response = HttpResponse {
@@ -42,6 +42,20 @@ impl LoadBalancer {
         self.backends.get(index).cloned()
     }
 
+    /// Select a backend other than `exclude` when another one is available
+    pub fn select_backend_excluding(&self, exclude: &str) -> Option<String> {
+        let selected = self.select_backend()?;
+        if selected != exclude || self.backends.len() < 2 {
+            return Some(selected);
+        }
+
+        // The strategy picked the excluded backend again: fall back to the next one
+        let index = self.get_backend_index(exclude)?;
+        self.backends
+            .get((index + 1) % self.backends.len())
+            .cloned()
+    }
+
     /// Round-robin selection
     fn round_robin(&self) -> usize {
         let current = self.round_robin_counter.fetch_add(1, Ordering::Relaxed);
@@ -161,4 +175,27 @@ mod tests {
         // Should now select backend 1 (fewer connections)
         assert_eq!(lb.select_backend(), Some("http://backend2.com".to_string()));
     }
+
+    #[test]
+    fn test_select_backend_excluding() {
+        let backends = vec![
+            "http://backend1.com".to_string(),
+            "http://backend2.com".to_string(),
+        ];
+
+        let lb = LoadBalancer::new(backends, LoadBalanceStrategy::LeastConnections);
+        assert_eq!(
+            lb.select_backend_excluding("http://backend1.com"),
+            Some("http://backend2.com".to_string())
+        );
+
+        let single = LoadBalancer::new(
+            vec!["http://only.com".to_string()],
+            LoadBalanceStrategy::RoundRobin,
+        );
+        assert_eq!(
+            single.select_backend_excluding("http://only.com"),
+            Some("http://only.com".to_string())
+        );
+    }
 }
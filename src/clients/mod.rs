@@ -2,11 +2,14 @@ pub mod http;
 pub mod load_balancer;
 pub mod mongodb;
 pub mod redis_client;
+pub mod retry;
 pub mod sql;
 
 use crate::config::{ClientConfig, Config};
+use crate::middleware::concurrency::ConcurrencyLimiter;
 use anyhow::Result;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 pub use http::HttpClient;
 pub use load_balancer::LoadBalancer;
@@ -21,6 +24,8 @@ pub struct ClientManager {
     sql_clients: HashMap<String, SqlClient>,
     mongodb_clients: HashMap<String, MongodbClient>,
     redis_clients: HashMap<String, RedisClient>,
+    /// Bulkheads of the clients that have one
+    concurrency_limiters: HashMap<String, Arc<ConcurrencyLimiter>>,
 }
 
 impl ClientManager {
@@ -30,8 +35,16 @@ impl ClientManager {
         let mut sql_clients = HashMap::new();
         let mut mongodb_clients = HashMap::new();
         let mut redis_clients = HashMap::new();
+        let mut concurrency_limiters = HashMap::new();
 
         for (client_id, client_config) in &config.clients {
+            if let Some(limit) = client_config.max_concurrent() {
+                concurrency_limiters.insert(
+                    client_id.clone(),
+                    ConcurrencyLimiter::new(format!("client {}", client_id), limit),
+                );
+            }
+
             match client_config {
                 ClientConfig::Http(http_config) => {
                     let client = HttpClient::new(http_config.clone())?;
@@ -65,6 +78,7 @@ impl ClientManager {
             sql_clients,
             mongodb_clients,
             redis_clients,
+            concurrency_limiters,
         })
     }
 
@@ -87,4 +101,9 @@ impl ClientManager {
     pub fn get_redis_client(&self, client_id: &str) -> Option<&RedisClient> {
         self.redis_clients.get(client_id)
     }
+
+    /// Get the bulkhead of a client, if it has one
+    pub fn get_concurrency_limiter(&self, client_id: &str) -> Option<&Arc<ConcurrencyLimiter>> {
+        self.concurrency_limiters.get(client_id)
+    }
 }
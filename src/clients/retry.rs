@@ -0,0 +1,202 @@
+use crate::config::{JitterStrategy, RetryBudgetConfig, RetryConfig};
+use rand::Rng;
+use reqwest::Method;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Seconds of `min_per_second` retries the budget can hold. This is also the
+/// most that `ratio` deposits can save up, so `min_per_second` must be above
+/// zero (checked by `Config::validate`).
+const BUDGET_WINDOW_SECONDS: f64 = 10.0;
+
+/// Client-wide retry budget.
+///
+/// Every request deposits `ratio` tokens and the budget refills by
+/// `min_per_second` tokens each second; every retry withdraws one token.
+/// When the budget is empty, failed requests are returned without retrying
+/// so that an unhealthy backend is not hit by a retry storm.
+#[derive(Debug)]
+pub struct RetryBudget {
+    ratio: f64,
+    min_per_second: f64,
+    capacity: f64,
+    state: Mutex<BudgetState>,
+}
+
+#[derive(Debug)]
+struct BudgetState {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RetryBudget {
+    /// Create a full retry budget from configuration
+    pub fn new(config: &RetryBudgetConfig) -> Self {
+        let min_per_second = f64::from(config.min_per_second);
+        let capacity = (min_per_second * BUDGET_WINDOW_SECONDS).max(1.0);
+
+        Self {
+            ratio: config.ratio,
+            min_per_second,
+            capacity,
+            state: Mutex::new(BudgetState {
+                tokens: capacity,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Record an original (non-retry) request
+    pub fn record_request(&self) {
+        let mut state = self.lock();
+        self.refill(&mut state);
+        state.tokens = (state.tokens + self.ratio).min(self.capacity);
+    }
+
+    /// Take a token for a retry, returning false when the budget is exhausted
+    pub fn try_withdraw(&self) -> bool {
+        let mut state = self.lock();
+        self.refill(&mut state);
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn refill(&self, state: &mut BudgetState) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.min_per_second).min(self.capacity);
+        state.last_refill = now;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BudgetState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Backoff before the given retry attempt (starting at 1), applying the configured jitter.
+/// `previous` is the delay used before the previous attempt (zero for the first retry).
+pub fn backoff(retry: &RetryConfig, attempt: u32, previous: Duration) -> Duration {
+    let initial = retry.initial_backoff_ms;
+    let cap = retry.max_backoff_ms;
+
+    let exponential = initial
+        .saturating_mul(2_u64.saturating_pow(attempt.saturating_sub(1)))
+        .min(cap);
+
+    let millis = match retry.jitter {
+        JitterStrategy::None => exponential,
+        JitterStrategy::Full => rand::thread_rng().gen_range(0..=exponential),
+        JitterStrategy::Decorrelated => {
+            let previous = (previous.as_millis() as u64).max(initial);
+            let upper = previous.saturating_mul(3).max(initial);
+            rand::thread_rng().gen_range(initial..=upper).min(cap)
+        }
+    };
+
+    Duration::from_millis(millis)
+}
+
+/// Parse a Retry-After header value: delay in seconds or an HTTP date
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.signed_duration_since(chrono::Utc::now());
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
+/// Whether a request with this method can safely be sent more than once
+pub fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE | Method::TRACE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retry_config(jitter: JitterStrategy) -> RetryConfig {
+        RetryConfig {
+            max_retries: 5,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1000,
+            retry_on_status: vec![503],
+            respect_retry_after: true,
+            jitter,
+            idempotent_only: true,
+            budget: None,
+        }
+    }
+
+    #[test]
+    fn test_exponential_backoff() {
+        let retry = retry_config(JitterStrategy::None);
+        assert_eq!(backoff(&retry, 1, Duration::ZERO).as_millis(), 100);
+        assert_eq!(backoff(&retry, 3, Duration::ZERO).as_millis(), 400);
+        assert_eq!(backoff(&retry, 10, Duration::ZERO).as_millis(), 1000);
+    }
+
+    #[test]
+    fn test_jittered_backoff_bounds() {
+        let full = retry_config(JitterStrategy::Full);
+        let decorrelated = retry_config(JitterStrategy::Decorrelated);
+
+        for _ in 0..100 {
+            assert!(backoff(&full, 3, Duration::ZERO) <= Duration::from_millis(400));
+
+            let delay = backoff(&decorrelated, 2, Duration::from_millis(200));
+            assert!(delay >= Duration::from_millis(100));
+            assert!(delay <= Duration::from_millis(600));
+
+            let capped = backoff(&decorrelated, 5, Duration::from_millis(900));
+            assert!(capped <= Duration::from_millis(1000));
+        }
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert!(parse_retry_after("soon").is_none());
+    }
+
+    #[test]
+    fn test_idempotent_methods() {
+        assert!(is_idempotent(&Method::GET));
+        assert!(is_idempotent(&Method::PUT));
+        assert!(!is_idempotent(&Method::POST));
+        assert!(!is_idempotent(&Method::PATCH));
+    }
+
+    #[test]
+    fn test_retry_budget() {
+        let budget = RetryBudget::new(&RetryBudgetConfig {
+            ratio: 0.5,
+            min_per_second: 1,
+        });
+
+        // The budget starts with ten seconds of min_per_second retries
+        for _ in 0..10 {
+            assert!(budget.try_withdraw());
+        }
+        assert!(!budget.try_withdraw());
+
+        budget.record_request();
+        assert!(!budget.try_withdraw());
+        budget.record_request();
+        assert!(budget.try_withdraw());
+    }
+}
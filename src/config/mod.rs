@@ -40,6 +40,22 @@ pub struct ServerConfig {
     /// Security configuration
     #[serde(default)]
     pub security: SecurityConfig,
+    /// How the client IP is resolved behind proxies, for IP filters and
+    /// rate limits
+    #[serde(default)]
+    pub client_ip: ClientIpConfig,
+    /// Storage shared by the response caches of all routes
+    #[serde(default)]
+    pub cache_store: CacheStoreConfig,
+    /// Request deduplication and `Idempotency-Key` handling
+    #[serde(default)]
+    pub deduplication: Option<DeduplicationConfig>,
+    /// Consumer tiers, in matching order
+    #[serde(default)]
+    pub tiers: Vec<TierConfig>,
+    /// Daily and monthly consumption quotas per consumer
+    #[serde(default)]
+    pub quota: Option<QuotaConfig>,
 }
 
 impl Default for ServerConfig {
@@ -51,6 +67,11 @@ impl Default for ServerConfig {
             max_body_size: default_max_body_size(),
             rate_limit: None,
             security: SecurityConfig::default(),
+            client_ip: ClientIpConfig::default(),
+            cache_store: CacheStoreConfig::default(),
+            deduplication: None,
+            tiers: Vec::new(),
+            quota: None,
         }
     }
 }
@@ -101,11 +122,192 @@ impl Default for LoggingConfig {
 /// Rate limiting configuration
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RateLimitConfig {
+    /// Limits of consumers without a tier, or of a tier not listed in `tiers`
+    #[serde(flatten)]
+    pub limits: RateLimits,
+    /// What requests are counted by; each key gets its own limits
+    #[serde(default)]
+    pub key: RateLimitKeyConfig,
+    /// Limits by consumer tier name (see `server.tiers`)
+    #[serde(default)]
+    pub tiers: HashMap<String, RateLimits>,
+    /// Where limiter state is kept
+    #[serde(default)]
+    pub store: RateLimitStoreConfig,
+}
+
+/// Rate limiter state: in-process, or in Redis and shared by all replicas
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum RateLimitStoreConfig {
+    #[default]
+    Memory,
+    Redis(RedisRateLimitStoreConfig),
+}
+
+/// Redis rate limit store configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RedisRateLimitStoreConfig {
+    /// Redis connection string
+    pub connection_string: String,
+    /// Prefix of every limiter key written to Redis
+    #[serde(default = "default_rate_limit_key_prefix")]
+    pub key_prefix: String,
+}
+
+fn default_rate_limit_key_prefix() -> String {
+    "pmp:ratelimit:".to_string()
+}
+
+/// Request rate limits applied to each key
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RateLimits {
     /// Requests per second
     pub requests_per_second: u64,
     /// Burst size
     #[serde(default = "default_burst_size")]
     pub burst_size: u32,
+    /// Additional limit on requests per minute
+    #[serde(default)]
+    pub requests_per_minute: Option<u64>,
+}
+
+/// Consumer tier, e.g. free, pro or internal. A consumer belongs to the first
+/// tier listing its API key, or whose JWT claims it all has.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TierConfig {
+    /// Tier name, referenced by rate limits
+    pub name: String,
+    /// API keys of the tier's consumers
+    #[serde(default)]
+    pub api_keys: Vec<String>,
+    /// JWT claim values of the tier's consumers, e.g. `plan: pro`
+    /// (requires `security.jwt`)
+    #[serde(default)]
+    pub jwt_claims: HashMap<String, String>,
+}
+
+/// Consumption quota configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct QuotaConfig {
+    /// Quotas of consumers without a tier, or of a tier not listed in `tiers`
+    #[serde(flatten)]
+    pub limits: QuotaLimits,
+    /// What identifies a consumer; requests without it are not counted
+    #[serde(default)]
+    pub key: QuotaKeyConfig,
+    /// Quotas by consumer tier name (see `server.tiers`)
+    #[serde(default)]
+    pub tiers: HashMap<String, QuotaLimits>,
+    /// When quota periods start over
+    #[serde(default)]
+    pub reset: QuotaResetConfig,
+    /// Where usage counters are kept
+    #[serde(default)]
+    pub store: QuotaStoreConfig,
+}
+
+/// Calls allowed per period; periods without a limit are not enforced
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct QuotaLimits {
+    /// Calls per day
+    #[serde(default)]
+    pub daily: Option<u64>,
+    /// Calls per month
+    #[serde(default)]
+    pub monthly: Option<u64>,
+}
+
+/// Consumer identity quotas are counted by
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaKeyConfig {
+    /// API key, from the header configured in `security.api_keys`
+    #[default]
+    ApiKey,
+    /// `sub` claim of the validated JWT (requires `security.jwt`)
+    JwtSubject,
+}
+
+/// Quota reset schedule, in UTC
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct QuotaResetConfig {
+    /// Hour of the day periods start at (0-23)
+    #[serde(default)]
+    pub hour: u32,
+    /// Day of the month monthly periods start on (1-28)
+    #[serde(default = "default_quota_reset_day")]
+    pub day_of_month: u32,
+}
+
+impl Default for QuotaResetConfig {
+    fn default() -> Self {
+        Self {
+            hour: 0,
+            day_of_month: default_quota_reset_day(),
+        }
+    }
+}
+
+fn default_quota_reset_day() -> u32 {
+    1
+}
+
+/// Quota usage storage: in-process, or persisted in Redis or SQLite
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum QuotaStoreConfig {
+    #[default]
+    Memory,
+    Redis(RedisQuotaStoreConfig),
+    Sqlite(SqliteQuotaStoreConfig),
+}
+
+/// Redis quota store configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RedisQuotaStoreConfig {
+    /// Redis connection string
+    pub connection_string: String,
+    /// Prefix of every usage counter written to Redis
+    #[serde(default = "default_quota_key_prefix")]
+    pub key_prefix: String,
+}
+
+fn default_quota_key_prefix() -> String {
+    "pmp:quota:".to_string()
+}
+
+/// SQLite quota store configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SqliteQuotaStoreConfig {
+    /// Database file path (e.g., "sqlite://quotas.db"), created if missing
+    pub database_path: String,
+}
+
+/// Rate limit key extractor. Requests without the selected attribute are
+/// counted by client IP.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RateLimitKeyConfig {
+    /// A single limit shared by all requests
+    #[default]
+    Global,
+    /// Client IP address
+    Ip,
+    /// API key, from the header configured in `security.api_keys`; only keys
+    /// configured there or in a tier count
+    ApiKey,
+    /// Claim of the validated JWT (requires `security.jwt`)
+    JwtClaim {
+        #[serde(default = "default_jwt_claim")]
+        claim: String,
+    },
+    /// Value of a request header
+    Header { name: String },
+}
+
+fn default_jwt_claim() -> String {
+    "sub".to_string()
 }
 
 /// Security configuration
@@ -120,6 +322,82 @@ pub struct SecurityConfig {
     /// IP allowlist/blocklist
     #[serde(default)]
     pub ip_filter: Option<IpFilterConfig>,
+    /// Auth policy of routes without their own `auth` (default: every
+    /// configured credential is required)
+    #[serde(default)]
+    pub default_auth: Option<AuthPolicy>,
+    /// Auth policy of the admin API (default: `default_auth`)
+    #[serde(default)]
+    pub admin_auth: Option<AuthPolicy>,
+}
+
+impl SecurityConfig {
+    /// Auth policy of routes without their own
+    pub fn default_policy(&self) -> AuthPolicy {
+        if let Some(policy) = &self.default_auth {
+            return policy.clone();
+        }
+
+        let mut required = Vec::new();
+        if self.api_keys.is_some() {
+            required.push(AuthPolicy::ApiKey);
+        }
+        if self.jwt.is_some() {
+            required.push(AuthPolicy::Jwt);
+        }
+        match required.len() {
+            0 => AuthPolicy::None,
+            1 => required.remove(0),
+            _ => AuthPolicy::AllOf(required),
+        }
+    }
+}
+
+/// Credentials a request must present, e.g. `api_key`, or
+/// `any_of: [api_key, jwt]`
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", from = "AuthPolicyRepr")]
+pub enum AuthPolicy {
+    /// No credentials
+    None,
+    /// A valid API key (requires `security.api_keys`)
+    ApiKey,
+    /// A valid JWT bearer token (requires `security.jwt`)
+    Jwt,
+    /// At least one of the policies
+    AnyOf(Vec<AuthPolicy>),
+    /// Every one of the policies
+    AllOf(Vec<AuthPolicy>),
+}
+
+/// Auth policy as written in YAML, which only maps enum variants with data
+/// from tags: a credential name, or a single-key map
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AuthPolicyRepr {
+    Credential(AuthCredential),
+    AnyOf { any_of: Vec<AuthPolicy> },
+    AllOf { all_of: Vec<AuthPolicy> },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum AuthCredential {
+    None,
+    ApiKey,
+    Jwt,
+}
+
+impl From<AuthPolicyRepr> for AuthPolicy {
+    fn from(repr: AuthPolicyRepr) -> Self {
+        match repr {
+            AuthPolicyRepr::Credential(AuthCredential::None) => AuthPolicy::None,
+            AuthPolicyRepr::Credential(AuthCredential::ApiKey) => AuthPolicy::ApiKey,
+            AuthPolicyRepr::Credential(AuthCredential::Jwt) => AuthPolicy::Jwt,
+            AuthPolicyRepr::AnyOf { any_of } => AuthPolicy::AnyOf(any_of),
+            AuthPolicyRepr::AllOf { all_of } => AuthPolicy::AllOf(all_of),
+        }
+    }
 }
 
 /// API key configuration
@@ -132,30 +410,88 @@ pub struct ApiKeyConfig {
     pub keys: Vec<String>,
 }
 
-/// JWT configuration
+/// JWT configuration. Tokens are verified with `secret` (HS algorithms),
+/// or with `public_key` or `jwks` (RS, PS, ES and EdDSA algorithms).
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct JwtConfig {
-    /// JWT secret or public key
-    pub secret: String,
-    /// Algorithm (HS256, RS256, etc.)
+    /// Shared secret of HS algorithms
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// PEM public key
+    #[serde(default)]
+    pub public_key: Option<String>,
+    /// JSON Web Key Set, selected from by the token's `kid`
+    #[serde(default)]
+    pub jwks: Option<JwksConfig>,
+    /// Algorithm (HS256, RS256, ES256, EdDSA, PS256, etc.)
     #[serde(default = "default_jwt_algorithm")]
     pub algorithm: String,
     /// Whether to validate expiration
     #[serde(default = "default_true")]
     pub validate_exp: bool,
+    /// Whether to validate the `nbf` (not before) claim
+    #[serde(default = "default_true")]
+    pub validate_nbf: bool,
+    /// Required `iss` claim
+    #[serde(default)]
+    pub issuer: Option<String>,
+    /// Accepted `aud` claims; when set, tokens need one of them
+    #[serde(default)]
+    pub audiences: Vec<String>,
+    /// Clock skew tolerated by `exp` and `nbf` checks
+    #[serde(default = "default_jwt_leeway")]
+    pub leeway_seconds: u64,
 }
 
-/// IP filter configuration
+/// JSON Web Key Set source: a file, or a URL refetched periodically
 #[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JwksConfig {
+    /// URL of the key set, e.g. `https://issuer/.well-known/jwks.json`
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Path of a key set file
+    #[serde(default)]
+    pub file: Option<String>,
+    /// How long a fetched key set is used before it is refetched
+    #[serde(default = "default_jwks_refresh")]
+    pub refresh_seconds: u64,
+}
+
+/// IP filter configuration. Entries are addresses or CIDR networks, IPv4
+/// or IPv6; the blocklist takes precedence over the allowlist.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct IpFilterConfig {
     /// IP allowlist (if set, only these IPs are allowed)
     #[serde(default)]
     pub allowlist: Vec<String>,
-    /// IP blocklist (these IPs are blocked)
+    /// IP blocklist (these IPs are blocked, even when allowlisted)
     #[serde(default)]
     pub blocklist: Vec<String>,
 }
 
+/// Client IP resolution. The client is the connected peer, unless the peer
+/// is a trusted proxy reporting the client in `forwarded_header`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ClientIpConfig {
+    /// Proxies trusted to report the client IP (addresses or CIDR networks)
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    /// Header trusted proxies report the client IP in
+    #[serde(default)]
+    pub forwarded_header: ForwardedHeader,
+}
+
+/// Header carrying the addresses a request was forwarded for
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ForwardedHeader {
+    /// `X-Forwarded-For: client, proxy1`
+    #[default]
+    XForwardedFor,
+    /// `Forwarded: for=client, for=proxy1` (RFC 7239)
+    Forwarded,
+}
+
 fn default_global_timeout() -> u64 {
     30
 }
@@ -190,6 +526,14 @@ fn default_jwt_algorithm() -> String {
     "HS256".to_string()
 }
 
+fn default_jwt_leeway() -> u64 {
+    60
+}
+
+fn default_jwks_refresh() -> u64 {
+    300
+}
+
 fn default_true() -> bool {
     true
 }
@@ -236,6 +580,100 @@ pub struct HttpClientConfig {
     /// Circuit breaker configuration
     #[serde(default)]
     pub circuit_breaker: Option<CircuitBreakerConfigYaml>,
+    /// Bulkhead limiting the requests in flight to this client
+    #[serde(default)]
+    pub max_concurrent: Option<ConcurrencyLimitConfig>,
+}
+
+impl ClientConfig {
+    /// Bulkhead of the client, whatever its type
+    pub fn max_concurrent(&self) -> Option<&ConcurrencyLimitConfig> {
+        match self {
+            ClientConfig::Http(config) => config.max_concurrent.as_ref(),
+            ClientConfig::Postgres(config) => config.max_concurrent.as_ref(),
+            ClientConfig::Mysql(config) => config.max_concurrent.as_ref(),
+            ClientConfig::Sqlite(config) => config.max_concurrent.as_ref(),
+            ClientConfig::Mongodb(config) => config.max_concurrent.as_ref(),
+            ClientConfig::Redis(config) => config.max_concurrent.as_ref(),
+        }
+    }
+}
+
+/// Concurrency limit (bulkhead) of a route or client. Requests over the
+/// limit wait in a bounded queue, and are rejected with 503 when it is full
+/// or they waited too long.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConcurrencyLimitConfig {
+    /// Requests in flight at once; the initial limit in adaptive mode
+    pub limit: usize,
+    /// Requests waiting for a slot (default: none, requests over the limit are rejected)
+    #[serde(default)]
+    pub queue_size: usize,
+    /// Milliseconds a request waits in the queue before being rejected
+    #[serde(default = "default_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+    /// Adjust the limit to the observed latency
+    #[serde(default)]
+    pub adaptive: Option<AdaptiveConcurrencyConfig>,
+}
+
+fn default_queue_timeout_ms() -> u64 {
+    1000
+}
+
+/// Adaptive concurrency limit configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdaptiveConcurrencyConfig {
+    /// How the limit follows latency
+    #[serde(default)]
+    pub algorithm: AdaptiveAlgorithm,
+    /// Lowest limit
+    #[serde(default = "default_adaptive_min_limit")]
+    pub min_limit: usize,
+    /// Highest limit
+    #[serde(default = "default_adaptive_max_limit")]
+    pub max_limit: usize,
+    /// AIMD: requests slower than this count as overload
+    #[serde(default = "default_latency_threshold_ms")]
+    pub latency_threshold_ms: u64,
+    /// AIMD: factor the limit is multiplied by on overload
+    #[serde(default = "default_backoff_ratio")]
+    pub backoff_ratio: f64,
+    /// Gradient: latency increase over the baseline tolerated before the limit shrinks
+    #[serde(default = "default_latency_tolerance")]
+    pub tolerance: f64,
+}
+
+/// Adaptive concurrency algorithm
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AdaptiveAlgorithm {
+    /// Additive increase while requests are fast, multiplicative decrease when
+    /// they are slow or fail
+    #[default]
+    Aimd,
+    /// Scale the limit by the ratio of the baseline latency to the current one
+    Gradient,
+}
+
+fn default_adaptive_min_limit() -> usize {
+    1
+}
+
+fn default_adaptive_max_limit() -> usize {
+    1000
+}
+
+fn default_latency_threshold_ms() -> u64 {
+    1000
+}
+
+fn default_backoff_ratio() -> f64 {
+    0.9
+}
+
+fn default_latency_tolerance() -> f64 {
+    2.0
 }
 
 /// Load balancing strategy
@@ -259,6 +697,61 @@ pub struct RetryConfig {
     /// Maximum backoff in milliseconds
     #[serde(default = "default_max_backoff")]
     pub max_backoff_ms: u64,
+    /// Upstream status codes that are retried (default: 429, 502, 503, 504)
+    #[serde(default = "default_retry_on_status")]
+    pub retry_on_status: Vec<u16>,
+    /// Wait for the upstream Retry-After delay instead of the computed backoff
+    /// (responses asking for more than max_backoff_ms are not retried)
+    #[serde(default = "default_true")]
+    pub respect_retry_after: bool,
+    /// Randomization applied to the backoff
+    #[serde(default)]
+    pub jitter: JitterStrategy,
+    /// Only retry idempotent methods (GET, HEAD, OPTIONS, PUT, DELETE, TRACE)
+    #[serde(default = "default_true")]
+    pub idempotent_only: bool,
+    /// Client-wide limit on retries relative to the request rate
+    #[serde(default)]
+    pub budget: Option<RetryBudgetConfig>,
+}
+
+fn default_retry_on_status() -> Vec<u16> {
+    vec![429, 502, 503, 504]
+}
+
+/// Jitter strategy for retry backoff
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JitterStrategy {
+    /// Plain exponential backoff
+    #[default]
+    None,
+    /// Random delay between zero and the exponential backoff
+    Full,
+    /// Random delay between the initial backoff and three times the previous delay
+    Decorrelated,
+}
+
+/// Retry budget: retries may use up to `ratio` of the request rate, plus
+/// `min_per_second` retries that are always allowed
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RetryBudgetConfig {
+    /// Retries allowed per request (e.g., 0.2 allows one retry every five requests)
+    #[serde(default = "default_budget_ratio")]
+    pub ratio: f64,
+    /// Retries per second allowed regardless of traffic (at least 1). The
+    /// budget holds at most ten seconds' worth of them, which also caps the
+    /// retries saved up from `ratio`.
+    #[serde(default = "default_budget_min_per_second")]
+    pub min_per_second: u32,
+}
+
+fn default_budget_ratio() -> f64 {
+    0.2
+}
+
+fn default_budget_min_per_second() -> u32 {
+    10
 }
 
 fn default_max_retries() -> u32 {
@@ -303,6 +796,9 @@ pub struct PostgresClientConfig {
     /// Connection timeout in seconds
     #[serde(default = "default_timeout")]
     pub timeout: u64,
+    /// Bulkhead limiting the requests in flight to this client
+    #[serde(default)]
+    pub max_concurrent: Option<ConcurrencyLimitConfig>,
 }
 
 /// MySQL client configuration
@@ -316,6 +812,9 @@ pub struct MysqlClientConfig {
     /// Connection timeout in seconds
     #[serde(default = "default_timeout")]
     pub timeout: u64,
+    /// Bulkhead limiting the requests in flight to this client
+    #[serde(default)]
+    pub max_concurrent: Option<ConcurrencyLimitConfig>,
 }
 
 /// SQLite client configuration
@@ -326,6 +825,9 @@ pub struct SqliteClientConfig {
     /// Maximum number of connections in the pool
     #[serde(default = "default_max_connections_u32")]
     pub max_connections: u32,
+    /// Bulkhead limiting the requests in flight to this client
+    #[serde(default)]
+    pub max_concurrent: Option<ConcurrencyLimitConfig>,
 }
 
 /// MongoDB client configuration
@@ -338,6 +840,9 @@ pub struct MongodbClientConfig {
     /// Connection timeout in seconds
     #[serde(default = "default_timeout")]
     pub timeout: u64,
+    /// Bulkhead limiting the requests in flight to this client
+    #[serde(default)]
+    pub max_concurrent: Option<ConcurrencyLimitConfig>,
 }
 
 /// Redis client configuration
@@ -348,6 +853,9 @@ pub struct RedisClientConfig {
     /// Connection timeout in seconds
     #[serde(default = "default_timeout")]
     pub timeout: u64,
+    /// Bulkhead limiting the requests in flight to this client
+    #[serde(default)]
+    pub max_concurrent: Option<ConcurrencyLimitConfig>,
 }
 
 fn default_min_connections() -> usize {
@@ -374,7 +882,11 @@ pub struct RouteConfig {
     /// URI path for this route
     pub path: String,
     /// List of subrequests to execute for this route
+    #[serde(default)]
     pub subrequests: Vec<SubrequestConfig>,
+    /// Forward requests transparently to a single HTTP client instead of running subrequests
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
     /// Optional response transformation
     #[serde(default)]
     pub response_transform: Option<ResponseTransform>,
@@ -387,6 +899,88 @@ pub struct RouteConfig {
     /// Traffic mirroring configuration for testing
     #[serde(default)]
     pub traffic_mirror: Option<crate::middleware::TrafficMirrorConfig>,
+    /// Response status and header policy
+    #[serde(default)]
+    pub response: Option<ResponsePolicy>,
+    /// How failing subrequests affect the route (default: fail-fast)
+    #[serde(default)]
+    pub on_error: ErrorPolicy,
+    /// Merge named subrequest results into a single response instead of the subrequests list
+    #[serde(default)]
+    pub merge: Option<MergeConfig>,
+    /// Deadline in milliseconds for all subrequests of the route
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Maximum number of subrequests running at once in parallel mode (default: unlimited)
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
+    /// Response caching for this route
+    #[serde(default)]
+    pub cache: Option<RouteCacheConfig>,
+    /// Cache tags invalidated when this route responds successfully
+    /// (templates, e.g. `user:${request.path.id}`)
+    #[serde(default)]
+    pub invalidate_tags: Vec<String>,
+    /// Rate limit of this route, applied in addition to `server.rate_limit`
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Bulkhead limiting the requests of this route in flight
+    #[serde(default)]
+    pub max_concurrent: Option<ConcurrencyLimitConfig>,
+    /// Credentials required by this route (default: `security.default_auth`)
+    #[serde(default)]
+    pub auth: Option<AuthPolicy>,
+    /// IP filter of this route, applied after `security.ip_filter`
+    #[serde(default)]
+    pub ip_filter: Option<IpFilterConfig>,
+}
+
+impl RouteConfig {
+    /// Whether this route accepts every HTTP method ("ANY" or "*")
+    pub fn matches_any_method(&self) -> bool {
+        self.method == "*" || self.method.eq_ignore_ascii_case("ANY")
+    }
+
+    /// Method filter for this route, if it targets a single standard method
+    pub fn method_filter(&self) -> Option<axum::routing::MethodFilter> {
+        let method = axum::http::Method::from_bytes(self.method.to_uppercase().as_bytes()).ok()?;
+        axum::routing::MethodFilter::try_from(method).ok()
+    }
+
+    /// Dependencies of each subrequest, as indices into `subrequests`
+    pub fn dependencies(&self) -> anyhow::Result<Vec<Vec<usize>>> {
+        let mut indices = HashMap::new();
+        for (idx, subrequest) in self.subrequests.iter().enumerate() {
+            if let Some(name) = &subrequest.name {
+                if indices.insert(name.as_str(), idx).is_some() {
+                    anyhow::bail!(
+                        "Route {} has duplicate subrequest name: {}",
+                        self.path,
+                        name
+                    );
+                }
+            }
+        }
+
+        self.subrequests
+            .iter()
+            .map(|subrequest| {
+                subrequest
+                    .depends_on
+                    .iter()
+                    .map(|dep| {
+                        indices.get(dep.as_str()).copied().ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "Route {} depends on unknown subrequest: {}",
+                                self.path,
+                                dep
+                            )
+                        })
+                    })
+                    .collect()
+            })
+            .collect()
+    }
 }
 
 /// Execution mode for subrequests
@@ -399,6 +993,19 @@ pub enum ExecutionMode {
     Sequential,
 }
 
+/// Route-level policy for failed subrequests that are neither optional nor have a fallback
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorPolicy {
+    /// Abort the route on the first failure
+    #[default]
+    FailFast,
+    /// Run every subrequest, then fail with the full list of errors
+    CollectErrors,
+    /// Run every subrequest and respond with the partial results and errors
+    BestEffort,
+}
+
 fn default_execution_mode() -> ExecutionMode {
     ExecutionMode::Parallel
 }
@@ -423,73 +1030,392 @@ pub struct ResponseTransform {
     pub template: Option<String>,
 }
 
-/// Subrequest configuration
+/// Transparent reverse-proxy configuration
 #[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct SubrequestConfig {
-    /// Optional name for this subrequest (used for referencing results)
-    #[serde(default)]
-    pub name: Option<String>,
-    /// Reference to a client ID
+pub struct ProxyConfig {
+    /// HTTP client to forward requests to
     pub client_id: String,
-    /// Optional condition for executing this subrequest
+    /// Prefix removed from the request path before forwarding (e.g., "/api")
     #[serde(default)]
-    pub condition: Option<Condition>,
-    /// List of subrequest names this depends on (for sequential execution)
+    pub strip_prefix: Option<String>,
+    /// Regex rewrite applied to the path after prefix stripping
     #[serde(default)]
-    pub depends_on: Vec<String>,
-    /// Subrequest-specific configuration based on client type
-    #[serde(flatten)]
-    pub config: SubrequestTypeConfig,
+    pub rewrite: Option<PathRewrite>,
 }
 
-/// Condition for conditional execution
+/// Regex-based path rewrite
 #[derive(Debug, Clone, Deserialize, Serialize)]
-#[serde(tag = "type", rename_all = "lowercase")]
-pub enum Condition {
-    /// Always execute
-    Always,
-    /// Execute if a field exists
-    FieldExists { field: String },
-    /// Execute if a field equals a value
-    FieldEquals { field: String, value: String },
-    /// Execute if a field matches a regex
-    FieldMatches { field: String, pattern: String },
-    /// Execute if a header exists
-    HeaderExists { header: String },
-    /// Execute if a header equals a value
-    HeaderEquals { header: String, value: String },
-    /// Execute if query parameter exists
-    QueryExists { param: String },
-    /// Execute if query parameter equals a value
-    QueryEquals { param: String, value: String },
-    /// Combine multiple conditions with AND
-    And { conditions: Vec<Condition> },
-    /// Combine multiple conditions with OR
-    Or { conditions: Vec<Condition> },
-    /// Negate a condition
-    Not { condition: Box<Condition> },
+pub struct PathRewrite {
+    /// Regex matched against the path
+    pub pattern: String,
+    /// Replacement (supports capture groups like $1)
+    pub replacement: String,
 }
 
-/// Type-specific subrequest configuration
+/// Per-route response cache configuration
 #[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RouteCacheConfig {
+    /// Time to live in seconds when the response has no max-age directive
+    #[serde(default = "default_cache_ttl")]
+    pub ttl_seconds: u64,
+    /// Maximum number of cached responses for this route
+    #[serde(default = "default_cache_max_entries")]
+    pub max_entries: u64,
+    /// Request attributes that make up the cache key
+    #[serde(default)]
+    pub key: CacheKeyConfig,
+    /// Seconds after expiry during which the stale response is served while
+    /// it is refreshed in the background
+    #[serde(default)]
+    pub stale_while_revalidate: u64,
+    /// Seconds after expiry during which the stale response is served when
+    /// the upstream fails
+    #[serde(default)]
+    pub stale_if_error: u64,
+    /// Tags attached to cached responses, used for invalidation
+    /// (templates, e.g. `user:${request.path.id}`)
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+fn default_cache_ttl() -> u64 {
+    60
+}
+
+fn default_cache_max_entries() -> u64 {
+    1000
+}
+
+/// Response cache storage: in-process only, or in-process backed by Redis
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 #[serde(tag = "type", rename_all = "lowercase")]
-pub enum SubrequestTypeConfig {
-    Http(HttpSubrequestConfig),
-    Postgres(SqlSubrequestConfig),
-    Mysql(SqlSubrequestConfig),
-    Sqlite(SqlSubrequestConfig),
-    Mongodb(MongodbSubrequestConfig),
-    Redis(RedisSubrequestConfig),
+pub enum CacheStoreConfig {
+    #[default]
+    Memory,
+    Redis(RedisCacheStoreConfig),
 }
 
-/// HTTP subrequest configuration
+/// Redis cache store configuration
 #[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct HttpSubrequestConfig {
-    /// URI to append to the client's base_url
-    pub uri: String,
-    /// HTTP method for this subrequest (GET, POST, etc.)
-    #[serde(default = "default_method")]
-    pub method: String,
+pub struct RedisCacheStoreConfig {
+    /// Redis connection string
+    pub connection_string: String,
+    /// Prefix of every cache key written to Redis
+    #[serde(default = "default_cache_key_prefix")]
+    pub key_prefix: String,
+    /// Maximum time in seconds an entry stays in a replica's in-process cache
+    #[serde(default = "default_l1_ttl")]
+    pub l1_ttl_seconds: u64,
+    /// Compress response bodies before storing them in Redis
+    #[serde(default = "default_true")]
+    pub compression: bool,
+    /// Smallest body size in bytes that is compressed
+    #[serde(default = "default_compression_min_bytes")]
+    pub compression_min_bytes: usize,
+}
+
+fn default_cache_key_prefix() -> String {
+    "pmp:cache:".to_string()
+}
+
+fn default_l1_ttl() -> u64 {
+    5
+}
+
+fn default_compression_min_bytes() -> usize {
+    1024
+}
+
+/// Request deduplication configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeduplicationConfig {
+    /// Seconds a response is replayed to duplicates of its request
+    #[serde(default = "default_deduplication_ttl")]
+    pub ttl_seconds: u64,
+    /// Maximum number of responses kept in memory
+    #[serde(default = "default_deduplication_max_entries")]
+    pub max_entries: u64,
+    /// Where responses are recorded
+    #[serde(default)]
+    pub store: IdempotencyStoreConfig,
+}
+
+impl Default for DeduplicationConfig {
+    fn default() -> Self {
+        Self {
+            ttl_seconds: default_deduplication_ttl(),
+            max_entries: default_deduplication_max_entries(),
+            store: IdempotencyStoreConfig::default(),
+        }
+    }
+}
+
+fn default_deduplication_ttl() -> u64 {
+    60
+}
+
+fn default_deduplication_max_entries() -> u64 {
+    10000
+}
+
+/// Idempotency record storage: in-process, or persisted in Redis or SQLite
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum IdempotencyStoreConfig {
+    #[default]
+    Memory,
+    Redis(RedisIdempotencyStoreConfig),
+    Sqlite(SqliteIdempotencyStoreConfig),
+}
+
+/// Redis idempotency store configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RedisIdempotencyStoreConfig {
+    /// Redis connection string
+    pub connection_string: String,
+    /// Prefix of every record key written to Redis
+    #[serde(default = "default_idempotency_key_prefix")]
+    pub key_prefix: String,
+}
+
+fn default_idempotency_key_prefix() -> String {
+    "pmp:idempotency:".to_string()
+}
+
+/// SQLite idempotency store configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SqliteIdempotencyStoreConfig {
+    /// Database file path (e.g., "sqlite://idempotency.db"), created if missing
+    pub database_path: String,
+}
+
+/// Request attributes included in the cache key, in addition to the method and path
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CacheKeyConfig {
+    /// Query parameters to include (default: all of them)
+    #[serde(default)]
+    pub query_params: Option<Vec<String>>,
+    /// Request headers to include
+    #[serde(default)]
+    pub headers: Vec<String>,
+    /// Path parameters to include; when set, the key uses the route pattern
+    /// and these parameters instead of the full request path
+    #[serde(default)]
+    pub path_params: Option<Vec<String>>,
+    /// Include the subject of the validated JWT (requires server.security.jwt)
+    #[serde(default)]
+    pub jwt_subject: bool,
+}
+
+/// Response shaping that merges named subrequest results into a single value
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MergeConfig {
+    /// How the results are combined
+    pub strategy: MergeStrategy,
+    /// Subrequests to merge, in order (defaults to every named subrequest)
+    #[serde(default)]
+    pub subrequests: Vec<String>,
+}
+
+/// Strategy for merging subrequest results
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeStrategy {
+    /// Object keyed by subrequest name
+    Keyed,
+    /// Deep-merge object results, later subrequests winning on conflicts
+    Deep,
+    /// Concatenate array results into a single array
+    Concat,
+}
+
+/// Policy for deriving the gateway response status and headers from subrequests
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ResponsePolicy {
+    /// Name of the HTTP subrequest whose status is used for the response
+    #[serde(default)]
+    pub status_from: Option<String>,
+    /// Mappings from upstream status codes to gateway status codes (first match wins)
+    #[serde(default)]
+    pub status_map: Vec<StatusMapping>,
+    /// Upstream response headers to forward (e.g., ["Cache-Control", "ETag"])
+    #[serde(default)]
+    pub forward_headers: Vec<String>,
+    /// Subrequests to forward headers from (defaults to status_from, then all HTTP subrequests)
+    #[serde(default)]
+    pub headers_from: Vec<String>,
+}
+
+/// Mapping from an upstream status pattern to a gateway status
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StatusMapping {
+    /// Upstream status pattern: exact ("404"), class ("5xx") or range ("500-504")
+    pub upstream: String,
+    /// Status code returned by the gateway
+    pub status: u16,
+}
+
+impl StatusMapping {
+    /// Check whether an upstream status matches this mapping
+    pub fn matches(&self, status: u16) -> bool {
+        match parse_status_pattern(&self.upstream) {
+            Some((low, high)) => (low..=high).contains(&status),
+            None => false,
+        }
+    }
+}
+
+/// Parse a status pattern into an inclusive range
+fn parse_status_pattern(pattern: &str) -> Option<(u16, u16)> {
+    let pattern = pattern.trim().to_lowercase();
+
+    if let Some(class) = pattern.strip_suffix("xx") {
+        let class: u16 = class.parse().ok()?;
+        return (1..=5)
+            .contains(&class)
+            .then_some((class * 100, class * 100 + 99));
+    }
+
+    if let Some((low, high)) = pattern.split_once('-') {
+        let low: u16 = low.trim().parse().ok()?;
+        let high: u16 = high.trim().parse().ok()?;
+        return (low <= high).then_some((low, high));
+    }
+
+    let status: u16 = pattern.parse().ok()?;
+    Some((status, status))
+}
+
+/// Subrequest configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SubrequestConfig {
+    /// Optional name for this subrequest (used for referencing results)
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Reference to a client ID
+    pub client_id: String,
+    /// Optional condition for executing this subrequest
+    #[serde(default)]
+    pub condition: Option<Condition>,
+    /// Names of subrequests that must finish before this one starts
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// What to do when a dependency was skipped or produced no result (default: skip)
+    #[serde(default)]
+    pub on_dependency_skipped: DependencyPolicy,
+    /// Whether the route continues without this subrequest's result if it fails
+    #[serde(default)]
+    pub optional: bool,
+    /// Result used when this subrequest fails (string values support interpolation)
+    #[serde(default)]
+    pub fallback: Option<serde_json::Value>,
+    /// Array to iterate over, executing this subrequest once per item
+    /// (e.g., "subrequest.users.body.items" or "request.body.ids")
+    #[serde(default)]
+    pub for_each: Option<String>,
+    /// Maximum number of for_each items executed concurrently
+    #[serde(default = "default_for_each_concurrency")]
+    pub for_each_concurrency: usize,
+    /// Timeout in milliseconds for this subrequest (bounded by the route deadline)
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Result caching, shared by every route using the same client and key
+    #[serde(default)]
+    pub cache: Option<SubrequestCacheConfig>,
+    /// Subrequest-specific configuration based on client type
+    #[serde(flatten)]
+    pub config: SubrequestTypeConfig,
+}
+
+fn default_for_each_concurrency() -> usize {
+    10
+}
+
+/// Subrequest result caching
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SubrequestCacheConfig {
+    /// Cache key template (e.g., "country:${request.path.code}"); defaults to
+    /// the interpolated request
+    #[serde(default)]
+    pub key: Option<String>,
+    /// Time to live in seconds
+    #[serde(default = "default_cache_ttl")]
+    pub ttl_seconds: u64,
+}
+
+/// Handling of a subrequest whose dependency was skipped or failed without a result
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyPolicy {
+    /// Skip this subrequest as well
+    #[default]
+    Skip,
+    /// Run this subrequest anyway
+    Run,
+}
+
+/// Condition for conditional execution
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Condition {
+    /// Always execute
+    Always,
+    /// Execute if a field exists
+    FieldExists { field: String },
+    /// Execute if a field equals a value
+    FieldEquals { field: String, value: String },
+    /// Execute if a field matches a regex
+    FieldMatches { field: String, pattern: String },
+    /// Execute if a header exists
+    HeaderExists { header: String },
+    /// Execute if a header equals a value
+    HeaderEquals { header: String, value: String },
+    /// Execute if query parameter exists
+    QueryExists { param: String },
+    /// Execute if query parameter equals a value
+    QueryEquals { param: String, value: String },
+    /// Combine multiple conditions with AND
+    And { conditions: Vec<Condition> },
+    /// Combine multiple conditions with OR
+    Or { conditions: Vec<Condition> },
+    /// Negate a condition
+    Not { condition: Box<Condition> },
+}
+
+/// Type-specific subrequest configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SubrequestTypeConfig {
+    Http(HttpSubrequestConfig),
+    Postgres(SqlSubrequestConfig),
+    Mysql(SqlSubrequestConfig),
+    Sqlite(SqlSubrequestConfig),
+    Mongodb(MongodbSubrequestConfig),
+    Redis(RedisSubrequestConfig),
+}
+
+impl SubrequestTypeConfig {
+    /// Subrequest type as written in configuration
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SubrequestTypeConfig::Http(_) => "http",
+            SubrequestTypeConfig::Postgres(_) => "postgres",
+            SubrequestTypeConfig::Mysql(_) => "mysql",
+            SubrequestTypeConfig::Sqlite(_) => "sqlite",
+            SubrequestTypeConfig::Mongodb(_) => "mongodb",
+            SubrequestTypeConfig::Redis(_) => "redis",
+        }
+    }
+}
+
+/// HTTP subrequest configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HttpSubrequestConfig {
+    /// URI to append to the client's base_url
+    pub uri: String,
+    /// HTTP method for this subrequest (GET, POST, etc.)
+    #[serde(default = "default_method")]
+    pub method: String,
     /// Additional headers for this specific subrequest
     /// Supports interpolation like: ${request.headers["Authorization"]}
     #[serde(default)]
@@ -663,9 +1589,198 @@ impl Config {
 
     /// Validate configuration
     pub fn validate(&self) -> anyhow::Result<()> {
-        // Validate that all client_ids in subrequests exist
+        for (client_id, client) in &self.clients {
+            if let ClientConfig::Http(http) = client {
+                if let Some(retry) = &http.retry {
+                    Self::validate_retry(client_id, retry)?;
+                }
+            }
+            if let Some(limit) = client.max_concurrent() {
+                Self::validate_concurrency_limit(&format!("client {}", client_id), limit)?;
+            }
+        }
+
+        if let Some(deduplication) = &self.server.deduplication {
+            if deduplication.ttl_seconds == 0 {
+                anyhow::bail!("Deduplication ttl_seconds must be greater than 0");
+            }
+        }
+
+        let mut tiers = std::collections::HashSet::new();
+        for tier in &self.server.tiers {
+            if !tiers.insert(tier.name.as_str()) {
+                anyhow::bail!("Duplicate tier: {}", tier.name);
+            }
+            if !tier.jwt_claims.is_empty() && self.server.security.jwt.is_none() {
+                anyhow::bail!(
+                    "Tier {} matches JWT claims, but security.jwt is not configured",
+                    tier.name
+                );
+            }
+        }
+
+        if let Some(rate_limit) = &self.server.rate_limit {
+            self.validate_rate_limit("server", rate_limit)?;
+        }
+
+        if let Some(quota) = &self.server.quota {
+            self.validate_quota(quota)?;
+        }
+
+        if let Some(jwt) = &self.server.security.jwt {
+            Self::validate_jwt(jwt)?;
+        }
+
+        if let Some(ip_filter) = &self.server.security.ip_filter {
+            Self::validate_ip_filter("security.ip_filter", ip_filter)?;
+        }
+
+        for proxy in &self.server.client_ip.trusted_proxies {
+            if crate::middleware::client_ip::parse_network(proxy).is_err() {
+                anyhow::bail!(
+                    "client_ip has an invalid trusted proxy: {} (expected an address or CIDR network)",
+                    proxy
+                );
+            }
+        }
+
+        if let Some(policy) = &self.server.security.default_auth {
+            self.validate_auth_policy("security.default_auth", policy)?;
+        }
+        if let Some(policy) = &self.server.security.admin_auth {
+            self.validate_auth_policy("security.admin_auth", policy)?;
+        }
+
+        let mut registered = std::collections::HashSet::new();
+
         for route in &self.routes {
+            // Validate the route method and reject duplicate method/path pairs
+            if route.method_filter().is_none() && !route.matches_any_method() {
+                anyhow::bail!(
+                    "Route {} has unsupported method: {}",
+                    route.path,
+                    route.method
+                );
+            }
+
+            let method_key = if route.matches_any_method() {
+                "*".to_string()
+            } else {
+                route.method.to_uppercase()
+            };
+            if !registered.insert((method_key, route.path.clone())) {
+                anyhow::bail!("Duplicate route: {} {}", route.method, route.path);
+            }
+
+            if let Some(proxy) = &route.proxy {
+                Self::validate_proxy(&self.clients, route, proxy)?;
+            }
+
+            if let Some(policy) = &route.response {
+                Self::validate_response_policy(route, policy)?;
+            }
+
+            if route.timeout_ms == Some(0) {
+                anyhow::bail!("Route {} has timeout_ms of 0", route.path);
+            }
+
+            if route.max_concurrency == Some(0) {
+                anyhow::bail!("Route {} has max_concurrency of 0", route.path);
+            }
+
+            if let Some(limit) = &route.max_concurrent {
+                Self::validate_concurrency_limit(
+                    &format!("route {} {}", route.method, route.path),
+                    limit,
+                )?;
+            }
+
+            if let Some(policy) = &route.auth {
+                self.validate_auth_policy(
+                    &format!("route {} {}", route.method, route.path),
+                    policy,
+                )?;
+            }
+
+            if let Some(ip_filter) = &route.ip_filter {
+                Self::validate_ip_filter(
+                    &format!("route {} {}", route.method, route.path),
+                    ip_filter,
+                )?;
+            }
+
+            Self::validate_dependencies(route)?;
+
+            if let Some(rate_limit) = &route.rate_limit {
+                self.validate_rate_limit(
+                    &format!("route {} {}", route.method, route.path),
+                    rate_limit,
+                )?;
+            }
+
+            if let Some(cache) = &route.cache {
+                if cache.ttl_seconds == 0 || cache.max_entries == 0 {
+                    anyhow::bail!(
+                        "Route {} cache needs a positive ttl_seconds and max_entries",
+                        route.path
+                    );
+                }
+                if cache.key.jwt_subject && self.server.security.jwt.is_none() {
+                    anyhow::bail!(
+                        "Route {} keys its cache by JWT subject but server.security.jwt is not configured",
+                        route.path
+                    );
+                }
+            }
+
+            if let Some(merge) = &route.merge {
+                for name in &merge.subrequests {
+                    if !route
+                        .subrequests
+                        .iter()
+                        .any(|sr| sr.name.as_deref() == Some(name.as_str()))
+                    {
+                        anyhow::bail!(
+                            "Route {} merge references unknown subrequest: {}",
+                            route.path,
+                            name
+                        );
+                    }
+                }
+            }
+
+            // Validate that all client_ids in subrequests exist
             for subrequest in &route.subrequests {
+                if let Some(for_each) = &subrequest.for_each {
+                    if !(for_each.starts_with("subrequest.")
+                        || for_each.starts_with("request.body"))
+                    {
+                        anyhow::bail!(
+                            "Route {} has invalid for_each expression: {} (expected subrequest.* or request.body*)",
+                            route.path,
+                            for_each
+                        );
+                    }
+                    if subrequest.for_each_concurrency == 0 {
+                        anyhow::bail!("Route {} has for_each_concurrency of 0", route.path);
+                    }
+                }
+
+                if subrequest.timeout_ms == Some(0) {
+                    anyhow::bail!("Route {} has a subrequest with timeout_ms of 0", route.path);
+                }
+
+                if subrequest
+                    .cache
+                    .as_ref()
+                    .is_some_and(|cache| cache.ttl_seconds == 0)
+                {
+                    anyhow::bail!(
+                        "Route {} has a cached subrequest with ttl_seconds of 0",
+                        route.path
+                    );
+                }
+
                 if !self.clients.contains_key(&subrequest.client_id) {
                     anyhow::bail!(
                         "Route {} references unknown client_id: {}",
@@ -677,32 +1792,449 @@ impl Config {
         }
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Validate the JWT algorithm and its keys
+    fn validate_jwt(jwt: &JwtConfig) -> anyhow::Result<()> {
+        let Ok(algorithm) = jwt.algorithm.parse::<jsonwebtoken::Algorithm>() else {
+            anyhow::bail!(
+                "security.jwt has unsupported algorithm {} (expected HS256, HS384, HS512, RS256, RS384, RS512, PS256, PS384, PS512, ES256, ES384 or EdDSA)",
+                jwt.algorithm
+            );
+        };
+
+        let hmac = matches!(
+            algorithm,
+            jsonwebtoken::Algorithm::HS256
+                | jsonwebtoken::Algorithm::HS384
+                | jsonwebtoken::Algorithm::HS512
+        );
+        let sources = [
+            jwt.secret.is_some(),
+            jwt.public_key.is_some(),
+            jwt.jwks.is_some(),
+        ];
+        if sources.iter().filter(|&&set| set).count() != 1 {
+            anyhow::bail!("security.jwt needs exactly one of secret, public_key or jwks");
+        }
+        if hmac != jwt.secret.is_some() {
+            anyhow::bail!(
+                "security.jwt algorithm {} needs {}",
+                jwt.algorithm,
+                if hmac {
+                    "a secret"
+                } else {
+                    "a public_key or jwks"
+                }
+            );
+        }
 
-    #[test]
-    fn test_config_deserialization() {
-        let yaml = r#"
-clients:
-  api1:
-    type: http
-    base_url: "https://api.example.com"
-    headers:
-      User-Agent: "PMP-Gateway/1.0"
-    min_connections: 2
-    max_connections: 20
-    timeout: 60
+        if let Some(jwks) = &jwt.jwks {
+            match (&jwks.url, &jwks.file) {
+                (Some(url), None) => {
+                    if !(url.starts_with("http://") || url.starts_with("https://")) {
+                        anyhow::bail!("security.jwt.jwks has an invalid url: {}", url);
+                    }
+                }
+                (None, Some(_)) => {}
+                _ => anyhow::bail!("security.jwt.jwks needs exactly one of url or file"),
+            }
+            if jwks.refresh_seconds == 0 {
+                anyhow::bail!("security.jwt.jwks has refresh_seconds of 0");
+            }
+        }
 
-routes:
-  - method: GET
-    path: /api/users/:id
-    subrequests:
-      - client_id: api1
-        type: http
-        uri: /users/${request.path.id}
+        // Load the keys, so malformed PEM keys and key set files are reported now
+        crate::middleware::jwt::JwtValidator::new(jwt)
+            .map_err(|e| anyhow::anyhow!("security.jwt has invalid keys: {}", e))?;
+        Ok(())
+    }
+
+    /// Validate that every IP filter entry is an address or a CIDR network
+    fn validate_ip_filter(scope: &str, ip_filter: &IpFilterConfig) -> anyhow::Result<()> {
+        let lists = [
+            ("allowlist", &ip_filter.allowlist),
+            ("blocklist", &ip_filter.blocklist),
+        ];
+        for (name, entries) in lists {
+            for entry in entries {
+                if crate::middleware::client_ip::parse_network(entry).is_err() {
+                    anyhow::bail!(
+                        "IP filter of {} has an invalid {} entry: {} (expected an address or CIDR network)",
+                        scope,
+                        name,
+                        entry
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate subrequest dependencies: unique names, known dependencies and no cycles
+    fn validate_dependencies(route: &RouteConfig) -> anyhow::Result<()> {
+        let dependencies = route.dependencies()?;
+
+        // Kahn's algorithm: whatever cannot be ordered is part of a cycle
+        let mut remaining: Vec<usize> = dependencies.iter().map(Vec::len).collect();
+        let mut ready: Vec<usize> = (0..remaining.len())
+            .filter(|&idx| remaining[idx] == 0)
+            .collect();
+        let mut ordered = 0;
+
+        while let Some(done) = ready.pop() {
+            ordered += 1;
+            for (idx, deps) in dependencies.iter().enumerate() {
+                for _ in deps.iter().filter(|&&dep| dep == done) {
+                    remaining[idx] -= 1;
+                    if remaining[idx] == 0 {
+                        ready.push(idx);
+                    }
+                }
+            }
+        }
+
+        if ordered < dependencies.len() {
+            let cycle: Vec<&str> = route
+                .subrequests
+                .iter()
+                .zip(&remaining)
+                .filter(|(_, &count)| count > 0)
+                .filter_map(|(subrequest, _)| subrequest.name.as_deref())
+                .collect();
+            anyhow::bail!(
+                "Route {} has circular dependencies between subrequests: {}",
+                route.path,
+                cycle.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Validate an HTTP client's retry configuration
+    fn validate_retry(client_id: &str, retry: &RetryConfig) -> anyhow::Result<()> {
+        for status in &retry.retry_on_status {
+            if !(100..=599).contains(status) {
+                anyhow::bail!(
+                    "Client {} has invalid retry_on_status code: {}",
+                    client_id,
+                    status
+                );
+            }
+        }
+
+        if let Some(budget) = &retry.budget {
+            if !budget.ratio.is_finite() || budget.ratio < 0.0 {
+                anyhow::bail!(
+                    "Client {} has invalid retry budget ratio: {}",
+                    client_id,
+                    budget.ratio
+                );
+            }
+            if budget.min_per_second == 0 {
+                anyhow::bail!(
+                    "Client {} retry budget needs min_per_second above 0",
+                    client_id
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validate a rate limit; `scope` names where it is configured
+    fn validate_rate_limit(&self, scope: &str, rate_limit: &RateLimitConfig) -> anyhow::Result<()> {
+        Self::validate_rate_limits(scope, &rate_limit.limits)?;
+
+        for (tier, limits) in &rate_limit.tiers {
+            if !self
+                .server
+                .tiers
+                .iter()
+                .any(|defined| &defined.name == tier)
+            {
+                anyhow::bail!("Rate limit of {} references unknown tier: {}", scope, tier);
+            }
+            Self::validate_rate_limits(&format!("{} for tier {}", scope, tier), limits)?;
+        }
+
+        if let RateLimitStoreConfig::Redis(redis) = &rate_limit.store {
+            use redis::IntoConnectionInfo;
+            if redis
+                .connection_string
+                .as_str()
+                .into_connection_info()
+                .is_err()
+            {
+                anyhow::bail!(
+                    "Rate limit of {} has an invalid Redis connection string",
+                    scope
+                );
+            }
+        }
+
+        if matches!(rate_limit.key, RateLimitKeyConfig::JwtClaim { .. })
+            && self.server.security.jwt.is_none()
+        {
+            anyhow::bail!(
+                "Rate limit of {} is keyed by a JWT claim, but security.jwt is not configured",
+                scope
+            );
+        }
+
+        Ok(())
+    }
+
+    fn validate_auth_policy(&self, scope: &str, policy: &AuthPolicy) -> anyhow::Result<()> {
+        match policy {
+            AuthPolicy::None => {}
+            AuthPolicy::ApiKey => {
+                if self.server.security.api_keys.is_none() {
+                    anyhow::bail!(
+                        "Auth policy of {} requires an API key, but security.api_keys is not configured",
+                        scope
+                    );
+                }
+            }
+            AuthPolicy::Jwt => {
+                if self.server.security.jwt.is_none() {
+                    anyhow::bail!(
+                        "Auth policy of {} requires a JWT, but security.jwt is not configured",
+                        scope
+                    );
+                }
+            }
+            AuthPolicy::AnyOf(policies) | AuthPolicy::AllOf(policies) => {
+                if policies.is_empty() {
+                    anyhow::bail!("Auth policy of {} has an empty any_of or all_of", scope);
+                }
+                for policy in policies {
+                    self.validate_auth_policy(scope, policy)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_concurrency_limit(
+        scope: &str,
+        config: &ConcurrencyLimitConfig,
+    ) -> anyhow::Result<()> {
+        if config.limit == 0 {
+            anyhow::bail!("Concurrency limit of {} is 0", scope);
+        }
+        if config.queue_size > 0 && config.queue_timeout_ms == 0 {
+            anyhow::bail!("Concurrency limit of {} has a queue_timeout_ms of 0", scope);
+        }
+
+        if let Some(adaptive) = &config.adaptive {
+            if adaptive.min_limit == 0
+                || adaptive.min_limit > config.limit
+                || config.limit > adaptive.max_limit
+            {
+                anyhow::bail!(
+                    "Concurrency limit of {} must satisfy 0 < min_limit <= limit <= max_limit",
+                    scope
+                );
+            }
+            if adaptive.latency_threshold_ms == 0 {
+                anyhow::bail!(
+                    "Concurrency limit of {} has a latency_threshold_ms of 0",
+                    scope
+                );
+            }
+            if !(adaptive.backoff_ratio > 0.0 && adaptive.backoff_ratio < 1.0) {
+                anyhow::bail!(
+                    "Concurrency limit of {} has a backoff_ratio outside (0, 1)",
+                    scope
+                );
+            }
+            if adaptive.tolerance.is_nan() || adaptive.tolerance < 1.0 {
+                anyhow::bail!("Concurrency limit of {} has a tolerance below 1", scope);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_quota(&self, quota: &QuotaConfig) -> anyhow::Result<()> {
+        Self::validate_quota_limits("default", &quota.limits)?;
+        for (tier, limits) in &quota.tiers {
+            if !self
+                .server
+                .tiers
+                .iter()
+                .any(|defined| &defined.name == tier)
+            {
+                anyhow::bail!("Quota references unknown tier: {}", tier);
+            }
+            Self::validate_quota_limits(tier, limits)?;
+        }
+
+        if quota.reset.hour > 23 {
+            anyhow::bail!("Quota reset hour must be between 0 and 23");
+        }
+        if !(1..=28).contains(&quota.reset.day_of_month) {
+            anyhow::bail!("Quota reset day_of_month must be between 1 and 28");
+        }
+
+        if quota.key == QuotaKeyConfig::JwtSubject && self.server.security.jwt.is_none() {
+            anyhow::bail!("Quota is keyed by JWT subject, but security.jwt is not configured");
+        }
+
+        if let QuotaStoreConfig::Redis(redis) = &quota.store {
+            use redis::IntoConnectionInfo;
+            if redis
+                .connection_string
+                .as_str()
+                .into_connection_info()
+                .is_err()
+            {
+                anyhow::bail!("Quota store has an invalid Redis connection string");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_quota_limits(tier: &str, limits: &QuotaLimits) -> anyhow::Result<()> {
+        if limits.daily == Some(0) || limits.monthly == Some(0) {
+            anyhow::bail!("Quota of tier {} has a limit of 0", tier);
+        }
+        Ok(())
+    }
+
+    fn validate_rate_limits(scope: &str, limits: &RateLimits) -> anyhow::Result<()> {
+        if limits.requests_per_second == 0 || limits.requests_per_second > u32::MAX as u64 {
+            anyhow::bail!(
+                "Rate limit of {} has invalid requests_per_second: {}",
+                scope,
+                limits.requests_per_second
+            );
+        }
+        if limits.burst_size == 0 {
+            anyhow::bail!("Rate limit of {} has a burst_size of 0", scope);
+        }
+        if let Some(per_minute) = limits.requests_per_minute {
+            if per_minute == 0 || per_minute > u32::MAX as u64 {
+                anyhow::bail!(
+                    "Rate limit of {} has invalid requests_per_minute: {}",
+                    scope,
+                    per_minute
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate a route's proxy configuration
+    fn validate_proxy(
+        clients: &HashMap<String, ClientConfig>,
+        route: &RouteConfig,
+        proxy: &ProxyConfig,
+    ) -> anyhow::Result<()> {
+        if !route.subrequests.is_empty() {
+            anyhow::bail!(
+                "Route {} cannot define both proxy and subrequests",
+                route.path
+            );
+        }
+
+        match clients.get(&proxy.client_id) {
+            Some(ClientConfig::Http(_)) => {}
+            Some(_) => anyhow::bail!(
+                "Route {} proxies to non-HTTP client: {}",
+                route.path,
+                proxy.client_id
+            ),
+            None => anyhow::bail!(
+                "Route {} references unknown client_id: {}",
+                route.path,
+                proxy.client_id
+            ),
+        }
+
+        if let Some(rewrite) = &proxy.rewrite {
+            if let Err(e) = regex::Regex::new(&rewrite.pattern) {
+                anyhow::bail!(
+                    "Route {} has invalid rewrite pattern {}: {}",
+                    route.path,
+                    rewrite.pattern,
+                    e
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validate a route's response policy
+    fn validate_response_policy(
+        route: &RouteConfig,
+        policy: &ResponsePolicy,
+    ) -> anyhow::Result<()> {
+        let known = |name: &str| {
+            route
+                .subrequests
+                .iter()
+                .any(|sr| sr.name.as_deref() == Some(name))
+        };
+
+        for name in policy.status_from.iter().chain(&policy.headers_from) {
+            if !known(name) {
+                anyhow::bail!(
+                    "Route {} response policy references unknown subrequest: {}",
+                    route.path,
+                    name
+                );
+            }
+        }
+
+        for mapping in &policy.status_map {
+            if parse_status_pattern(&mapping.upstream).is_none() {
+                anyhow::bail!(
+                    "Route {} has invalid upstream status pattern: {}",
+                    route.path,
+                    mapping.upstream
+                );
+            }
+            if axum::http::StatusCode::from_u16(mapping.status).is_err() {
+                anyhow::bail!(
+                    "Route {} maps to invalid status code: {}",
+                    route.path,
+                    mapping.status
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_deserialization() {
+        let yaml = r#"
+clients:
+  api1:
+    type: http
+    base_url: "https://api.example.com"
+    headers:
+      User-Agent: "PMP-Gateway/1.0"
+    min_connections: 2
+    max_connections: 20
+    timeout: 60
+
+routes:
+  - method: GET
+    path: /api/users/:id
+    subrequests:
+      - client_id: api1
+        type: http
+        uri: /users/${request.path.id}
         method: GET
         headers:
           Authorization: "${request.headers[\"Authorization\"]}"
@@ -738,6 +2270,320 @@ routes:
         assert_eq!(config.routes.len(), 1);
     }
 
+    #[test]
+    fn test_validate_route_methods() {
+        let yaml = r#"
+clients: {}
+routes:
+  - method: GET
+    path: /items
+    subrequests: []
+  - method: post
+    path: /items
+    subrequests: []
+"#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert!(config.validate().is_ok());
+
+        let duplicate = r#"
+clients: {}
+routes:
+  - method: GET
+    path: /items
+    subrequests: []
+  - method: get
+    path: /items
+    subrequests: []
+"#;
+
+        let config: Config = serde_yaml::from_str(duplicate).unwrap();
+        assert!(config.validate().is_err());
+
+        let unsupported = r#"
+clients: {}
+routes:
+  - method: FETCH
+    path: /items
+    subrequests: []
+"#;
+
+        let config: Config = serde_yaml::from_str(unsupported).unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_proxy_route_validation() {
+        let yaml = r#"
+clients:
+  backend:
+    type: http
+    base_url: "http://backend:8080"
+  cache:
+    type: redis
+    connection_string: "redis://localhost:6379"
+
+routes:
+  - method: ANY
+    path: /api/*rest
+    proxy:
+      client_id: backend
+      strip_prefix: /api
+      rewrite:
+        pattern: "^/v1/(.*)"
+        replacement: "/$1"
+"#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert!(config.routes[0].subrequests.is_empty());
+        assert!(config.validate().is_ok());
+
+        let mut invalid = config.clone();
+        invalid.routes[0].proxy.as_mut().unwrap().client_id = "cache".to_string();
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config;
+        invalid.routes[0].proxy.as_mut().unwrap().rewrite = Some(PathRewrite {
+            pattern: "([".to_string(),
+            replacement: String::new(),
+        });
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_status_mapping_patterns() {
+        let exact = StatusMapping {
+            upstream: "404".to_string(),
+            status: 404,
+        };
+        assert!(exact.matches(404));
+        assert!(!exact.matches(405));
+
+        let class = StatusMapping {
+            upstream: "5xx".to_string(),
+            status: 502,
+        };
+        assert!(class.matches(500));
+        assert!(class.matches(599));
+        assert!(!class.matches(499));
+
+        let range = StatusMapping {
+            upstream: "500-503".to_string(),
+            status: 503,
+        };
+        assert!(range.matches(502));
+        assert!(!range.matches(504));
+
+        assert!(parse_status_pattern("9xx").is_none());
+        assert!(parse_status_pattern("abc").is_none());
+    }
+
+    #[test]
+    fn test_merge_validation() {
+        let yaml = r#"
+clients:
+  api1:
+    type: http
+    base_url: "https://api.example.com"
+
+routes:
+  - method: GET
+    path: /profile
+    merge:
+      strategy: keyed
+      subrequests: [user, orders]
+    subrequests:
+      - name: user
+        client_id: api1
+        type: http
+        uri: /user
+"#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            config.routes[0].merge.as_ref().unwrap().strategy,
+            MergeStrategy::Keyed
+        );
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("unknown subrequest: orders"));
+    }
+
+    #[test]
+    fn test_timeout_validation() {
+        let yaml = r#"
+clients:
+  api1:
+    type: http
+    base_url: "https://api.example.com"
+
+routes:
+  - method: GET
+    path: /profile
+    timeout_ms: 500
+    subrequests:
+      - name: user
+        client_id: api1
+        type: http
+        uri: /user
+        timeout_ms: 200
+"#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.routes[0].timeout_ms, Some(500));
+        assert_eq!(config.routes[0].subrequests[0].timeout_ms, Some(200));
+        assert!(config.validate().is_ok());
+
+        let config: Config =
+            serde_yaml::from_str(&yaml.replace("timeout_ms: 200", "timeout_ms: 0")).unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_subrequest_cache_config() {
+        let yaml = r#"
+clients:
+  api1:
+    type: http
+    base_url: "https://api.example.com"
+
+routes:
+  - method: GET
+    path: /users/:id
+    subrequests:
+      - name: user
+        client_id: api1
+        type: http
+        uri: /users/${request.path.id}
+        cache:
+          key: "user:${request.path.id}"
+"#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let cache = config.routes[0].subrequests[0].cache.as_ref().unwrap();
+        assert_eq!(cache.key.as_deref(), Some("user:${request.path.id}"));
+        assert_eq!(cache.ttl_seconds, 60);
+        assert!(config.validate().is_ok());
+
+        let config: Config =
+            serde_yaml::from_str(&yaml.replace("cache:\n", "cache:\n          ttl_seconds: 0\n"))
+                .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_retry_config() {
+        let yaml = r#"
+clients:
+  api1:
+    type: http
+    base_url: "https://api.example.com"
+    retry:
+      max_retries: 2
+      jitter: decorrelated
+      budget:
+        ratio: 0.1
+routes: []
+"#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let ClientConfig::Http(http) = &config.clients["api1"] else {
+            panic!("expected an HTTP client");
+        };
+        let retry = http.retry.as_ref().unwrap();
+        assert_eq!(retry.retry_on_status, vec![429, 502, 503, 504]);
+        assert_eq!(retry.jitter, JitterStrategy::Decorrelated);
+        assert!(retry.idempotent_only);
+        assert_eq!(retry.budget.as_ref().unwrap().min_per_second, 10);
+        assert!(config.validate().is_ok());
+
+        let config: Config =
+            serde_yaml::from_str(&yaml.replace("ratio: 0.1", "ratio: -1")).unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config = serde_yaml::from_str(
+            &yaml.replace("ratio: 0.1", "ratio: 0.1\n        min_per_second: 0"),
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_dependency_validation() {
+        let route = |subrequests: &str| {
+            let yaml = format!(
+                "clients:\n  api1:\n    type: http\n    base_url: \"https://api.example.com\"\nroutes:\n  - method: GET\n    path: /graph\n    subrequests:\n{}",
+                subrequests
+            );
+            serde_yaml::from_str::<Config>(&yaml).unwrap().validate()
+        };
+        let subrequest = |name: &str, depends_on: &str| {
+            format!(
+                "      - name: {name}\n        client_id: api1\n        type: http\n        uri: /{name}\n        depends_on: [{depends_on}]\n"
+            )
+        };
+
+        let valid = subrequest("a", "") + &subrequest("b", "a") + &subrequest("c", "a, b");
+        assert!(route(&valid).is_ok());
+
+        let unknown = subrequest("a", "missing");
+        let err = route(&unknown).unwrap_err().to_string();
+        assert!(err.contains("unknown subrequest: missing"));
+
+        let cycle = subrequest("a", "c") + &subrequest("b", "a") + &subrequest("c", "b");
+        let err = route(&cycle).unwrap_err().to_string();
+        assert!(err.contains("circular dependencies"));
+        assert!(err.contains("a, b, c"));
+
+        let self_cycle = subrequest("a", "a");
+        assert!(route(&self_cycle).is_err());
+
+        let duplicate = subrequest("a", "") + &subrequest("a", "");
+        let err = route(&duplicate).unwrap_err().to_string();
+        assert!(err.contains("duplicate subrequest name"));
+    }
+
+    #[test]
+    fn test_error_policy_deserialization() {
+        let yaml = r#"
+clients:
+  api1:
+    type: http
+    base_url: "https://api.example.com"
+
+routes:
+  - method: GET
+    path: /dashboard
+    on_error: best-effort
+    subrequests:
+      - name: profile
+        client_id: api1
+        type: http
+        uri: /profile
+      - name: recommendations
+        client_id: api1
+        type: http
+        uri: /recommendations
+        optional: true
+      - name: banner
+        client_id: api1
+        type: http
+        uri: /banner
+        fallback:
+          text: "Welcome ${request.query.name}"
+"#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let route = &config.routes[0];
+        assert_eq!(route.on_error, ErrorPolicy::BestEffort);
+        assert!(!route.subrequests[0].optional);
+        assert!(route.subrequests[1].optional);
+        assert!(route.subrequests[2].fallback.is_some());
+
+        let default: Config =
+            serde_yaml::from_str("clients: {}\nroutes:\n  - method: GET\n    path: /\n").unwrap();
+        assert_eq!(default.routes[0].on_error, ErrorPolicy::FailFast);
+    }
+
     #[test]
     fn test_conditional_execution() {
         let yaml = r#"
@@ -761,4 +2607,380 @@ routes:
         let config: Config = serde_yaml::from_str(yaml).unwrap();
         assert!(config.routes[0].subrequests[0].condition.is_some());
     }
+
+    #[test]
+    fn test_deduplication_config() {
+        let yaml = r#"
+clients: {}
+routes: []
+server:
+  deduplication:
+    ttl_seconds: 86400
+    store:
+      type: sqlite
+      database_path: "sqlite://idempotency.db"
+"#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let deduplication = config.server.deduplication.as_ref().unwrap();
+        assert_eq!(deduplication.ttl_seconds, 86400);
+        assert_eq!(deduplication.max_entries, 10000);
+        assert!(matches!(
+            &deduplication.store,
+            IdempotencyStoreConfig::Sqlite(sqlite) if sqlite.database_path == "sqlite://idempotency.db"
+        ));
+        assert!(config.validate().is_ok());
+
+        let config: Config =
+            serde_yaml::from_str(&yaml.replace("ttl_seconds: 86400", "ttl_seconds: 0")).unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_rate_limit_config() {
+        let yaml = r#"
+clients: {}
+routes: []
+server:
+  rate_limit:
+    requests_per_second: 10
+    requests_per_minute: 300
+    key:
+      type: header
+      name: x-tenant
+"#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let rate_limit = config.server.rate_limit.as_ref().unwrap();
+        assert_eq!(rate_limit.limits.burst_size, 10);
+        assert_eq!(rate_limit.limits.requests_per_minute, Some(300));
+        assert!(matches!(
+            &rate_limit.key,
+            RateLimitKeyConfig::Header { name } if name == "x-tenant"
+        ));
+        assert!(config.validate().is_ok());
+
+        // JWT claims can only be read when JWTs are validated
+        let config: Config = serde_yaml::from_str(
+            &yaml.replace("type: header\n      name: x-tenant", "type: jwt_claim"),
+        )
+        .unwrap();
+        assert!(matches!(
+            &config.server.rate_limit.as_ref().unwrap().key,
+            RateLimitKeyConfig::JwtClaim { claim } if claim == "sub"
+        ));
+        assert!(config.validate().is_err());
+
+        let config: Config = serde_yaml::from_str(&format!(
+            "{}    store:\n      type: redis\n      connection_string: redis://localhost:6379\n",
+            yaml
+        ))
+        .unwrap();
+        assert!(matches!(
+            &config.server.rate_limit.as_ref().unwrap().store,
+            RateLimitStoreConfig::Redis(redis) if redis.key_prefix == "pmp:ratelimit:"
+        ));
+        assert!(config.validate().is_ok());
+
+        let config: Config = serde_yaml::from_str(&format!(
+            "{}    store:\n      type: redis\n      connection_string: localhost\n",
+            yaml
+        ))
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_concurrency_limit_config() {
+        let yaml = r#"
+clients:
+  db:
+    type: postgres
+    connection_string: "postgres://localhost/app"
+    max_concurrent:
+      limit: 20
+      queue_size: 50
+routes:
+  - method: GET
+    path: /reports
+    max_concurrent:
+      limit: 10
+      adaptive:
+        algorithm: gradient
+        max_limit: 100
+"#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let client = config.clients["db"].max_concurrent().unwrap();
+        assert_eq!((client.limit, client.queue_size), (20, 50));
+        assert_eq!(client.queue_timeout_ms, 1000);
+        assert!(client.adaptive.is_none());
+
+        let adaptive = config.routes[0]
+            .max_concurrent
+            .as_ref()
+            .unwrap()
+            .adaptive
+            .as_ref()
+            .unwrap();
+        assert_eq!(adaptive.algorithm, AdaptiveAlgorithm::Gradient);
+        assert_eq!((adaptive.min_limit, adaptive.max_limit), (1, 100));
+        assert!(config.validate().is_ok());
+
+        // The initial limit must be within the adaptive bounds
+        let config: Config =
+            serde_yaml::from_str(&yaml.replace("max_limit: 100", "max_limit: 5")).unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config = serde_yaml::from_str(&yaml.replace("limit: 20", "limit: 0")).unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config =
+            serde_yaml::from_str(&yaml.replace("algorithm: gradient", "backoff_ratio: 1.5"))
+                .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_auth_policy_config() {
+        let yaml = r#"
+clients: {}
+routes:
+  - method: GET
+    path: /reports
+    auth:
+      all_of:
+        - api_key
+        - any_of: [jwt, api_key]
+  - method: GET
+    path: /health-details
+    auth: none
+server:
+  security:
+    api_keys:
+      keys: ["secret"]
+    jwt:
+      secret: "jwt-secret"
+    admin_auth: api_key
+"#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            config.routes[0].auth,
+            Some(AuthPolicy::AllOf(vec![
+                AuthPolicy::ApiKey,
+                AuthPolicy::AnyOf(vec![AuthPolicy::Jwt, AuthPolicy::ApiKey]),
+            ]))
+        );
+        assert_eq!(config.routes[1].auth, Some(AuthPolicy::None));
+        assert_eq!(config.server.security.admin_auth, Some(AuthPolicy::ApiKey));
+        assert_eq!(
+            config.server.security.default_policy(),
+            AuthPolicy::AllOf(vec![AuthPolicy::ApiKey, AuthPolicy::Jwt])
+        );
+        assert!(config.validate().is_ok());
+
+        // Policies can only require configured credentials
+        let config: Config =
+            serde_yaml::from_str(&yaml.replace("    jwt:\n      secret: \"jwt-secret\"\n", ""))
+                .unwrap();
+        assert_eq!(config.server.security.default_policy(), AuthPolicy::ApiKey);
+        assert!(config.validate().is_err());
+
+        let config: Config =
+            serde_yaml::from_str(&yaml.replace("any_of: [jwt, api_key]", "any_of: []")).unwrap();
+        assert!(config.validate().is_err());
+
+        assert_eq!(SecurityConfig::default().default_policy(), AuthPolicy::None);
+    }
+
+    #[test]
+    fn test_ip_filter_config() {
+        let yaml = r#"
+clients: {}
+routes:
+  - method: GET
+    path: /internal
+    ip_filter:
+      allowlist: ["10.0.0.0/8", "fd00::/8"]
+server:
+  security:
+    ip_filter:
+      blocklist: ["192.0.2.1", "2001:db8::/32"]
+  client_ip:
+    trusted_proxies: ["10.0.0.1"]
+    forwarded_header: forwarded
+"#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            config.server.client_ip.forwarded_header,
+            ForwardedHeader::Forwarded
+        );
+        assert_eq!(
+            ServerConfig::default().client_ip.forwarded_header,
+            ForwardedHeader::XForwardedFor
+        );
+        assert!(config.validate().is_ok());
+
+        // Prefix entries are not networks
+        for (from, to) in [
+            ("\"192.0.2.1\"", "\"192.168.\""),
+            ("\"fd00::/8\"", "\"fd00::/200\""),
+            ("\"10.0.0.1\"", "\"proxy\""),
+        ] {
+            let config: Config = serde_yaml::from_str(&yaml.replace(from, to)).unwrap();
+            assert!(config.validate().is_err(), "{} should be rejected", to);
+        }
+    }
+
+    #[test]
+    fn test_jwt_config() {
+        let yaml = r#"
+clients: {}
+routes: []
+server:
+  security:
+    jwt:
+      jwks:
+        url: https://issuer.example/.well-known/jwks.json
+      algorithm: RS256
+      issuer: https://issuer.example
+      audiences: [orders]
+"#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let jwt = config.server.security.jwt.as_ref().unwrap();
+        assert_eq!(jwt.jwks.as_ref().unwrap().refresh_seconds, 300);
+        assert_eq!(jwt.leeway_seconds, 60);
+        assert!(jwt.validate_nbf);
+        assert!(config.validate().is_ok());
+
+        for (from, to) in [
+            // Unknown algorithms do not fall back to HS256
+            ("algorithm: RS256", "algorithm: RS1024"),
+            // Asymmetric algorithms need public keys, and HS ones a secret
+            (
+                "jwks:\n        url: https://issuer.example/.well-known/jwks.json",
+                "secret: shared",
+            ),
+            ("algorithm: RS256", "algorithm: HS256"),
+            (
+                "url: https://issuer.example/.well-known/jwks.json",
+                "url: https://issuer.example/.well-known/jwks.json\n        file: jwks.json",
+            ),
+            (
+                "url: https://issuer.example/.well-known/jwks.json",
+                "file: missing.json",
+            ),
+            (
+                "jwks:\n        url: https://issuer.example/.well-known/jwks.json",
+                "public_key: not a key",
+            ),
+        ] {
+            let config: Config = serde_yaml::from_str(&yaml.replace(from, to)).unwrap();
+            assert!(config.validate().is_err(), "{} should be rejected", to);
+        }
+    }
+
+    #[test]
+    fn test_quota_config() {
+        let yaml = r#"
+clients: {}
+routes: []
+server:
+  tiers:
+    - name: partner
+      api_keys: ["partner-key"]
+  quota:
+    daily: 1000
+    monthly: 100000
+    tiers:
+      partner:
+        monthly: 1000000
+    reset:
+      hour: 6
+    store:
+      type: sqlite
+      database_path: "sqlite://quotas.db"
+"#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let quota = config.server.quota.as_ref().unwrap();
+        assert_eq!(quota.limits.daily, Some(1000));
+        assert_eq!(quota.key, QuotaKeyConfig::ApiKey);
+        assert_eq!(quota.tiers["partner"].monthly, Some(1000000));
+        assert_eq!(quota.tiers["partner"].daily, None);
+        assert_eq!((quota.reset.hour, quota.reset.day_of_month), (6, 1));
+        assert!(matches!(quota.store, QuotaStoreConfig::Sqlite(_)));
+        assert!(config.validate().is_ok());
+
+        let config: Config =
+            serde_yaml::from_str(&yaml.replace("hour: 6", "day_of_month: 31")).unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config =
+            serde_yaml::from_str(&yaml.replace("      partner:", "      gold:")).unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config =
+            serde_yaml::from_str(&yaml.replace("daily: 1000", "daily: 0")).unwrap();
+        assert!(config.validate().is_err());
+
+        // Counting by JWT subject needs JWT validation
+        let config: Config = serde_yaml::from_str(
+            &yaml.replace("    daily: 1000", "    key: jwt_subject\n    daily: 1000"),
+        )
+        .unwrap();
+        assert_eq!(
+            config.server.quota.as_ref().unwrap().key,
+            QuotaKeyConfig::JwtSubject
+        );
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_route_rate_limit_tiers() {
+        let yaml = r#"
+clients: {}
+routes:
+  - method: GET
+    path: /reports
+    rate_limit:
+      requests_per_second: 1
+      key:
+        type: api_key
+      tiers:
+        pro:
+          requests_per_second: 20
+          burst_size: 40
+server:
+  tiers:
+    - name: pro
+      api_keys: ["partner-key"]
+"#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let rate_limit = config.routes[0].rate_limit.as_ref().unwrap();
+        assert_eq!(rate_limit.limits.requests_per_second, 1);
+        assert_eq!(rate_limit.tiers["pro"].burst_size, 40);
+        assert!(config.validate().is_ok());
+
+        // Tiers must be defined before rate limits can use them
+        let config: Config =
+            serde_yaml::from_str(&yaml.replace("- name: pro", "- name: free")).unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config =
+            serde_yaml::from_str(&yaml.replace("burst_size: 40", "burst_size: 0")).unwrap();
+        assert!(config.validate().is_err());
+
+        // Matching tiers by JWT claims needs JWT validation
+        let config: Config = serde_yaml::from_str(&yaml.replace(
+            "api_keys: [\"partner-key\"]",
+            "jwt_claims:\n        plan: pro",
+        ))
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
 }
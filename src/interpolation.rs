@@ -22,6 +22,8 @@ pub struct InterpolationContext {
     pub method: Method,
     /// Results from previously executed subrequests (name -> result JSON)
     pub subrequest_results: HashMap<String, Value>,
+    /// Current item and its index when executing a for_each subrequest
+    pub item: Option<(usize, Value)>,
 }
 
 impl InterpolationContext {
@@ -39,9 +41,17 @@ impl InterpolationContext {
             body,
             method,
             subrequest_results: HashMap::new(),
+            item: None,
         }
     }
 
+    /// Create a copy of this context for one item of a for_each subrequest
+    pub fn with_item(&self, index: usize, item: Value) -> Self {
+        let mut context = self.clone();
+        context.item = Some((index, item));
+        context
+    }
+
     /// Add a subrequest result to the context
     pub fn add_subrequest_result(&mut self, name: String, result: Value) {
         self.subrequest_results.insert(name, result);
@@ -55,6 +65,7 @@ impl InterpolationContext {
     /// - ${request.body}
     /// - ${request.method}
     /// - ${subrequest.name.field.path} (access previous subrequest results)
+    /// - ${item}, ${item.field.path} and ${index} (inside for_each subrequests)
     pub fn interpolate(&self, template: &str) -> String {
         let regex = get_interpolation_regex();
 
@@ -115,6 +126,20 @@ impl InterpolationContext {
             return self.extract_subrequest_value(subreq_expr);
         }
 
+        // Handle item, item.path and index (for_each iteration)
+        if let Some((index, item)) = &self.item {
+            if expr == "index" {
+                return index.to_string();
+            }
+            if expr == "item" {
+                return value_to_string(item);
+            }
+            if let Some(item_path) = expr.strip_prefix("item.") {
+                let parts: Vec<&str> = item_path.split('.').collect();
+                return value_to_string(&navigate(item, &parts));
+            }
+        }
+
         // If no match, return the original expression
         format!("${{{}}}", expr)
     }
@@ -137,34 +162,62 @@ impl InterpolationContext {
             }
 
             // Navigate through the JSON path
-            let mut current = result.clone();
-            for part in &parts[1..] {
-                current = match current {
-                    Value::Object(map) => map.get(*part).cloned().unwrap_or(Value::Null),
-                    Value::Array(arr) => {
-                        // Try to parse as array index
-                        if let Ok(index) = part.parse::<usize>() {
-                            arr.get(index).cloned().unwrap_or(Value::Null)
-                        } else {
-                            Value::Null
-                        }
-                    }
-                    _ => Value::Null,
-                };
-            }
-
-            // Convert the final value to string
-            match current {
-                Value::String(s) => s,
-                Value::Number(n) => n.to_string(),
-                Value::Bool(b) => b.to_string(),
-                Value::Null => String::new(),
-                _ => serde_json::to_string(&current).unwrap_or_default(),
-            }
+            value_to_string(&navigate(result, &parts[1..]))
         } else {
             String::new()
         }
     }
+
+    /// Resolve an expression to a JSON value instead of a string.
+    /// Supports request.body[.path] (body parsed as JSON), subrequest.name[.path]
+    /// and item[.path]. Returns None if the source does not exist.
+    pub fn resolve_value(&self, expr: &str) -> Option<Value> {
+        let parts: Vec<&str> = expr.trim().split('.').collect();
+
+        match parts.as_slice() {
+            ["request", "body", path @ ..] => {
+                let body: Value = serde_json::from_str(self.body.as_deref()?).ok()?;
+                Some(navigate(&body, path))
+            }
+            ["subrequest", name, path @ ..] => {
+                let result = self.subrequest_results.get(*name)?;
+                Some(navigate(result, path))
+            }
+            ["item", path @ ..] => {
+                let (_, item) = self.item.as_ref()?;
+                Some(navigate(item, path))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Navigate a JSON value using path segments (object keys or array indices)
+fn navigate(value: &Value, path: &[&str]) -> Value {
+    let mut current = value;
+    for part in path {
+        let next = match current {
+            Value::Object(map) => map.get(*part),
+            Value::Array(arr) => part.parse::<usize>().ok().and_then(|index| arr.get(index)),
+            _ => None,
+        };
+        match next {
+            Some(value) => current = value,
+            None => return Value::Null,
+        }
+    }
+    current.clone()
+}
+
+/// Convert a JSON value to its interpolated string form
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Null => String::new(),
+        _ => serde_json::to_string(value).unwrap_or_default(),
+    }
 }
 
 #[cfg(test)]
@@ -246,6 +299,50 @@ mod tests {
         assert_eq!(result, "Method is POST");
     }
 
+    #[test]
+    fn test_item_interpolation() {
+        let ctx = InterpolationContext::new(
+            HeaderMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            None,
+            Method::GET,
+        )
+        .with_item(2, serde_json::json!({"id": 7, "tags": ["a", "b"]}));
+
+        assert_eq!(
+            ctx.interpolate("/items/${item.id}?pos=${index}&tag=${item.tags.1}"),
+            "/items/7?pos=2&tag=b"
+        );
+        assert_eq!(ctx.interpolate("${item}"), r#"{"id":7,"tags":["a","b"]}"#);
+    }
+
+    #[test]
+    fn test_resolve_value() {
+        let mut ctx = InterpolationContext::new(
+            HeaderMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            Some(r#"{"ids": [1, 2, 3]}"#.to_string()),
+            Method::POST,
+        );
+        ctx.add_subrequest_result(
+            "users".to_string(),
+            serde_json::json!({"rows": [{"id": 1}, {"id": 2}]}),
+        );
+
+        assert_eq!(
+            ctx.resolve_value("request.body.ids"),
+            Some(serde_json::json!([1, 2, 3]))
+        );
+        assert_eq!(
+            ctx.resolve_value("subrequest.users.rows.1.id"),
+            Some(serde_json::json!(2))
+        );
+        assert_eq!(ctx.resolve_value("subrequest.missing.rows"), None);
+        assert_eq!(ctx.resolve_value("item"), None);
+    }
+
     #[test]
     fn test_multiple_interpolations() {
         let mut headers = HeaderMap::new();
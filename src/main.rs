@@ -14,9 +14,11 @@ use admin_api::{create_admin_router, AdminState};
 use anyhow::Result;
 use axum::http::Method;
 use clients::ClientManager;
-use config::Config;
+use config::{AuthPolicy, Config};
 use health_aggregation::HealthCheckManager;
+use middleware::cache::CacheRegistry;
 use routes::{build_router, handler::AppState};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::RwLock;
@@ -60,29 +62,74 @@ async fn main() -> Result<()> {
         config.routes.len()
     );
 
+    // Fetch a remote JWT key set now, so the first tokens can be verified
+    if let Some(jwt) = middleware::jwt::create_jwt_validator(config.server.security.jwt.as_ref()) {
+        jwt.load_keys().await;
+    }
+
     // Initialize client manager (now async)
     let client_manager = ClientManager::from_config(&config).await?;
     info!("Initialized client manager");
 
+    // Connect the shared response cache store, if configured
+    let cache_store =
+        middleware::cache_store::create_cache_store(&config.server.cache_store).await?;
+    if cache_store.is_some() {
+        info!("Initialized shared cache store");
+    }
+
     // Initialize health check manager
     let health_manager = Arc::new(HealthCheckManager::new());
     info!("Initialized health check manager");
 
+    // Initialize consumption quotas, if configured
+    let quotas = match config.server.quota {
+        Some(ref quota_config) => {
+            let store = middleware::quota_store::create_quota_store(quota_config).await?;
+            info!("Initialized consumption quotas");
+            Some(Arc::new(middleware::quota::QuotaManager::new(
+                quota_config,
+                &config.server,
+                store,
+            )))
+        }
+        None => None,
+    };
+
     // Create application state
     let state = AppState {
         config: Arc::new(config.clone()),
         client_manager: Arc::new(client_manager),
+        caches: Arc::new(CacheRegistry::new(cache_store)),
+        quotas: quotas.clone(),
     };
 
     // Create admin state (with RwLock for config reload)
     let admin_state = AdminState {
         config: Arc::new(RwLock::new(config.clone())),
         health_manager: health_manager.clone(),
+        caches: state.caches.clone(),
+        quotas: quotas.clone(),
     };
 
     // Build routers
     let main_router = build_router(state);
-    let admin_router = create_admin_router(admin_state);
+    let mut admin_router = create_admin_router(admin_state);
+
+    // Protect the admin API with its auth policy
+    let admin_auth = match config.server.security.admin_auth {
+        Some(ref policy) => policy.clone(),
+        None => config.server.security.default_policy(),
+    };
+    if admin_auth != AuthPolicy::None {
+        info!("Enabling admin API authentication");
+        let authenticator = Arc::new(middleware::security::Authenticator::new(
+            &config.server.security,
+        ));
+        admin_router = admin_router.layer(axum::middleware::from_fn(
+            middleware::security::create_auth_middleware(authenticator, admin_auth),
+        ));
+    }
 
     // Merge routers
     let mut app = main_router.merge(admin_router);
@@ -134,6 +181,26 @@ async fn main() -> Result<()> {
         app = app.layer(cors);
     }
 
+    // Apply request deduplication if configured (inside the body size limit,
+    // since request bodies are buffered to be hashed). It runs before per-route
+    // auth, so records are scoped to the credentials of the request.
+    if let Some(ref deduplication_config) = config.server.deduplication {
+        info!(
+            "Enabling request deduplication: {} second window",
+            deduplication_config.ttl_seconds
+        );
+        let store =
+            middleware::idempotency_store::create_idempotency_store(deduplication_config).await?;
+        let dedup = middleware::deduplication::RequestDeduplicator::new(
+            store,
+            Duration::from_secs(deduplication_config.ttl_seconds),
+            &config.server.security,
+        );
+        app = app.layer(axum::middleware::from_fn(
+            middleware::deduplication::create_deduplication_middleware(Arc::new(dedup)),
+        ));
+    }
+
     // Apply request body size limit
     info!(
         "Setting max request body size: {} bytes",
@@ -151,22 +218,23 @@ async fn main() -> Result<()> {
     if let Some(ref rate_limit_config) = config.server.rate_limit {
         info!(
             "Enabling rate limiting: {} req/s, burst: {}",
-            rate_limit_config.requests_per_second, rate_limit_config.burst_size
+            rate_limit_config.limits.requests_per_second, rate_limit_config.limits.burst_size
         );
-        let limiter = middleware::create_rate_limiter(rate_limit_config);
+        let limiter = middleware::create_rate_limiter(rate_limit_config, &config.server, "server");
         app = app.layer(axum::middleware::from_fn(
             middleware::create_rate_limit_middleware(limiter),
         ));
     }
 
-    // TODO: Apply security middleware if configured
-    // Note: Security middleware requires ConnectInfo layer setup
-    if config.server.security.api_keys.is_some()
-        || config.server.security.jwt.is_some()
-        || config.server.security.ip_filter.is_some()
-    {
-        info!("Security configuration detected (middleware integration pending)");
-        // app = app.layer(...);
+    // Apply the IP filter if configured (credentials are checked per route)
+    if let Some(ref ip_filter) = config.server.security.ip_filter {
+        info!("Enabling IP filtering");
+        app = app.layer(axum::middleware::from_fn(
+            middleware::security::create_ip_filter_middleware(middleware::security::IpFilter::new(
+                ip_filter,
+                middleware::client_ip::ClientIpResolver::new(&config.server.client_ip),
+            )),
+        ));
     }
 
     // Apply logging middleware
@@ -193,9 +261,12 @@ async fn main() -> Result<()> {
 
     // Start server with graceful shutdown
     let listener = tokio::net::TcpListener::bind(&bind_addr).await?;
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await?;
 
     info!("Server stopped gracefully");
     Ok(())
@@ -1,18 +1,49 @@
-#![allow(dead_code)]
+use crate::config::{CacheKeyConfig, CacheStoreConfig, RouteCacheConfig, ServerConfig};
+use crate::interpolation::InterpolationContext;
+use crate::middleware::cache_store::{CacheEntry, CacheStore, MemoryStore};
+use crate::middleware::jwt::{create_jwt_validator, JwtValidator};
+use axum::{
+    body::Body,
+    extract::{FromRequestParts, MatchedPath, Query, RawPathParams, Request},
+    http::{header, request::Parts, HeaderMap, HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use metrics::counter;
+use moka::Expiry;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::watch;
+use tracing::{debug, trace, warn};
 
-use axum::{body::Body, extract::Request, http::HeaderValue, middleware::Next, response::Response};
-use moka::future::Cache;
-use std::sync::Arc;
-use std::time::Duration;
-use tracing::{debug, trace};
+/// Cache status header added to cacheable requests
+const X_CACHE: &str = "x-cache";
 
 /// Cache configuration
 #[derive(Debug, Clone)]
 pub struct CacheConfig {
     /// Maximum number of entries in the cache
     pub max_capacity: u64,
-    /// Time to live for cache entries
+    /// Time to live for entries whose response has no max-age directive
     pub ttl: Duration,
+    /// Request attributes that make up the cache key
+    pub key: CacheKeyConfig,
+    /// JWT validation used to key entries by subject
+    pub jwt: Option<Arc<JwtValidator>>,
+    /// Maximum time an entry stays in memory when a shared store is used
+    pub l1_ttl: Option<Duration>,
+    /// How long an expired entry is served while it is refreshed
+    pub stale_while_revalidate: Duration,
+    /// How long an expired entry is served when the upstream fails
+    pub stale_if_error: Duration,
+    /// Tag templates rendered against each request
+    pub tags: Vec<String>,
+    /// Request headers carrying credentials (lowercase): `Authorization` and
+    /// the API key header
+    pub credential_headers: Vec<String>,
 }
 
 impl Default for CacheConfig {
@@ -20,52 +51,633 @@ impl Default for CacheConfig {
         Self {
             max_capacity: 1000,
             ttl: Duration::from_secs(60),
+            key: CacheKeyConfig::default(),
+            jwt: None,
+            l1_ttl: None,
+            stale_while_revalidate: Duration::ZERO,
+            stale_if_error: Duration::ZERO,
+            tags: Vec::new(),
+            credential_headers: vec!["authorization".to_string(), "x-api-key".to_string()],
         }
     }
 }
 
-/// Cache key based on request method and path
-#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+impl CacheConfig {
+    /// Build the cache configuration of a route
+    pub fn for_route(config: &RouteCacheConfig, server: &ServerConfig) -> Self {
+        let l1_ttl = match &server.cache_store {
+            CacheStoreConfig::Memory => None,
+            CacheStoreConfig::Redis(redis) => Some(Duration::from_secs(redis.l1_ttl_seconds)),
+        };
+
+        Self {
+            max_capacity: config.max_entries,
+            ttl: Duration::from_secs(config.ttl_seconds),
+            key: config.key.clone(),
+            jwt: create_jwt_validator(server.security.jwt.as_ref()),
+            l1_ttl,
+            stale_while_revalidate: Duration::from_secs(config.stale_while_revalidate),
+            stale_if_error: Duration::from_secs(config.stale_if_error),
+            tags: config.tags.clone(),
+            credential_headers: vec![
+                "authorization".to_string(),
+                server
+                    .security
+                    .api_keys
+                    .as_ref()
+                    .map(|api_keys| api_keys.header.to_ascii_lowercase())
+                    .unwrap_or_else(|| "x-api-key".to_string()),
+            ],
+        }
+    }
+
+    /// Whether a request carries credentials that are not part of the cache
+    /// key, so that its response may only be stored when explicitly shareable
+    /// (RFC 9111 section 3.5)
+    fn has_unkeyed_credentials(&self, headers: &HeaderMap) -> bool {
+        self.credential_headers.iter().any(|name| {
+            let keyed = self
+                .key
+                .headers
+                .iter()
+                .any(|keyed| keyed.eq_ignore_ascii_case(name))
+                || (self.key.jwt_subject && name == "authorization");
+            headers.contains_key(name.as_str()) && !keyed
+        })
+    }
+}
+
+/// Cache key: request method and path plus the configured key attributes.
+/// `vary` holds the request headers named by the response's Vary header.
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize)]
 struct CacheKey {
     method: String,
     path: String,
+    query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    subject: Option<String>,
+    vary: Vec<(String, String)>,
+}
+
+impl CacheKey {
+    /// Store key of the response cached under this key
+    fn response_key(&self) -> String {
+        format!("r:{}", serde_json::to_string(self).unwrap_or_default())
+    }
+
+    /// Store key of the Vary header names recorded for this primary key
+    fn variants_key(&self) -> String {
+        format!("v:{}", serde_json::to_string(self).unwrap_or_default())
+    }
 }
 
 /// Cached response data
-#[derive(Debug, Clone)]
-struct CachedResponse {
-    status: u16,
-    headers: Vec<(String, String)>,
-    body: bytes::Bytes,
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: bytes::Bytes,
+    pub stored_at: SystemTime,
+    pub ttl: Duration,
+    /// Tags the response was stored under
+    pub tags: Vec<String>,
+}
+
+impl CachedResponse {
+    /// Time since the response was stored
+    pub fn age(&self) -> Duration {
+        self.stored_at.elapsed().unwrap_or_default()
+    }
+}
+
+/// Cache-Control directives relevant to a shared cache
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheDirectives {
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub private: bool,
+    pub public: bool,
+    pub max_age: Option<u64>,
+    pub s_maxage: Option<u64>,
 }
 
-/// Response cache using moka
+impl CacheDirectives {
+    /// Parse the directives of one or more Cache-Control header values
+    pub fn parse<'a>(values: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut directives = Self::default();
+
+        for directive in values.into_iter().flat_map(|value| value.split(',')) {
+            let (name, argument) = match directive.split_once('=') {
+                Some((name, argument)) => (name, Some(argument.trim().trim_matches('"'))),
+                None => (directive, None),
+            };
+
+            match name.trim().to_ascii_lowercase().as_str() {
+                "no-store" => directives.no_store = true,
+                "no-cache" => directives.no_cache = true,
+                "private" => directives.private = true,
+                "public" => directives.public = true,
+                "max-age" => directives.max_age = argument.and_then(|a| a.parse().ok()),
+                "s-maxage" => directives.s_maxage = argument.and_then(|a| a.parse().ok()),
+                _ => {}
+            }
+        }
+
+        directives
+    }
+
+    /// Directives from the Cache-Control headers of a header map
+    fn from_headers(headers: &HeaderMap) -> Self {
+        Self::parse(
+            headers
+                .get_all(header::CACHE_CONTROL)
+                .iter()
+                .filter_map(|value| value.to_str().ok()),
+        )
+    }
+
+    /// Combine with another set of directives, keeping the most restrictive of each
+    pub fn merge(self, other: Self) -> Self {
+        let min = |a: Option<u64>, b: Option<u64>| match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+
+        Self {
+            no_store: self.no_store || other.no_store,
+            no_cache: self.no_cache || other.no_cache,
+            private: self.private || other.private,
+            public: self.public || other.public,
+            max_age: min(self.max_age, other.max_age),
+            s_maxage: min(self.s_maxage, other.s_maxage),
+        }
+    }
+
+    /// Freshness lifetime granted to a shared cache, if any
+    fn shared_max_age(&self) -> Option<u64> {
+        self.s_maxage.or(self.max_age)
+    }
+}
+
+/// Caching hints from the upstream responses of a route, attached to the
+/// gateway response as an extension by the route handler
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamCacheHints {
+    /// Combined Cache-Control directives of the upstream responses
+    pub directives: CacheDirectives,
+    /// Request headers named in upstream Vary headers
+    pub vary: Vec<String>,
+}
+
+/// Response caches of all routes and the store they share, used to
+/// invalidate and inspect cached responses across routes
+#[derive(Debug, Default)]
+pub struct CacheRegistry {
+    store: Option<Arc<dyn CacheStore>>,
+    caches: RwLock<Vec<(String, Arc<ResponseCache>)>>,
+    subrequests: SubrequestCache,
+}
+
+/// Maximum number of cached subrequest results
+const SUBREQUEST_CACHE_CAPACITY: u64 = 10_000;
+
+/// Expires each subrequest result after the time to live it was stored with
+struct ResultExpiry;
+
+impl Expiry<(String, String), (Value, Duration)> for ResultExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &(String, String),
+        value: &(Value, Duration),
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(value.1)
+    }
+}
+
+/// Subrequest results by client ID and key, shared by all routes
+#[derive(Debug)]
+pub struct SubrequestCache {
+    results: moka::future::Cache<(String, String), (Value, Duration)>,
+}
+
+impl Default for SubrequestCache {
+    fn default() -> Self {
+        Self {
+            results: moka::future::Cache::builder()
+                .max_capacity(SUBREQUEST_CACHE_CAPACITY)
+                .expire_after(ResultExpiry)
+                .build(),
+        }
+    }
+}
+
+impl SubrequestCache {
+    /// Get a cached result, counting the hit or miss for the client
+    pub async fn get(&self, client_id: &str, key: &str) -> Option<Value> {
+        let cached = self
+            .results
+            .get(&(client_id.to_string(), key.to_string()))
+            .await;
+
+        let name = match cached {
+            Some(_) => "subrequest_cache_hits_total",
+            None => "subrequest_cache_misses_total",
+        };
+        counter!(name, "client_id" => client_id.to_string()).increment(1);
+
+        cached.map(|(value, _)| value)
+    }
+
+    /// Store a result for the given time to live
+    pub async fn put(&self, client_id: &str, key: &str, value: Value, ttl: Duration) {
+        self.results
+            .insert((client_id.to_string(), key.to_string()), (value, ttl))
+            .await;
+    }
+}
+
+/// A cached response, as listed by the admin API
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheEntryInfo {
+    /// Route that cached the response
+    pub route: String,
+    /// Store key, accepted by the admin key purge endpoint
+    pub key: String,
+    pub status: u16,
+    pub age_seconds: u64,
+    pub ttl_seconds: u64,
+    pub tags: Vec<String>,
+}
+
+impl CacheRegistry {
+    /// Create a registry whose caches are backed by a shared store, if any
+    pub fn new(store: Option<Arc<dyn CacheStore>>) -> Self {
+        Self {
+            store,
+            caches: RwLock::new(Vec::new()),
+            subrequests: SubrequestCache::default(),
+        }
+    }
+
+    /// Create and register the response cache of a route
+    pub fn create(&self, route: String, config: CacheConfig) -> Arc<ResponseCache> {
+        let mut cache = ResponseCache::new(config);
+        if let Some(store) = &self.store {
+            cache = cache.with_store(store.clone());
+        }
+
+        let cache = Arc::new(cache);
+        self.caches
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((route, cache.clone()));
+        cache
+    }
+
+    /// Results of cached subrequests
+    pub fn subrequests(&self) -> &SubrequestCache {
+        &self.subrequests
+    }
+
+    fn caches(&self) -> Vec<(String, Arc<ResponseCache>)> {
+        self.caches
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Remove every response stored under one of the tags, returning how many were removed
+    pub async fn invalidate_tags(&self, tags: &[String]) -> usize {
+        let mut removed = HashSet::new();
+
+        for (_, cache) in self.caches() {
+            for tag in tags {
+                removed.extend(cache.l1.invalidate_tag(tag).await.unwrap_or_default());
+            }
+        }
+
+        if let Some(store) = &self.store {
+            for tag in tags {
+                match store.invalidate_tag(tag).await {
+                    Ok(keys) => removed.extend(keys),
+                    Err(e) => warn!("Cache store invalidation of tag {} failed: {}", tag, e),
+                }
+            }
+        }
+
+        removed.len()
+    }
+
+    /// Remove the responses stored under the given keys
+    pub async fn remove_keys(&self, keys: &[String]) {
+        for (_, cache) in self.caches() {
+            for key in keys {
+                let _ = cache.l1.remove(key).await;
+            }
+        }
+
+        if let Some(store) = &self.store {
+            for key in keys {
+                if let Err(e) = store.remove(key).await {
+                    warn!("Cache store removal failed: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Remove every cached response and subrequest result
+    pub async fn clear(&self) {
+        self.subrequests.results.invalidate_all();
+        for (_, cache) in self.caches() {
+            let _ = cache.l1.clear().await;
+        }
+
+        if let Some(store) = &self.store {
+            if let Err(e) = store.clear().await {
+                warn!("Cache store clear failed: {}", e);
+            }
+        }
+    }
+
+    /// Responses held in this replica's memory
+    pub fn entries(&self) -> Vec<CacheEntryInfo> {
+        let mut entries = Vec::new();
+
+        for (route, cache) in self.caches() {
+            for (key, entry) in cache.l1.entries() {
+                if let CacheEntry::Response(response) = entry {
+                    entries.push(CacheEntryInfo {
+                        route: route.clone(),
+                        key,
+                        status: response.status,
+                        age_seconds: response.age().as_secs(),
+                        ttl_seconds: response.ttl.as_secs(),
+                        tags: response.tags,
+                    });
+                }
+            }
+        }
+
+        entries.sort_by(|a, b| (&a.route, &a.key).cmp(&(&b.route, &b.key)));
+        entries
+    }
+}
+
+/// Response cache: an in-process L1 store, optionally backed by a shared L2
+/// store so that replicas share entries
+#[derive(Debug)]
 pub struct ResponseCache {
-    cache: Cache<CacheKey, CachedResponse>,
+    config: CacheConfig,
+    l1: MemoryStore,
+    l2: Option<Arc<dyn CacheStore>>,
+    /// Upstream requests in progress, by primary key
+    flights: Arc<Mutex<HashMap<CacheKey, watch::Receiver<()>>>>,
+}
+
+/// Participation in the upstream request for a primary key
+enum Flight {
+    /// This request fetches the response; others wait until the guard is dropped
+    Leader(FlightGuard),
+    /// Another request is fetching the response; resolves when it is done
+    Follower(watch::Receiver<()>),
+}
+
+/// Marks an upstream request in progress until dropped
+struct FlightGuard {
+    flights: Arc<Mutex<HashMap<CacheKey, watch::Receiver<()>>>>,
+    key: CacheKey,
+    _done: watch::Sender<()>,
+}
+
+impl Drop for FlightGuard {
+    fn drop(&mut self) {
+        // Waiters wake up when the sender is dropped, after the entry is removed
+        self.flights
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.key);
+    }
 }
 
 impl ResponseCache {
-    /// Create a new response cache
+    /// Create a new in-process response cache
     pub fn new(config: CacheConfig) -> Self {
-        let cache = Cache::builder()
-            .max_capacity(config.max_capacity)
-            .time_to_live(config.ttl)
-            .build();
+        let l1 = MemoryStore::new(config.max_capacity);
+
+        Self {
+            config,
+            l1,
+            l2: None,
+            flights: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Lead the upstream request for a primary key, or wait for the one in progress
+    fn join_flight(&self, key: &CacheKey) -> Flight {
+        let mut flights = self.flights.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(done) = flights.get(key) {
+            return Flight::Follower(done.clone());
+        }
+
+        let (done, receiver) = watch::channel(());
+        flights.insert(key.clone(), receiver);
+        Flight::Leader(FlightGuard {
+            flights: self.flights.clone(),
+            key: key.clone(),
+            _done: done,
+        })
+    }
+
+    /// How long an expired response is kept for stale serving
+    fn stale_window(&self) -> Duration {
+        self.config
+            .stale_while_revalidate
+            .max(self.config.stale_if_error)
+    }
+
+    /// Back the cache with a shared store
+    pub fn with_store(mut self, store: Arc<dyn CacheStore>) -> Self {
+        self.l2 = Some(store);
+        self
+    }
 
-        Self { cache }
+    /// Get an entry from L1, then L2, copying L2 hits into L1
+    async fn get_entry(&self, key: &str) -> Option<CacheEntry> {
+        if let Ok(Some(entry)) = self.l1.get(key).await {
+            return Some(entry);
+        }
+
+        let l2 = self.l2.as_ref()?;
+        let entry = match l2.get(key).await {
+            Ok(entry) => entry?,
+            Err(e) => {
+                warn!("Cache store lookup failed: {}", e);
+                return None;
+            }
+        };
+
+        let ttl = match &entry {
+            CacheEntry::Response(response) => {
+                (response.ttl + self.stale_window()).saturating_sub(response.age())
+            }
+            CacheEntry::Variants(_) => Duration::MAX,
+        };
+        let _ = self.l1.put(key, &entry, self.l1_ttl(ttl)).await;
+        Some(entry)
+    }
+
+    /// Store an entry in L1 and L2
+    async fn put_entry(&self, key: &str, entry: CacheEntry, ttl: Duration) {
+        let _ = self.l1.put(key, &entry, self.l1_ttl(ttl)).await;
+
+        if let Some(l2) = &self.l2 {
+            if let Err(e) = l2.put(key, &entry, ttl).await {
+                warn!("Cache store write failed: {}", e);
+            }
+        }
+    }
+
+    /// Time to live in L1 of an entry with the given overall time to live
+    fn l1_ttl(&self, ttl: Duration) -> Duration {
+        match (self.l2.is_some(), self.config.l1_ttl) {
+            (true, Some(l1_ttl)) => ttl.min(l1_ttl),
+            _ => ttl,
+        }
     }
 
     /// Get a cached response
     async fn get(&self, key: &CacheKey) -> Option<CachedResponse> {
-        self.cache.get(key).await
+        match self.get_entry(&key.response_key()).await? {
+            CacheEntry::Response(response) => Some(response),
+            CacheEntry::Variants(_) => None,
+        }
     }
 
     /// Store a response in the cache
     async fn put(&self, key: CacheKey, response: CachedResponse) {
-        self.cache.insert(key, response).await;
+        let ttl = response.ttl + self.stale_window();
+        let key = key.response_key();
+        let tags = response.tags.clone();
+        self.put_entry(&key, CacheEntry::Response(response), ttl)
+            .await;
+
+        if !tags.is_empty() {
+            let _ = self.l1.add_tags(&key, &tags, self.l1_ttl(ttl)).await;
+            if let Some(l2) = &self.l2 {
+                if let Err(e) = l2.add_tags(&key, &tags, ttl).await {
+                    warn!("Cache store tagging failed: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Vary header names recorded for a primary key
+    async fn variants(&self, primary: &CacheKey) -> Vec<String> {
+        match self.get_entry(&primary.variants_key()).await {
+            Some(CacheEntry::Variants(names)) => names,
+            _ => Vec::new(),
+        }
+    }
+
+    /// Record the Vary header names of a response stored under a primary key
+    async fn set_variants(&self, primary: &CacheKey, names: Vec<String>, ttl: Duration) {
+        let ttl = ttl + self.stale_window();
+        self.put_entry(&primary.variants_key(), CacheEntry::Variants(names), ttl)
+            .await;
+    }
+
+    /// Build the primary cache key of a request, or None if the request can't be cached
+    async fn primary_key(&self, request: Request) -> (Request, Option<CacheKey>) {
+        let key_config = &self.config.key;
+
+        let subject = if key_config.jwt_subject {
+            let subject = self
+                .config
+                .jwt
+                .as_ref()
+                .and_then(|jwt| jwt.subject(request.headers()));
+            if subject.is_none() {
+                return (request, None);
+            }
+            subject
+        } else {
+            None
+        };
+
+        let (request, path) = match &key_config.path_params {
+            Some(names) => {
+                let (mut parts, body) = request.into_parts();
+                let pattern = parts
+                    .extensions
+                    .get::<MatchedPath>()
+                    .map(|matched| matched.as_str().to_string())
+                    .unwrap_or_else(|| parts.uri.path().to_string());
+
+                let mut path = pattern;
+                if let Ok(params) = RawPathParams::from_request_parts(&mut parts, &()).await {
+                    for (name, value) in params.iter() {
+                        if names.iter().any(|n| n == name) {
+                            path.push_str(&format!(";{}={}", name, value));
+                        }
+                    }
+                }
+                (Request::from_parts(parts, body), path)
+            }
+            None => {
+                let path = request.uri().path().to_string();
+                (request, path)
+            }
+        };
+
+        let mut query: Vec<(String, String)> = request
+            .uri()
+            .query()
+            .unwrap_or("")
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match pair.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None => (pair.to_string(), String::new()),
+            })
+            .filter(|(name, _)| match &key_config.query_params {
+                Some(names) => names.contains(name),
+                None => true,
+            })
+            .collect();
+        query.sort();
+
+        let headers = header_values(request.headers(), &key_config.headers);
+
+        let key = CacheKey {
+            method: request.method().to_string(),
+            path,
+            query,
+            headers,
+            subject,
+            vary: Vec::new(),
+        };
+        (request, Some(key))
     }
 }
 
+/// Values of the named request headers, lowercased names, in the given order
+fn header_values(headers: &HeaderMap, names: &[String]) -> Vec<(String, String)> {
+    names
+        .iter()
+        .map(|name| {
+            let name = name.to_ascii_lowercase();
+            let value = headers
+                .get_all(name.as_str())
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect::<Vec<_>>()
+                .join(", ");
+            (name, value)
+        })
+        .collect()
+}
+
 /// Create caching middleware
 pub fn create_cache_middleware(
     cache: Arc<ResponseCache>,
@@ -77,57 +689,145 @@ pub fn create_cache_middleware(
     }
 }
 
+/// Create middleware that invalidates cache tags when a route responds successfully
+pub fn create_invalidation_middleware(
+    caches: Arc<CacheRegistry>,
+    tags: Vec<String>,
+) -> impl Fn(Request, Next) -> std::pin::Pin<Box<dyn std::future::Future<Output = Response> + Send>>
+       + Clone {
+    let tags = Arc::new(tags);
+    move |request: Request, next: Next| {
+        let (caches, tags) = (caches.clone(), tags.clone());
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+            let tags = render_tags(&tags, &mut parts).await;
+            let response = next.run(Request::from_parts(parts, body)).await;
+
+            if response.status().is_success() {
+                let removed = caches.invalidate_tags(&tags).await;
+                debug!(
+                    "Invalidated {} cached responses for tags {:?}",
+                    removed, tags
+                );
+            }
+            response
+        })
+    }
+}
+
 /// Cache middleware handler
 async fn cache_middleware(cache: Arc<ResponseCache>, request: Request, next: Next) -> Response {
-    let method = request.method().to_string();
+    let method = request.method().clone();
     let path = request.uri().path().to_string();
 
     // Only cache GET requests
-    if method != "GET" {
+    if method != Method::GET {
         trace!("Skipping cache for non-GET request: {} {}", method, path);
         return next.run(request).await;
     }
 
-    let key = CacheKey {
-        method: method.clone(),
-        path: path.clone(),
+    // A request with no-store must not be served from or stored in the cache
+    let request_directives = CacheDirectives::from_headers(request.headers());
+    if request_directives.no_store {
+        return with_cache_status(next.run(request).await, "BYPASS");
+    }
+
+    let (request, primary) = cache.primary_key(request).await;
+    let Some(primary) = primary else {
+        debug!("Cache BYPASS: {} {} (no cache key)", method, path);
+        return with_cache_status(next.run(request).await, "BYPASS");
+    };
+
+    // no-cache and max-age=0 force a fresh response, which is then stored
+    let revalidate = request_directives.no_cache
+        || request_directives.max_age == Some(0)
+        || request
+            .headers()
+            .get(header::PRAGMA)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.eq_ignore_ascii_case("no-cache"));
+
+    let cached = match revalidate {
+        true => None,
+        false => lookup(&cache, &primary, request.headers()).await,
     };
 
-    // Check cache
-    if let Some(cached) = cache.get(&key).await {
-        debug!("Cache HIT: {} {}", method, path);
+    if let Some(cached) = &cached {
+        let age = cached.age();
+        let acceptable = request_directives
+            .max_age
+            .is_none_or(|max_age| age.as_secs() <= max_age);
+        if acceptable && age < cached.ttl {
+            debug!("Cache HIT: {} {}", method, path);
+            return cached_response(cached, "HIT");
+        }
 
-        // Build response from cache
-        let mut response = Response::builder().status(cached.status);
+        // Serve the stale response while a single background request refreshes it
+        if request_directives.max_age.is_none()
+            && age < cached.ttl + cache.config.stale_while_revalidate
+        {
+            if let Flight::Leader(flight) = cache.join_flight(&primary) {
+                let (cache, next) = (cache.clone(), next.clone());
+                tokio::spawn(async move {
+                    fetch(&cache, &primary, request, next).await;
+                    drop(flight);
+                });
+            }
+            debug!("Cache STALE: {} {} (revalidating)", method, path);
+            return cached_response(cached, "STALE");
+        }
+    }
 
-        // Add headers
-        for (name, value) in &cached.headers {
-            if let Ok(header_value) = value.parse::<HeaderValue>() {
-                response = response.header(name, header_value);
+    // Concurrent misses for the same key share the upstream request of the first one
+    let flight = match cache.join_flight(&primary) {
+        Flight::Leader(flight) => Some(flight),
+        Flight::Follower(mut done) => {
+            let _ = done.changed().await;
+            if let Some(cached) = lookup(&cache, &primary, request.headers()).await {
+                if cached.age() < cached.ttl {
+                    debug!("Cache HIT: {} {} (coalesced)", method, path);
+                    return cached_response(&cached, "HIT");
+                }
             }
+            // The shared response was not stored, so fetch our own
+            None
         }
+    };
 
-        // Add cache hit header
-        response = response.header("X-Cache", "HIT");
+    debug!("Cache MISS: {} {}", method, path);
+    let response = fetch(&cache, &primary, request, next).await;
+    drop(flight);
 
-        return response
-            .body(Body::from(cached.body.clone()))
-            .unwrap_or_else(|_| Response::new(Body::empty()));
+    if response.status().is_server_error() {
+        if let Some(cached) = cached.filter(|c| c.age() < c.ttl + cache.config.stale_if_error) {
+            debug!("Cache STALE: {} {} (upstream error)", method, path);
+            return cached_response(&cached, "STALE");
+        }
     }
 
-    debug!("Cache MISS: {} {}", method, path);
+    response
+}
+
+/// Run the request upstream and store the response if it is cacheable
+async fn fetch(
+    cache: &ResponseCache,
+    primary: &CacheKey,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let tags = render_tags(&cache.config.tags, &mut parts).await;
+    let request = Request::from_parts(parts, body);
 
-    // Execute request
+    let request_headers = request.headers().clone();
     let response = next.run(request).await;
 
     // Only cache successful responses (2xx)
-    let status = response.status();
-    if !status.is_success() {
-        return response;
-    }
+    let Some(ttl) = storable_ttl(&cache.config, &request_headers, &response) else {
+        return with_cache_status(response, "MISS");
+    };
 
-    // Extract response parts for caching
-    let (parts, body) = response.into_parts();
+    let (mut parts, body) = response.into_parts();
 
     // Read body
     let body_bytes = match axum::body::to_bytes(body, usize::MAX).await {
@@ -141,40 +841,192 @@ async fn cache_middleware(cache: Arc<ResponseCache>, request: Request, next: Nex
         }
     };
 
-    // Store in cache
+    let vary = vary_headers(&parts.headers, parts.extensions.get::<UpstreamCacheHints>());
+    let key = CacheKey {
+        vary: header_values(&request_headers, &vary),
+        ..primary.clone()
+    };
+
     let headers: Vec<(String, String)> = parts
         .headers
         .iter()
+        .filter(|(name, _)| *name != header::AGE && name.as_str() != X_CACHE)
         .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("").to_string()))
         .collect();
 
-    let cached_response = CachedResponse {
-        status: parts.status.as_u16(),
-        headers: headers.clone(),
-        body: body_bytes.clone(),
+    cache.set_variants(primary, vary, ttl).await;
+    cache
+        .put(
+            key,
+            CachedResponse {
+                status: parts.status.as_u16(),
+                headers,
+                body: body_bytes.clone(),
+                stored_at: SystemTime::now(),
+                ttl,
+                tags,
+            },
+        )
+        .await;
+
+    parts
+        .headers
+        .insert(X_CACHE, HeaderValue::from_static("MISS"));
+    Response::from_parts(parts, Body::from(body_bytes))
+}
+
+/// Render tag templates against the method, path parameters, query and headers of a request
+async fn render_tags(templates: &[String], parts: &mut Parts) -> Vec<String> {
+    if templates.is_empty() {
+        return Vec::new();
+    }
+
+    let path_params = RawPathParams::from_request_parts(parts, &())
+        .await
+        .map(|params| {
+            params
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    let query_params = Query::<HashMap<String, String>>::try_from_uri(&parts.uri)
+        .map(|Query(query)| query)
+        .unwrap_or_default();
+
+    let context = InterpolationContext::new(
+        parts.headers.clone(),
+        path_params,
+        query_params,
+        None,
+        parts.method.clone(),
+    );
+    templates
+        .iter()
+        .map(|template| context.interpolate(template))
+        .collect()
+}
+
+/// Find the cached variant of a request
+async fn lookup(
+    cache: &ResponseCache,
+    primary: &CacheKey,
+    headers: &HeaderMap,
+) -> Option<CachedResponse> {
+    let vary = cache.variants(primary).await;
+    let key = CacheKey {
+        vary: header_values(headers, &vary),
+        ..primary.clone()
     };
+    cache.get(&key).await
+}
 
-    cache.put(key, cached_response).await;
+/// Time to live of a response, or None if it must not be stored: non-2xx,
+/// no-store/private/no-cache directives from the response or upstreams,
+/// Set-Cookie, Vary: *, a zero max-age, or a request with credentials outside
+/// the key unless the response is public or has s-maxage
+fn storable_ttl(
+    config: &CacheConfig,
+    request_headers: &HeaderMap,
+    response: &Response,
+) -> Option<Duration> {
+    if !response.status().is_success() {
+        return None;
+    }
 
-    // Build response with body
-    let mut response = Response::builder()
-        .status(parts.status)
-        .header("X-Cache", "MISS");
+    let headers = response.headers();
+    if headers.contains_key(header::SET_COOKIE) {
+        return None;
+    }
+    if headers
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.split(',').any(|name| name.trim() == "*"))
+    {
+        return None;
+    }
 
-    for (name, value) in headers {
+    let mut directives = CacheDirectives::from_headers(headers);
+    if let Some(hints) = response.extensions().get::<UpstreamCacheHints>() {
+        directives = directives.merge(hints.directives.clone());
+    }
+    if directives.no_store || directives.private || directives.no_cache {
+        return None;
+    }
+    if config.has_unkeyed_credentials(request_headers)
+        && !directives.public
+        && directives.s_maxage.is_none()
+    {
+        return None;
+    }
+
+    let ttl = directives
+        .shared_max_age()
+        .map(Duration::from_secs)
+        .unwrap_or(config.ttl);
+    (!ttl.is_zero()).then_some(ttl)
+}
+
+/// Request headers a response varies on, from its Vary header and upstream hints
+fn vary_headers(headers: &HeaderMap, hints: Option<&UpstreamCacheHints>) -> Vec<String> {
+    let mut vary: Vec<String> = headers
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .chain(hints.iter().flat_map(|h| h.vary.iter().map(String::as_str)))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    vary.sort();
+    vary.dedup();
+    vary
+}
+
+/// Build a response from a cache entry, with Age and X-Cache headers
+fn cached_response(cached: &CachedResponse, status: &'static str) -> Response {
+    let mut response = Response::builder().status(cached.status);
+
+    for (name, value) in &cached.headers {
         if let Ok(header_value) = value.parse::<HeaderValue>() {
             response = response.header(name, header_value);
         }
     }
 
+    response = response
+        .header(header::AGE, cached.age().as_secs())
+        .header(X_CACHE, status);
+
     response
-        .body(Body::from(body_bytes))
+        .body(Body::from(cached.body.clone()))
         .unwrap_or_else(|_| Response::new(Body::empty()))
 }
 
+fn with_cache_status(mut response: Response, status: &'static str) -> Response {
+    response
+        .headers_mut()
+        .insert(X_CACHE, HeaderValue::from_static(status));
+    response
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use axum::{routing::get, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+
+    fn key(path: &str) -> CacheKey {
+        CacheKey {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            query: vec![],
+            headers: vec![],
+            subject: None,
+            vary: vec![],
+        }
+    }
 
     #[tokio::test]
     async fn test_cache_creation() {
@@ -182,12 +1034,7 @@ mod tests {
         let cache = ResponseCache::new(config);
 
         // Verify cache is empty
-        let key = CacheKey {
-            method: "GET".to_string(),
-            path: "/test".to_string(),
-        };
-
-        assert!(cache.get(&key).await.is_none());
+        assert!(cache.get(&key("/test")).await.is_none());
     }
 
     #[tokio::test]
@@ -195,24 +1042,334 @@ mod tests {
         let config = CacheConfig::default();
         let cache = ResponseCache::new(config);
 
-        let key = CacheKey {
-            method: "GET".to_string(),
-            path: "/test".to_string(),
-        };
-
         let response = CachedResponse {
             status: 200,
             headers: vec![("content-type".to_string(), "application/json".to_string())],
             body: bytes::Bytes::from("test response"),
+            stored_at: SystemTime::now(),
+            ttl: Duration::from_secs(60),
+            tags: vec![],
         };
 
-        cache.put(key.clone(), response.clone()).await;
+        cache.put(key("/test"), response.clone()).await;
 
-        let cached = cache.get(&key).await;
+        let cached = cache.get(&key("/test")).await;
         assert!(cached.is_some());
 
         let cached = cached.unwrap();
         assert_eq!(cached.status, 200);
         assert_eq!(cached.body, bytes::Bytes::from("test response"));
     }
+
+    #[tokio::test]
+    async fn test_shared_store() {
+        let store: Arc<dyn CacheStore> = Arc::new(MemoryStore::new(100));
+        let config = CacheConfig {
+            l1_ttl: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        let replica_a = ResponseCache::new(config.clone()).with_store(store.clone());
+        let replica_b = ResponseCache::new(config).with_store(store.clone());
+
+        let response = CachedResponse {
+            status: 200,
+            headers: vec![],
+            body: bytes::Bytes::from("shared"),
+            stored_at: SystemTime::now(),
+            ttl: Duration::from_secs(60),
+            tags: vec![],
+        };
+        replica_a.put(key("/shared"), response).await;
+        replica_b
+            .set_variants(
+                &key("/shared"),
+                vec!["accept".to_string()],
+                Duration::from_secs(60),
+            )
+            .await;
+
+        // Entries written by one replica are served to the other from L2
+        assert_eq!(
+            replica_b.get(&key("/shared")).await.unwrap().body,
+            bytes::Bytes::from("shared")
+        );
+        assert_eq!(replica_a.variants(&key("/shared")).await, vec!["accept"]);
+
+        // With a zero L1 time to live, nothing is kept in memory
+        assert!(replica_a
+            .l1
+            .get(&key("/shared").response_key())
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_parse_cache_directives() {
+        let directives = CacheDirectives::parse(["public, max-age=60", "s-maxage=\"30\""]);
+        assert_eq!(directives.max_age, Some(60));
+        assert_eq!(directives.shared_max_age(), Some(30));
+        assert!(!directives.no_store);
+
+        let merged = directives.merge(CacheDirectives::parse(["max-age=10, private"]));
+        assert_eq!(merged.max_age, Some(10));
+        assert!(merged.private);
+    }
+
+    /// Router whose handler counts calls and echoes the query string
+    fn cached_router(
+        config: CacheConfig,
+        response_headers: &'static [(&'static str, &'static str)],
+    ) -> (Router, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let cache = Arc::new(ResponseCache::new(config));
+
+        let router = Router::new()
+            .route(
+                "/items/:id",
+                get(move |request: Request| {
+                    let counter = counter.clone();
+                    async move {
+                        let call = counter.fetch_add(1, Ordering::SeqCst);
+                        let mut response = Response::new(Body::from(format!(
+                            "{}?{} #{}",
+                            request.uri().path(),
+                            request.uri().query().unwrap_or(""),
+                            call
+                        )));
+                        for (name, value) in response_headers {
+                            response
+                                .headers_mut()
+                                .insert(*name, HeaderValue::from_static(value));
+                        }
+                        response
+                    }
+                }),
+            )
+            .layer(axum::middleware::from_fn(create_cache_middleware(cache)));
+        (router, calls)
+    }
+
+    async fn send(router: &Router, uri: &str, headers: &[(&str, &str)]) -> Response {
+        let mut request = Request::builder().uri(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_query_params_are_part_of_the_key() {
+        let (router, calls) = cached_router(CacheConfig::default(), &[]);
+
+        let first = send(&router, "/items/1?q=a&page=1", &[]).await;
+        assert_eq!(first.headers()[X_CACHE], "MISS");
+
+        let hit = send(&router, "/items/1?page=1&q=a", &[]).await;
+        assert_eq!(hit.headers()[X_CACHE], "HIT");
+        assert!(hit.headers().contains_key(header::AGE));
+
+        let other = send(&router, "/items/1?q=b&page=1", &[]).await;
+        assert_eq!(other.headers()[X_CACHE], "MISS");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_selected_key_attributes() {
+        let config = CacheConfig {
+            key: CacheKeyConfig {
+                query_params: Some(vec!["q".to_string()]),
+                headers: vec!["X-Tenant".to_string()],
+                path_params: Some(vec![]),
+                jwt_subject: false,
+            },
+            ..Default::default()
+        };
+        let (router, calls) = cached_router(config, &[]);
+
+        send(&router, "/items/1?q=a&utm=x", &[("x-tenant", "acme")]).await;
+        // Ignored query parameter and path parameter: same entry
+        let hit = send(&router, "/items/2?q=a&utm=y", &[("x-tenant", "acme")]).await;
+        assert_eq!(hit.headers()[X_CACHE], "HIT");
+        // Different tenant: different entry
+        let miss = send(&router, "/items/1?q=a", &[("x-tenant", "other")]).await;
+        assert_eq!(miss.headers()[X_CACHE], "MISS");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_request_cache_control() {
+        let (router, calls) = cached_router(CacheConfig::default(), &[]);
+
+        send(&router, "/items/1", &[]).await;
+        let bypass = send(&router, "/items/1", &[("cache-control", "no-store")]).await;
+        assert_eq!(bypass.headers()[X_CACHE], "BYPASS");
+        let refreshed = send(&router, "/items/1", &[("cache-control", "no-cache")]).await;
+        assert_eq!(refreshed.headers()[X_CACHE], "MISS");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_response_cache_control_and_vary() {
+        let (router, calls) =
+            cached_router(CacheConfig::default(), &[("cache-control", "no-store")]);
+        send(&router, "/items/1", &[]).await;
+        send(&router, "/items/1", &[]).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let (router, calls) = cached_router(CacheConfig::default(), &[("vary", "Accept-Language")]);
+        send(&router, "/items/1", &[("accept-language", "en")]).await;
+        let hit = send(&router, "/items/1", &[("accept-language", "en")]).await;
+        assert_eq!(hit.headers()[X_CACHE], "HIT");
+        let miss = send(&router, "/items/1", &[("accept-language", "fr")]).await;
+        assert_eq!(miss.headers()[X_CACHE], "MISS");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_credentialed_responses_are_not_shared() {
+        let (router, calls) = cached_router(CacheConfig::default(), &[]);
+        send(&router, "/items/1", &[("authorization", "Bearer alice")]).await;
+        let response = send(&router, "/items/1", &[("x-api-key", "bob")]).await;
+        assert_eq!(response.headers()[X_CACHE], "MISS");
+        let response = send(&router, "/items/1", &[]).await;
+        assert_eq!(response.headers()[X_CACHE], "MISS");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // Explicitly shareable responses are stored
+        let (router, calls) = cached_router(CacheConfig::default(), &[("cache-control", "public")]);
+        send(&router, "/items/1", &[("authorization", "Bearer alice")]).await;
+        let response = send(&router, "/items/1", &[]).await;
+        assert_eq!(response.headers()[X_CACHE], "HIT");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // So are responses keyed by the credential
+        let config = CacheConfig {
+            key: CacheKeyConfig {
+                headers: vec!["Authorization".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (router, calls) = cached_router(config, &[]);
+        send(&router, "/items/1", &[("authorization", "Bearer alice")]).await;
+        let hit = send(&router, "/items/1", &[("authorization", "Bearer alice")]).await;
+        assert_eq!(hit.headers()[X_CACHE], "HIT");
+        let miss = send(&router, "/items/1", &[("authorization", "Bearer bob")]).await;
+        assert_eq!(miss.headers()[X_CACHE], "MISS");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    /// Router whose handler takes `delay_ms`, answers with its call number and
+    /// fails with 500 from call `fail_from` on
+    fn slow_router(
+        config: CacheConfig,
+        delay_ms: u64,
+        fail_from: usize,
+    ) -> (Router, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let cache = Arc::new(ResponseCache::new(config));
+
+        let router = Router::new()
+            .route(
+                "/slow",
+                get(move || {
+                    let call = counter.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+                        let status = if call >= fail_from { 500 } else { 200 };
+                        (
+                            axum::http::StatusCode::from_u16(status).unwrap(),
+                            format!("#{}", call),
+                        )
+                    }
+                }),
+            )
+            .layer(axum::middleware::from_fn(create_cache_middleware(cache)));
+        (router, calls)
+    }
+
+    async fn body_text(response: Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_misses_are_coalesced() {
+        let (router, calls) = slow_router(CacheConfig::default(), 100, usize::MAX);
+
+        let responses =
+            futures::future::join_all((0..5).map(|_| send(&router, "/slow", &[]))).await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        let mut statuses: Vec<_> = responses
+            .iter()
+            .map(|r| r.headers()[X_CACHE].to_str().unwrap().to_string())
+            .collect();
+        statuses.sort();
+        assert_eq!(statuses, ["HIT", "HIT", "HIT", "HIT", "MISS"]);
+    }
+
+    #[tokio::test]
+    async fn test_stale_while_revalidate() {
+        let config = CacheConfig {
+            ttl: Duration::from_millis(50),
+            stale_while_revalidate: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let (router, calls) = slow_router(config, 0, usize::MAX);
+
+        send(&router, "/slow", &[]).await;
+        tokio::time::sleep(Duration::from_millis(80)).await;
+
+        let stale = send(&router, "/slow", &[]).await;
+        assert_eq!(stale.headers()[X_CACHE], "STALE");
+        assert_eq!(body_text(stale).await, "#0");
+
+        // The background refresh replaces the entry
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let refreshed = send(&router, "/slow", &[]).await;
+        assert_eq!(refreshed.headers()[X_CACHE], "HIT");
+        assert_eq!(body_text(refreshed).await, "#1");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_stale_if_error() {
+        let config = CacheConfig {
+            ttl: Duration::from_millis(50),
+            stale_if_error: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let (router, _) = slow_router(config, 0, 1);
+
+        send(&router, "/slow", &[]).await;
+        tokio::time::sleep(Duration::from_millis(80)).await;
+
+        let stale = send(&router, "/slow", &[]).await;
+        assert_eq!(stale.status(), 200);
+        assert_eq!(stale.headers()[X_CACHE], "STALE");
+        assert_eq!(body_text(stale).await, "#0");
+
+        // Without a stale window the error is returned
+        let (router, _) = slow_router(
+            CacheConfig {
+                ttl: Duration::from_millis(50),
+                ..Default::default()
+            },
+            0,
+            1,
+        );
+        send(&router, "/slow", &[]).await;
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        assert_eq!(send(&router, "/slow", &[]).await.status(), 500);
+    }
 }
@@ -0,0 +1,442 @@
+use crate::config::{CacheStoreConfig, RedisCacheStoreConfig};
+use crate::middleware::cache::CachedResponse;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use moka::{future::Cache, Expiry};
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, Client};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::info;
+
+/// Value kept in a cache store
+#[derive(Debug, Clone, PartialEq)]
+pub enum CacheEntry {
+    /// A cached response
+    Response(CachedResponse),
+    /// Request headers that the responses stored under a primary key vary on
+    Variants(Vec<String>),
+}
+
+/// Storage backend of the response cache.
+///
+/// Errors are reported to the caller, which treats a failing store as a miss.
+#[async_trait]
+pub trait CacheStore: Send + Sync + std::fmt::Debug {
+    /// Get an entry
+    async fn get(&self, key: &str) -> Result<Option<CacheEntry>>;
+
+    /// Store an entry for the given time to live
+    async fn put(&self, key: &str, entry: &CacheEntry, ttl: Duration) -> Result<()>;
+
+    /// Remove an entry
+    async fn remove(&self, key: &str) -> Result<()>;
+
+    /// Record the tags of an entry stored for the given time to live
+    async fn add_tags(&self, key: &str, tags: &[String], ttl: Duration) -> Result<()>;
+
+    /// Remove every entry carrying a tag, returning the removed keys
+    async fn invalidate_tag(&self, tag: &str) -> Result<Vec<String>>;
+
+    /// Remove every entry
+    async fn clear(&self) -> Result<()>;
+}
+
+/// Create the shared cache store, if one is configured
+pub async fn create_cache_store(config: &CacheStoreConfig) -> Result<Option<Arc<dyn CacheStore>>> {
+    match config {
+        CacheStoreConfig::Memory => Ok(None),
+        CacheStoreConfig::Redis(redis_config) => {
+            let store = RedisStore::new(redis_config.clone()).await?;
+            Ok(Some(Arc::new(store)))
+        }
+    }
+}
+
+/// Expires each entry after the time to live it was stored with
+struct EntryExpiry;
+
+impl Expiry<String, (CacheEntry, Duration)> for EntryExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &String,
+        value: &(CacheEntry, Duration),
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(value.1)
+    }
+}
+
+/// In-process store using moka
+#[derive(Debug)]
+pub struct MemoryStore {
+    cache: Cache<String, (CacheEntry, Duration)>,
+    /// Keys stored under each tag; keys of expired entries are pruned lazily
+    tags: Mutex<HashMap<String, HashSet<String>>>,
+}
+
+impl MemoryStore {
+    /// Create a store holding at most `max_capacity` entries
+    pub fn new(max_capacity: u64) -> Self {
+        let cache = Cache::builder()
+            .max_capacity(max_capacity)
+            .expire_after(EntryExpiry)
+            .build();
+
+        Self {
+            cache,
+            tags: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Live entries with their keys
+    pub fn entries(&self) -> Vec<(String, CacheEntry)> {
+        self.cache
+            .iter()
+            .map(|(key, (entry, _))| (key.to_string(), entry))
+            .collect()
+    }
+
+    fn lock_tags(&self) -> std::sync::MutexGuard<'_, HashMap<String, HashSet<String>>> {
+        self.tags.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl CacheStore for MemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<CacheEntry>> {
+        Ok(self.cache.get(key).await.map(|(entry, _)| entry))
+    }
+
+    async fn put(&self, key: &str, entry: &CacheEntry, ttl: Duration) -> Result<()> {
+        if !ttl.is_zero() {
+            self.cache
+                .insert(key.to_string(), (entry.clone(), ttl))
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, key: &str) -> Result<()> {
+        self.cache.invalidate(key).await;
+        Ok(())
+    }
+
+    async fn add_tags(&self, key: &str, tags: &[String], _ttl: Duration) -> Result<()> {
+        let mut index = self.lock_tags();
+        for tag in tags {
+            let keys = index.entry(tag.clone()).or_default();
+            keys.insert(key.to_string());
+            if keys.len() as u64 > self.cache.entry_count().max(64) {
+                keys.retain(|key| self.cache.contains_key(key));
+            }
+        }
+        Ok(())
+    }
+
+    async fn invalidate_tag(&self, tag: &str) -> Result<Vec<String>> {
+        let keys = self.lock_tags().remove(tag).unwrap_or_default();
+        let mut removed = Vec::new();
+        for key in keys {
+            if self.cache.remove(&key).await.is_some() {
+                removed.push(key);
+            }
+        }
+        Ok(removed)
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.cache.invalidate_all();
+        self.lock_tags().clear();
+        Ok(())
+    }
+}
+
+/// Store shared by all gateway replicas, in Redis
+#[derive(Clone)]
+pub struct RedisStore {
+    manager: ConnectionManager,
+    config: RedisCacheStoreConfig,
+}
+
+impl std::fmt::Debug for RedisStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisStore")
+            .field("key_prefix", &self.config.key_prefix)
+            .finish()
+    }
+}
+
+impl RedisStore {
+    /// Connect to Redis
+    pub async fn new(config: RedisCacheStoreConfig) -> Result<Self> {
+        info!("Creating Redis cache store");
+
+        let client = Client::open(config.connection_string.as_str())?;
+        let manager = ConnectionManager::new(client).await?;
+
+        Ok(Self { manager, config })
+    }
+
+    fn redis_key(&self, key: &str) -> String {
+        format!("{}{}", self.config.key_prefix, key)
+    }
+
+    fn tag_key(&self, tag: &str) -> String {
+        format!("{}tag:{}", self.config.key_prefix, tag)
+    }
+
+    fn min_compressed_size(&self) -> Option<usize> {
+        self.config
+            .compression
+            .then_some(self.config.compression_min_bytes)
+    }
+}
+
+#[async_trait]
+impl CacheStore for RedisStore {
+    async fn get(&self, key: &str) -> Result<Option<CacheEntry>> {
+        let mut conn = self.manager.clone();
+        let value: Option<Vec<u8>> = conn.get(self.redis_key(key)).await?;
+        value.map(|bytes| decode_entry(&bytes)).transpose()
+    }
+
+    async fn put(&self, key: &str, entry: &CacheEntry, ttl: Duration) -> Result<()> {
+        let millis = ttl.as_millis() as u64;
+        if millis == 0 {
+            return Ok(());
+        }
+
+        let bytes = encode_entry(entry, self.min_compressed_size())?;
+        let mut conn = self.manager.clone();
+        let _: () = conn.pset_ex(self.redis_key(key), bytes, millis).await?;
+        Ok(())
+    }
+
+    async fn remove(&self, key: &str) -> Result<()> {
+        let mut conn = self.manager.clone();
+        let _: () = conn.del(self.redis_key(key)).await?;
+        Ok(())
+    }
+
+    async fn add_tags(&self, key: &str, tags: &[String], ttl: Duration) -> Result<()> {
+        let millis = ttl.as_millis() as i64;
+        let mut conn = self.manager.clone();
+
+        for tag in tags {
+            let tag_key = self.tag_key(tag);
+            let _: () = conn.sadd(&tag_key, key).await?;
+            // A tag set lives as long as its longest-lived entry
+            let remaining: i64 = conn.pttl(&tag_key).await?;
+            if remaining < millis {
+                let _: () = conn.pexpire(&tag_key, millis).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn invalidate_tag(&self, tag: &str) -> Result<Vec<String>> {
+        let mut conn = self.manager.clone();
+        let tag_key = self.tag_key(tag);
+
+        let keys: Vec<String> = conn.smembers(&tag_key).await?;
+        let mut removed = Vec::new();
+        for key in keys {
+            let deleted: i64 = conn.del(self.redis_key(&key)).await?;
+            if deleted > 0 {
+                removed.push(key);
+            }
+        }
+        let _: () = conn.del(&tag_key).await?;
+        Ok(removed)
+    }
+
+    async fn clear(&self) -> Result<()> {
+        let mut conn = self.manager.clone();
+        let pattern = format!("{}*", self.config.key_prefix);
+
+        let keys: Vec<String> = {
+            let mut scan = conn.scan_match::<_, String>(&pattern).await?;
+            let mut keys = Vec::new();
+            while let Some(key) = scan.next_item().await {
+                keys.push(key);
+            }
+            keys
+        };
+        for chunk in keys.chunks(100) {
+            let _: () = conn.del(chunk).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Entry metadata; a response body follows it in the encoded form
+#[derive(Debug, Serialize, Deserialize)]
+enum EntryMeta {
+    Response {
+        status: u16,
+        headers: Vec<(String, String)>,
+        stored_at: SystemTime,
+        ttl: Duration,
+        #[serde(default)]
+        tags: Vec<String>,
+    },
+    Variants(Vec<String>),
+}
+
+/// Flag set when the encoded body is gzip-compressed
+const FLAG_COMPRESSED: u8 = 1;
+
+/// Encode an entry as: flags byte, metadata length (u32, big endian),
+/// JSON metadata, body. The body is gzip-compressed when it is at least
+/// `min_compressed_size` bytes and compression makes it smaller.
+fn encode_entry(entry: &CacheEntry, min_compressed_size: Option<usize>) -> Result<Vec<u8>> {
+    let (meta, body) = match entry {
+        CacheEntry::Response(response) => (
+            EntryMeta::Response {
+                status: response.status,
+                headers: response.headers.clone(),
+                stored_at: response.stored_at,
+                ttl: response.ttl,
+                tags: response.tags.clone(),
+            },
+            &response.body[..],
+        ),
+        CacheEntry::Variants(names) => (EntryMeta::Variants(names.clone()), &[][..]),
+    };
+
+    let mut flags = 0;
+    let mut body = body.to_vec();
+    if min_compressed_size.is_some_and(|min| !body.is_empty() && body.len() >= min) {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&body)?;
+        let compressed = encoder.finish()?;
+        if compressed.len() < body.len() {
+            body = compressed;
+            flags |= FLAG_COMPRESSED;
+        }
+    }
+
+    let meta = serde_json::to_vec(&meta)?;
+    let mut bytes = Vec::with_capacity(5 + meta.len() + body.len());
+    bytes.push(flags);
+    bytes.extend_from_slice(&(meta.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&meta);
+    bytes.extend_from_slice(&body);
+    Ok(bytes)
+}
+
+/// Decode an entry written by `encode_entry`
+fn decode_entry(bytes: &[u8]) -> Result<CacheEntry> {
+    let (&flags, rest) = bytes.split_first().context("Empty cache entry")?;
+    let length: [u8; 4] = rest
+        .get(..4)
+        .and_then(|length| length.try_into().ok())
+        .context("Truncated cache entry")?;
+    let rest = &rest[4..];
+    let length = u32::from_be_bytes(length) as usize;
+    if rest.len() < length {
+        anyhow::bail!("Truncated cache entry");
+    }
+    let (meta, body) = rest.split_at(length);
+
+    match serde_json::from_slice(meta)? {
+        EntryMeta::Response {
+            status,
+            headers,
+            stored_at,
+            ttl,
+            tags,
+        } => {
+            let body = if flags & FLAG_COMPRESSED != 0 {
+                let mut decompressed = Vec::new();
+                GzDecoder::new(body).read_to_end(&mut decompressed)?;
+                decompressed
+            } else {
+                body.to_vec()
+            };
+
+            Ok(CacheEntry::Response(CachedResponse {
+                status,
+                headers,
+                body: bytes::Bytes::from(body),
+                stored_at,
+                ttl,
+                tags,
+            }))
+        }
+        EntryMeta::Variants(names) => Ok(CacheEntry::Variants(names)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &str) -> CacheEntry {
+        CacheEntry::Response(CachedResponse {
+            status: 200,
+            headers: vec![("content-type".to_string(), "text/plain".to_string())],
+            body: bytes::Bytes::from(body.to_string()),
+            stored_at: SystemTime::now(),
+            ttl: Duration::from_secs(60),
+            tags: vec!["greeting".to_string()],
+        })
+    }
+
+    #[test]
+    fn test_entry_encoding_round_trip() {
+        let large = response(&"all work and no play ".repeat(100));
+        let compressed = encode_entry(&large, Some(64)).unwrap();
+        let uncompressed = encode_entry(&large, None).unwrap();
+        assert_eq!(compressed[0], FLAG_COMPRESSED);
+        assert!(compressed.len() < uncompressed.len());
+        assert_eq!(decode_entry(&compressed).unwrap(), large);
+        assert_eq!(decode_entry(&uncompressed).unwrap(), large);
+
+        // Small bodies are stored as they are
+        let small = response("tiny");
+        let encoded = encode_entry(&small, Some(64)).unwrap();
+        assert_eq!(encoded[0], 0);
+        assert_eq!(decode_entry(&encoded).unwrap(), small);
+
+        let variants = CacheEntry::Variants(vec!["accept-language".to_string()]);
+        let encoded = encode_entry(&variants, Some(0)).unwrap();
+        assert_eq!(decode_entry(&encoded).unwrap(), variants);
+
+        assert!(decode_entry(&encoded[..3]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        let store = MemoryStore::new(10);
+        let entry = response("hello");
+
+        store
+            .put("a", &entry, Duration::from_secs(60))
+            .await
+            .unwrap();
+        store.put("b", &entry, Duration::ZERO).await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), Some(entry.clone()));
+        assert_eq!(store.get("b").await.unwrap(), None);
+
+        store
+            .add_tags("a", &["user:1".to_string()], Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(store.invalidate_tag("user:2").await.unwrap().len(), 0);
+        assert_eq!(store.invalidate_tag("user:1").await.unwrap(), vec!["a"]);
+        assert_eq!(store.get("a").await.unwrap(), None);
+
+        store
+            .put("c", &entry, Duration::from_secs(60))
+            .await
+            .unwrap();
+        store.clear().await.unwrap();
+        assert_eq!(store.get("c").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,259 @@
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::HeaderMap,
+};
+use ipnet::IpNet;
+use std::net::{AddrParseError, IpAddr, Ipv4Addr, SocketAddr};
+
+use crate::config::{ClientIpConfig, ForwardedHeader};
+
+/// Resolves client IPs with the server's `client_ip` configuration, so IP
+/// filters and rate limits agree on who the client is
+#[derive(Debug, Clone, Default)]
+pub struct ClientIpResolver {
+    trusted_proxies: Vec<IpNet>,
+    forwarded_header: ForwardedHeader,
+}
+
+impl ClientIpResolver {
+    /// Create a resolver; invalid proxies are rejected by config validation
+    pub fn new(config: &ClientIpConfig) -> Self {
+        Self {
+            trusted_proxies: parse_networks(&config.trusted_proxies),
+            forwarded_header: config.forwarded_header,
+        }
+    }
+
+    /// Whether a peer is one of the trusted proxies
+    pub fn is_trusted(&self, peer: IpAddr) -> bool {
+        let peer = peer.to_canonical();
+        self.trusted_proxies.iter().any(|net| net.contains(&peer))
+    }
+
+    /// IP of the client that sent a request
+    pub fn resolve(&self, request: &Request) -> Option<IpAddr> {
+        resolve_client_ip(
+            request.headers(),
+            peer_ip(request),
+            &self.trusted_proxies,
+            self.forwarded_header,
+        )
+    }
+}
+
+/// Parse an IP network in CIDR notation, or a single address
+pub fn parse_network(entry: &str) -> Result<IpNet, AddrParseError> {
+    let entry = entry.trim();
+    entry
+        .parse::<IpNet>()
+        .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+}
+
+/// Parse a list of networks, skipping invalid entries (rejected by config validation)
+pub fn parse_networks(entries: &[String]) -> Vec<IpNet> {
+    entries
+        .iter()
+        .filter_map(|entry| parse_network(entry).ok())
+        .collect()
+}
+
+/// Address of the connected peer, when the server was started with connect info
+pub fn peer_ip(request: &Request) -> Option<IpAddr> {
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+/// Resolve the IP of the client that sent a request.
+///
+/// This is the peer address, unless the peer is a trusted proxy: then the
+/// forwarded header is walked from the right, skipping trusted proxies, and
+/// the first untrusted address is the client.
+pub fn resolve_client_ip(
+    headers: &HeaderMap,
+    peer: Option<IpAddr>,
+    trusted_proxies: &[IpNet],
+    header: ForwardedHeader,
+) -> Option<IpAddr> {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+
+    // IPv4 clients of a dual-stack listener connect from mapped IPv6 addresses
+    let peer = peer?.to_canonical();
+    if !is_trusted(&peer) {
+        return Some(peer);
+    }
+
+    let mut client = peer;
+    for hop in forwarded_hops(headers, header).into_iter().rev() {
+        let Some(ip) = hop else {
+            // Anything left of a malformed or obfuscated entry cannot be trusted
+            break;
+        };
+        client = ip.to_canonical();
+        if !is_trusted(&client) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+/// Addresses listed in every instance of the forwarded header, in order,
+/// with `None` for entries that are not an address
+fn forwarded_hops(headers: &HeaderMap, header: ForwardedHeader) -> Vec<Option<IpAddr>> {
+    let name = match header {
+        ForwardedHeader::XForwardedFor => "x-forwarded-for",
+        ForwardedHeader::Forwarded => "forwarded",
+    };
+    let elements = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|element| !element.is_empty());
+
+    match header {
+        ForwardedHeader::XForwardedFor => elements.map(parse_node).collect(),
+        ForwardedHeader::Forwarded => elements
+            .map(|element| {
+                // An element is `;`-separated pairs, e.g. `for=192.0.2.1;proto=https`
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node))
+            })
+            .collect(),
+    }
+}
+
+/// Parse a forwarded node: an address, optionally quoted, bracketed (IPv6)
+/// or followed by a port, e.g. `"[2001:db8::1]:4711"` or `192.0.2.1:8080`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    node.rsplit_once(':')?
+        .0
+        .parse::<Ipv4Addr>()
+        .ok()
+        .map(IpAddr::V4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_network() {
+        assert!(parse_network("10.0.0.0/8")
+            .unwrap()
+            .contains(&ip("10.1.2.3")));
+        assert!(parse_network("10.0.0.1").unwrap().contains(&ip("10.0.0.1")));
+        assert!(!parse_network("10.0.0.1")
+            .unwrap()
+            .contains(&ip("10.0.0.100")));
+        assert!(parse_network("2001:db8::/32")
+            .unwrap()
+            .contains(&ip("2001:db8::1")));
+        assert!(parse_network("not an ip").is_err());
+    }
+
+    #[test]
+    fn test_resolve_client_ip() {
+        let trusted = parse_networks(&["10.0.0.0/8".to_string()]);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("203.0.113.9, 198.51.100.7, 10.0.0.2"),
+        );
+
+        // Untrusted peers are the client, whatever they forward
+        assert_eq!(
+            resolve_client_ip(
+                &headers,
+                Some(ip("192.0.2.1")),
+                &trusted,
+                ForwardedHeader::XForwardedFor
+            ),
+            Some(ip("192.0.2.1"))
+        );
+
+        // Trusted proxies are skipped up to the first untrusted hop
+        assert_eq!(
+            resolve_client_ip(
+                &headers,
+                Some(ip("10.0.0.1")),
+                &trusted,
+                ForwardedHeader::XForwardedFor
+            ),
+            Some(ip("198.51.100.7"))
+        );
+
+        // Without the header, a trusted peer is the client
+        assert_eq!(
+            resolve_client_ip(
+                &HeaderMap::new(),
+                Some(ip("10.0.0.1")),
+                &trusted,
+                ForwardedHeader::XForwardedFor
+            ),
+            Some(ip("10.0.0.1"))
+        );
+        assert_eq!(
+            resolve_client_ip(&headers, None, &trusted, ForwardedHeader::XForwardedFor),
+            None
+        );
+    }
+
+    #[test]
+    fn test_resolve_client_ip_from_forwarded() {
+        let trusted = parse_networks(&["10.0.0.0/8".to_string(), "2001:db8::/32".to_string()]);
+        let resolve = |value: &'static str, peer: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("forwarded", HeaderValue::from_static(value));
+            // The other header is ignored
+            headers.insert("x-forwarded-for", HeaderValue::from_static("192.0.2.99"));
+            resolve_client_ip(
+                &headers,
+                Some(ip(peer)),
+                &trusted,
+                ForwardedHeader::Forwarded,
+            )
+        };
+
+        assert_eq!(
+            resolve(
+                r#"for=198.51.100.7:4711;proto=https, For="[2001:db8::17]:80""#,
+                "10.0.0.1"
+            ),
+            Some(ip("198.51.100.7"))
+        );
+        assert_eq!(
+            resolve(r#"for="[2001:db9:cafe::17]""#, "10.0.0.1"),
+            Some(ip("2001:db9:cafe::17"))
+        );
+
+        // Obfuscated identifiers stop the walk at the last trusted hop
+        assert_eq!(
+            resolve("for=198.51.100.7, for=_hidden, for=10.0.0.2", "10.0.0.1"),
+            Some(ip("10.0.0.2"))
+        );
+
+        // IPv4-mapped peers are matched as IPv4
+        assert_eq!(
+            resolve("for=198.51.100.7", "::ffff:10.0.0.1"),
+            Some(ip("198.51.100.7"))
+        );
+    }
+}
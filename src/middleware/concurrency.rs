@@ -0,0 +1,437 @@
+use axum::{
+    extract::Request,
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+use crate::config::{AdaptiveAlgorithm, AdaptiveConcurrencyConfig, ConcurrencyLimitConfig};
+use crate::middleware::metrics::{record_concurrency, record_concurrency_rejection};
+
+/// Weight of the newest sample in the gradient's baseline latency
+const BASELINE_SMOOTHING: f64 = 0.05;
+
+/// Weight of the newly computed limit in the gradient's limit
+const LIMIT_SMOOTHING: f64 = 0.2;
+
+/// Why a request was not given a slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// The limit was reached and the queue was full
+    QueueFull,
+    /// The request waited in the queue for longer than the queue timeout
+    QueueTimeout,
+}
+
+impl Rejection {
+    fn reason(self) -> &'static str {
+        match self {
+            Rejection::QueueFull => "queue_full",
+            Rejection::QueueTimeout => "queue_timeout",
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::QueueFull => write!(f, "concurrency limit reached"),
+            Rejection::QueueTimeout => write!(f, "timed out waiting for a concurrency slot"),
+        }
+    }
+}
+
+/// Adjusts the limit of an adaptive limiter to each completed request
+#[derive(Debug)]
+struct Adaptive {
+    config: AdaptiveConcurrencyConfig,
+    /// Gradient: smoothed latency of the backend when it is not overloaded
+    baseline: Option<f64>,
+}
+
+impl Adaptive {
+    /// The limit after a request that took `latency` and may have been
+    /// rejected as overloaded by the backend
+    fn update(&mut self, limit: f64, latency: Duration, overloaded: bool) -> f64 {
+        let latency = latency.as_secs_f64();
+
+        let limit = match self.config.algorithm {
+            AdaptiveAlgorithm::Aimd => {
+                let threshold = Duration::from_millis(self.config.latency_threshold_ms);
+                if overloaded || latency >= threshold.as_secs_f64() {
+                    limit * self.config.backoff_ratio
+                } else {
+                    // Increases by one per window of `limit` requests
+                    limit + 1.0 / limit.max(1.0)
+                }
+            }
+            AdaptiveAlgorithm::Gradient => {
+                let baseline = match self.baseline {
+                    Some(baseline) => {
+                        baseline * (1.0 - BASELINE_SMOOTHING) + latency * BASELINE_SMOOTHING
+                    }
+                    None => latency,
+                };
+                self.baseline = Some(baseline);
+
+                let gradient = if overloaded {
+                    0.5
+                } else if latency > 0.0 {
+                    (self.config.tolerance * baseline / latency).clamp(0.5, 1.0)
+                } else {
+                    1.0
+                };
+                // The square root of the limit leaves room for some queueing,
+                // so the limit can grow while latency is at its baseline
+                let target = limit * gradient + limit.sqrt();
+                limit * (1.0 - LIMIT_SMOOTHING) + target * LIMIT_SMOOTHING
+            }
+        };
+
+        limit.clamp(self.config.min_limit as f64, self.config.max_limit as f64)
+    }
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    in_flight: usize,
+    queued: usize,
+    limit: f64,
+    adaptive: Option<Adaptive>,
+}
+
+impl LimiterState {
+    fn has_capacity(&self) -> bool {
+        self.in_flight < (self.limit as usize).max(1)
+    }
+}
+
+/// Bulkhead limiting the requests in flight to a route or client, with a
+/// bounded wait queue and optionally a limit adapted to latency
+#[derive(Debug)]
+pub struct ConcurrencyLimiter {
+    /// What is limited, e.g. `route GET /users` or `client api`
+    scope: String,
+    state: Mutex<LimiterState>,
+    /// Wakes queued requests when a slot may have become free
+    released: Notify,
+    queue_size: usize,
+    queue_timeout: Duration,
+}
+
+impl ConcurrencyLimiter {
+    /// Create a limiter; `scope` labels its metrics
+    pub fn new(scope: impl Into<String>, config: &ConcurrencyLimitConfig) -> Arc<Self> {
+        let limiter = Arc::new(Self {
+            scope: scope.into(),
+            state: Mutex::new(LimiterState {
+                in_flight: 0,
+                queued: 0,
+                limit: config.limit as f64,
+                adaptive: config.adaptive.clone().map(|config| Adaptive {
+                    config,
+                    baseline: None,
+                }),
+            }),
+            released: Notify::new(),
+            queue_size: config.queue_size,
+            queue_timeout: Duration::from_millis(config.queue_timeout_ms),
+        });
+        limiter.publish(&limiter.lock());
+        limiter
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LimiterState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn publish(&self, state: &LimiterState) {
+        record_concurrency(&self.scope, state.in_flight, state.queued, state.limit);
+    }
+
+    /// Wait for a slot, for at most the queue timeout
+    pub async fn acquire(self: &Arc<Self>) -> Result<ConcurrencyPermit, Rejection> {
+        let deadline = tokio::time::Instant::now() + self.queue_timeout;
+        // Leaves the queue when dropped, including when the caller gives up waiting
+        let mut queued: Option<QueuedRequest> = None;
+
+        let result = loop {
+            // Register for wakeups before checking, so a release in between is not missed
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+
+            {
+                let mut state = self.lock();
+                if state.has_capacity() {
+                    if let Some(queued) = queued.take() {
+                        queued.leave(&mut state);
+                    }
+                    state.in_flight += 1;
+                    self.publish(&state);
+                    break Ok(ConcurrencyPermit {
+                        limiter: self.clone(),
+                        started: Instant::now(),
+                        completed: false,
+                    });
+                }
+                if queued.is_none() {
+                    if state.queued >= self.queue_size {
+                        break Err(Rejection::QueueFull);
+                    }
+                    state.queued += 1;
+                    queued = Some(QueuedRequest {
+                        limiter: self.clone(),
+                        left: false,
+                    });
+                    self.publish(&state);
+                }
+            }
+
+            if tokio::time::timeout_at(deadline, released).await.is_err() {
+                drop(queued.take());
+                break Err(Rejection::QueueTimeout);
+            }
+        };
+
+        if let Err(rejection) = result {
+            record_concurrency_rejection(&self.scope, rejection.reason());
+        }
+        result
+    }
+
+    fn release(&self, sample: Option<(Duration, bool)>) {
+        let mut guard = self.lock();
+        let state = &mut *guard;
+        state.in_flight -= 1;
+        if let (Some((latency, overloaded)), Some(adaptive)) = (sample, state.adaptive.as_mut()) {
+            state.limit = adaptive.update(state.limit, latency, overloaded);
+        }
+        self.publish(state);
+        drop(guard);
+
+        self.released.notify_waiters();
+    }
+}
+
+/// A request waiting in a limiter's queue, removed from it when dropped
+struct QueuedRequest {
+    limiter: Arc<ConcurrencyLimiter>,
+    left: bool,
+}
+
+impl QueuedRequest {
+    /// Leave the queue while already holding the limiter state
+    fn leave(mut self, state: &mut LimiterState) {
+        state.queued -= 1;
+        self.left = true;
+    }
+}
+
+impl Drop for QueuedRequest {
+    fn drop(&mut self) {
+        if !self.left {
+            let mut state = self.limiter.lock();
+            state.queued -= 1;
+            self.limiter.publish(&state);
+        }
+    }
+}
+
+/// A slot of a concurrency limiter, freed when dropped
+pub struct ConcurrencyPermit {
+    limiter: Arc<ConcurrencyLimiter>,
+    started: Instant,
+    completed: bool,
+}
+
+impl ConcurrencyPermit {
+    /// Free the slot, reporting whether the request found the backend
+    /// overloaded (failed or answered with a server error)
+    pub fn complete(mut self, overloaded: bool) {
+        self.completed = true;
+        self.limiter
+            .release(Some((self.started.elapsed(), overloaded)));
+    }
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        // Cancelled requests say nothing about the backend's latency
+        if !self.completed {
+            self.limiter.release(None);
+        }
+    }
+}
+
+/// Concurrency limiting middleware: requests without a slot are shed with 503
+pub async fn concurrency_middleware(
+    limiter: Arc<ConcurrencyLimiter>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Response> {
+    let permit = limiter.acquire().await.map_err(|rejection| {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [("retry-after", "1")],
+            Json(json!({"error": format!("Service overloaded: {}", rejection)})),
+        )
+            .into_response()
+    })?;
+
+    let response = next.run(request).await;
+    permit.complete(response.status().is_server_error());
+    Ok(response)
+}
+
+/// Create concurrency limiting middleware
+pub fn create_concurrency_middleware(
+    limiter: Arc<ConcurrencyLimiter>,
+) -> impl Fn(
+    Request,
+    Next,
+)
+    -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Response, Response>> + Send>>
+       + Clone {
+    move |request: Request, next: Next| {
+        let limiter = limiter.clone();
+        Box::pin(async move { concurrency_middleware(limiter, request, next).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(limit: usize, queue_size: usize) -> ConcurrencyLimitConfig {
+        ConcurrencyLimitConfig {
+            limit,
+            queue_size,
+            queue_timeout_ms: 50,
+            adaptive: None,
+        }
+    }
+
+    fn adaptive(algorithm: AdaptiveAlgorithm) -> Adaptive {
+        Adaptive {
+            config: AdaptiveConcurrencyConfig {
+                algorithm,
+                min_limit: 2,
+                max_limit: 20,
+                latency_threshold_ms: 100,
+                backoff_ratio: 0.5,
+                tolerance: 1.5,
+            },
+            baseline: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_limit_and_queue() {
+        let limiter = ConcurrencyLimiter::new("test", &config(1, 1));
+
+        let first = limiter.acquire().await.unwrap();
+
+        // The second request waits for the first one's slot
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire().await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // The queue is full
+        assert_eq!(limiter.acquire().await.err(), Some(Rejection::QueueFull));
+
+        first.complete(false);
+        assert_eq!(waiting.await.unwrap(), Ok(()));
+
+        // A queued request gives up after the queue timeout
+        let _held = limiter.acquire().await.unwrap();
+        assert_eq!(limiter.acquire().await.err(), Some(Rejection::QueueTimeout));
+        assert_eq!(limiter.state.lock().unwrap().queued, 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_wait_leaves_the_queue() {
+        let limiter = ConcurrencyLimiter::new(
+            "test",
+            &ConcurrencyLimitConfig {
+                queue_timeout_ms: 10_000,
+                ..config(1, 1)
+            },
+        );
+        let _held = limiter.acquire().await.unwrap();
+
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire().await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(limiter.state.lock().unwrap().queued, 1);
+
+        // A request dropped while queued, e.g. on disconnect, frees its place
+        waiting.abort();
+        let _ = waiting.await;
+        assert_eq!(limiter.state.lock().unwrap().queued, 0);
+    }
+
+    #[test]
+    fn test_aimd() {
+        let mut aimd = adaptive(AdaptiveAlgorithm::Aimd);
+
+        // Fast requests raise the limit by one per window of requests
+        let mut limit = 10.0;
+        for _ in 0..10 {
+            limit = aimd.update(limit, Duration::from_millis(10), false);
+        }
+        assert!((10.9..11.1).contains(&limit));
+
+        // Slow or failed requests cut it, down to the minimum
+        assert_eq!(aimd.update(10.0, Duration::from_millis(200), false), 5.0);
+        assert_eq!(aimd.update(10.0, Duration::from_millis(10), true), 5.0);
+        assert_eq!(aimd.update(3.0, Duration::from_millis(10), true), 2.0);
+        assert_eq!(aimd.update(20.0, Duration::from_millis(10), false), 20.0);
+    }
+
+    #[test]
+    fn test_gradient() {
+        let mut gradient = adaptive(AdaptiveAlgorithm::Gradient);
+
+        // At the baseline latency the limit grows
+        let limit = gradient.update(10.0, Duration::from_millis(10), false);
+        assert!(limit > 10.0);
+
+        // Far above the baseline it shrinks
+        let mut limit = 10.0;
+        for _ in 0..5 {
+            limit = gradient.update(limit, Duration::from_millis(100), false);
+        }
+        assert!(limit < 10.0);
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_limit_sheds_load() {
+        let limiter = ConcurrencyLimiter::new(
+            "test",
+            &ConcurrencyLimitConfig {
+                adaptive: Some(adaptive(AdaptiveAlgorithm::Aimd).config),
+                ..config(4, 0)
+            },
+        );
+
+        for _ in 0..2 {
+            limiter.acquire().await.unwrap().complete(true);
+        }
+        assert_eq!(limiter.state.lock().unwrap().limit, 2.0);
+
+        let _first = limiter.acquire().await.unwrap();
+        let _second = limiter.acquire().await.unwrap();
+        assert_eq!(limiter.acquire().await.err(), Some(Rejection::QueueFull));
+    }
+}
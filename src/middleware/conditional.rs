@@ -0,0 +1,332 @@
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use moka::future::Cache;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+use tracing::debug;
+
+/// Headers kept on a 304 response, as the full response would have sent them
+const NOT_MODIFIED_HEADERS: [HeaderName; 8] = [
+    header::AGE,
+    header::CACHE_CONTROL,
+    header::CONTENT_LOCATION,
+    header::DATE,
+    header::ETAG,
+    header::EXPIRES,
+    header::LAST_MODIFIED,
+    header::VARY,
+];
+
+/// Entity tags of the resources served through the gateway, by path and query,
+/// used to check `If-Match` preconditions of write requests. Versions are kept
+/// per replica: a resource this replica has not read, or that was written since,
+/// fails every precondition.
+#[derive(Debug, Clone)]
+pub struct EtagTracker {
+    /// The entity tag of the last successful GET of each resource
+    versions: Cache<String, String>,
+    /// Write locks of the resources being written
+    locks: Arc<Mutex<HashMap<String, Arc<AsyncMutex<()>>>>>,
+}
+
+impl Default for EtagTracker {
+    fn default() -> Self {
+        Self {
+            versions: Cache::builder()
+                .max_capacity(10_000)
+                .time_to_live(Duration::from_secs(3600))
+                .build(),
+            locks: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl EtagTracker {
+    /// Wait until no other write of a resource is running, so that its
+    /// precondition check and update are not interleaved with another's
+    async fn lock(&self, resource: &str) -> WriteLock {
+        let lock = self
+            .locks
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(resource.to_string())
+            .or_default()
+            .clone();
+
+        WriteLock {
+            locks: self.locks.clone(),
+            resource: resource.to_string(),
+            guard: Some(lock.lock_owned().await),
+        }
+    }
+}
+
+/// Holds the write lock of a resource until dropped
+struct WriteLock {
+    locks: Arc<Mutex<HashMap<String, Arc<AsyncMutex<()>>>>>,
+    resource: String,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for WriteLock {
+    fn drop(&mut self) {
+        let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+        self.guard.take();
+        // Waiters clone the lock while holding the map, so an unshared lock is unused
+        if locks
+            .get(&self.resource)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            locks.remove(&self.resource);
+        }
+    }
+}
+
+/// Generate a strong entity tag from a response body
+pub fn strong_etag(body: &[u8]) -> String {
+    let digest = Sha256::digest(body);
+    let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex)
+}
+
+/// Set a strong ETag computed from the body of a successful response. An
+/// ETag forwarded from one upstream is replaced, since it does not identify
+/// an aggregated or transformed body.
+pub async fn with_etag(response: Response) -> Response {
+    if !response.status().is_success() {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    if let Ok(etag) = HeaderValue::from_str(&strong_etag(&bytes)) {
+        parts.headers.insert(header::ETAG, etag);
+    }
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+/// Create conditional request middleware. With `check_if_match` unset,
+/// `If-Match` is left to the backend the request is forwarded to.
+pub fn create_conditional_middleware(
+    tracker: Arc<EtagTracker>,
+    check_if_match: bool,
+) -> impl Fn(Request, Next) -> std::pin::Pin<Box<dyn std::future::Future<Output = Response> + Send>>
+       + Clone {
+    move |request: Request, next: Next| {
+        let tracker = tracker.clone();
+        Box::pin(
+            async move { conditional_middleware(tracker, check_if_match, request, next).await },
+        )
+    }
+}
+
+/// Evaluate `If-Match` before write requests, and `If-None-Match` and
+/// `If-Modified-Since` against the response of GET and HEAD requests
+async fn conditional_middleware(
+    tracker: Arc<EtagTracker>,
+    check_if_match: bool,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let resource = request
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    let headers = request.headers().clone();
+    let safe = method == Method::GET || method == Method::HEAD;
+
+    if !safe {
+        if !check_if_match {
+            return next.run(request).await;
+        }
+
+        // Only conditional writes serialize, so that two writes matching the
+        // same tag cannot both succeed
+        let _lock = match header_str(&headers, header::IF_MATCH) {
+            Some(if_match) => {
+                let lock = tracker.lock(&resource).await;
+                let current = tracker.versions.get(&resource).await;
+                if !precondition_holds(if_match, current.as_deref()) {
+                    debug!("If-Match precondition failed for {} {}", method, resource);
+                    return (
+                        StatusCode::PRECONDITION_FAILED,
+                        Json(json!({
+                            "error": "Precondition failed: the resource has been modified",
+                        })),
+                    )
+                        .into_response();
+                }
+                Some(lock)
+            }
+            None => None,
+        };
+
+        // A write changes the resource, and the tag of its response is not the
+        // tag of the resource's representation, so it is read again first
+        let response = next.run(request).await;
+        if response.status().is_success() {
+            tracker.versions.invalidate(&resource).await;
+        }
+        return response;
+    }
+
+    let response = next.run(request).await;
+    if !response.status().is_success() {
+        return response;
+    }
+
+    let etag = header_str(response.headers(), header::ETAG).map(str::to_string);
+    if let Some(etag) = &etag {
+        tracker.versions.insert(resource, etag.clone()).await;
+    }
+
+    let not_modified = match header_str(&headers, header::IF_NONE_MATCH) {
+        Some(if_none_match) => etag
+            .as_deref()
+            .is_some_and(|etag| etag_matches(if_none_match, etag, false)),
+        None => header_str(&headers, header::IF_MODIFIED_SINCE).is_some_and(|since| {
+            header_str(response.headers(), header::LAST_MODIFIED)
+                .is_some_and(|modified| not_modified_since(modified, since))
+        }),
+    };
+
+    if not_modified {
+        return not_modified_response(response);
+    }
+    response
+}
+
+/// Whether an `If-Match` header holds for the current entity tag of a
+/// resource. Unknown versions fail, since the gateway cannot verify them.
+fn precondition_holds(if_match: &str, current: Option<&str>) -> bool {
+    current.is_some_and(|etag| etag_matches(if_match, etag, true))
+}
+
+/// Whether a list of entity tags matches a tag; strong comparison ignores weak tags
+fn etag_matches(list: &str, etag: &str, strong: bool) -> bool {
+    if list.trim() == "*" {
+        return true;
+    }
+
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    if strong && etag.starts_with("W/") {
+        return false;
+    }
+
+    list.split(',')
+        .map(str::trim)
+        .filter(|candidate| !(strong && candidate.starts_with("W/")))
+        .any(|candidate| opaque(candidate) == opaque(etag))
+}
+
+/// Whether a resource last modified at `modified` is unchanged since `since` (HTTP dates)
+fn not_modified_since(modified: &str, since: &str) -> bool {
+    let parse = |date: &str| chrono::DateTime::parse_from_rfc2822(date.trim()).ok();
+    match (parse(modified), parse(since)) {
+        (Some(modified), Some(since)) => modified <= since,
+        _ => false,
+    }
+}
+
+/// Turn a full response into a 304 that keeps its validators and caching headers
+fn not_modified_response(response: Response) -> Response {
+    let mut not_modified = Response::new(Body::empty());
+    *not_modified.status_mut() = StatusCode::NOT_MODIFIED;
+
+    for name in &NOT_MODIFIED_HEADERS {
+        for value in response.headers().get_all(name) {
+            not_modified
+                .headers_mut()
+                .append(name.clone(), value.clone());
+        }
+    }
+    if let Some(cache_status) = response.headers().get("x-cache") {
+        not_modified
+            .headers_mut()
+            .insert("x-cache", cache_status.clone());
+    }
+    not_modified
+}
+
+fn header_str(headers: &HeaderMap, name: HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_etag_matching() {
+        assert!(etag_matches("\"a\", \"b\"", "\"b\"", true));
+        assert!(etag_matches("W/\"a\"", "\"a\"", false));
+        assert!(!etag_matches("W/\"a\"", "\"a\"", true));
+        assert!(!etag_matches("\"a\"", "\"c\"", false));
+        assert!(etag_matches("*", "\"c\"", true));
+    }
+
+    #[test]
+    fn test_if_match_preconditions() {
+        assert!(precondition_holds("\"v1\"", Some("\"v1\"")));
+        assert!(!precondition_holds("\"v0\"", Some("\"v1\"")));
+        assert!(precondition_holds("*", Some("\"v1\"")));
+        // Unknown or modified resources fail closed
+        assert!(!precondition_holds("\"v1\"", None));
+        assert!(!precondition_holds("*", None));
+    }
+
+    #[test]
+    fn test_not_modified_since() {
+        let modified = "Wed, 21 Oct 2015 07:28:00 GMT";
+        assert!(not_modified_since(
+            modified,
+            "Wed, 21 Oct 2015 07:28:00 GMT"
+        ));
+        assert!(not_modified_since(
+            modified,
+            "Thu, 22 Oct 2015 07:28:00 GMT"
+        ));
+        assert!(!not_modified_since(
+            modified,
+            "Tue, 20 Oct 2015 07:28:00 GMT"
+        ));
+        assert!(!not_modified_since(modified, "yesterday"));
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_etag_is_replaced() {
+        let mut response = Response::new(Body::from("{\"id\":1}"));
+        response
+            .headers_mut()
+            .insert(header::ETAG, HeaderValue::from_static("\"upstream\""));
+
+        let response = with_etag(response).await;
+        assert_eq!(
+            response.headers()[header::ETAG],
+            strong_etag(b"{\"id\":1}").as_str()
+        );
+    }
+
+    #[test]
+    fn test_strong_etag() {
+        let etag = strong_etag(b"{\"id\":1}");
+        assert_eq!(etag, strong_etag(b"{\"id\":1}"));
+        assert_ne!(etag, strong_etag(b"{\"id\":2}"));
+        assert_eq!(etag.len(), 34);
+    }
+}
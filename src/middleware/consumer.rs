@@ -0,0 +1,194 @@
+use axum::http::HeaderMap;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::config::{ServerConfig, TierConfig};
+use crate::middleware::jwt::{create_jwt_validator, JwtValidator};
+
+/// The consumer sending a request, as far as its credentials tell
+#[derive(Debug, Clone, Default)]
+pub struct Consumer {
+    /// API key, from the header configured in `security.api_keys`; only set
+    /// when it is one of the configured keys or a tier's
+    pub api_key: Option<String>,
+    /// Claims of the bearer token, when it is valid
+    pub claims: Option<Value>,
+    /// Name of the first tier the consumer matches
+    pub tier: Option<String>,
+}
+
+impl Consumer {
+    /// A JWT claim as a string; non-string claims are rendered as JSON
+    pub fn claim(&self, name: &str) -> Option<String> {
+        match self.claims.as_ref()?.get(name)? {
+            Value::String(value) => Some(value.clone()),
+            Value::Null => None,
+            value => Some(value.to_string()),
+        }
+    }
+
+    /// Hash of the API key, to identify the consumer in stored keys without
+    /// the secret itself
+    pub fn api_key_hash(&self) -> Option<String> {
+        self.api_key.as_deref().map(hash_api_key)
+    }
+}
+
+/// First 16 hex digits of the SHA-256 of an API key
+pub fn hash_api_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .take(8)
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Identifies consumers and their tiers from request credentials
+#[derive(Debug, Clone)]
+pub struct ConsumerResolver {
+    api_key_header: String,
+    /// Keys of `security.api_keys` and of every tier; other keys are ignored,
+    /// so that clients cannot pick a fresh identity on each request
+    known_api_keys: HashSet<String>,
+    jwt: Option<Arc<JwtValidator>>,
+    tiers: Vec<TierConfig>,
+}
+
+impl ConsumerResolver {
+    /// Create a resolver from the server's security and tier configuration
+    pub fn new(server: &ServerConfig) -> Self {
+        Self {
+            api_key_header: server
+                .security
+                .api_keys
+                .as_ref()
+                .map(|api_keys| api_keys.header.clone())
+                .unwrap_or_else(|| "x-api-key".to_string()),
+            known_api_keys: server
+                .security
+                .api_keys
+                .iter()
+                .flat_map(|api_keys| api_keys.keys.iter())
+                .chain(server.tiers.iter().flat_map(|tier| tier.api_keys.iter()))
+                .cloned()
+                .collect(),
+            jwt: create_jwt_validator(server.security.jwt.as_ref()),
+            tiers: server.tiers.clone(),
+        }
+    }
+
+    /// Whether `key` is one of the configured API keys
+    pub fn is_known_api_key(&self, key: &str) -> bool {
+        self.known_api_keys.contains(key)
+    }
+
+    /// Identify the consumer of a request
+    pub fn resolve(&self, headers: &HeaderMap) -> Consumer {
+        let mut consumer = Consumer {
+            api_key: headers
+                .get(&self.api_key_header)
+                .and_then(|value| value.to_str().ok())
+                .filter(|value| self.known_api_keys.contains(*value))
+                .map(str::to_string),
+            claims: self.jwt.as_ref().and_then(|jwt| jwt.claims(headers)),
+            tier: None,
+        };
+
+        consumer.tier = self
+            .tiers
+            .iter()
+            .find(|tier| Self::matches(tier, &consumer))
+            .map(|tier| tier.name.clone());
+        consumer
+    }
+
+    fn matches(tier: &TierConfig, consumer: &Consumer) -> bool {
+        let by_api_key = consumer
+            .api_key
+            .as_ref()
+            .is_some_and(|api_key| tier.api_keys.contains(api_key));
+        let by_claims = !tier.jwt_claims.is_empty()
+            && tier
+                .jwt_claims
+                .iter()
+                .all(|(name, value)| consumer.claim(name).as_ref() == Some(value));
+
+        by_api_key || by_claims
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ApiKeyConfig, SecurityConfig};
+    use axum::http::HeaderValue;
+    use std::collections::HashMap;
+
+    fn tier(name: &str, api_keys: &[&str], jwt_claims: &[(&str, &str)]) -> TierConfig {
+        TierConfig {
+            name: name.to_string(),
+            api_keys: api_keys.iter().map(|key| key.to_string()).collect(),
+            jwt_claims: jwt_claims
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>(),
+        }
+    }
+
+    #[test]
+    fn test_tier_resolution() {
+        let resolver = ConsumerResolver::new(&ServerConfig {
+            security: SecurityConfig {
+                api_keys: Some(ApiKeyConfig {
+                    header: "x-key".to_string(),
+                    keys: vec!["basic".to_string()],
+                }),
+                ..Default::default()
+            },
+            tiers: vec![
+                tier("internal", &["svc"], &[]),
+                tier("pro", &["partner", "svc"], &[]),
+            ],
+            ..Default::default()
+        });
+
+        let consumer = |key: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-key", HeaderValue::from_static(key));
+            resolver.resolve(&headers)
+        };
+
+        // The first matching tier wins
+        assert_eq!(consumer("svc").tier.as_deref(), Some("internal"));
+        assert_eq!(consumer("partner").tier.as_deref(), Some("pro"));
+        assert_eq!(consumer("partner").api_key.as_deref(), Some("partner"));
+        assert_eq!(consumer("basic").api_key.as_deref(), Some("basic"));
+        assert_eq!(consumer("basic").tier, None);
+
+        // Unknown keys do not identify a consumer
+        assert_eq!(consumer("other").tier, None);
+        assert_eq!(consumer("other").api_key, None);
+        assert_eq!(resolver.resolve(&HeaderMap::new()).api_key, None);
+    }
+
+    #[test]
+    fn test_tier_matching_by_claims() {
+        let pro = tier("pro", &[], &[("plan", "pro"), ("level", "2")]);
+        let consumer = |claims: Value| Consumer {
+            claims: Some(claims),
+            ..Default::default()
+        };
+
+        assert!(ConsumerResolver::matches(
+            &pro,
+            &consumer(serde_json::json!({"plan": "pro", "level": 2}))
+        ));
+        assert!(!ConsumerResolver::matches(
+            &pro,
+            &consumer(serde_json::json!({"plan": "pro"}))
+        ));
+        assert!(!ConsumerResolver::matches(&pro, &Consumer::default()));
+    }
+}
@@ -1,99 +1,182 @@
-#![allow(dead_code)]
-#![allow(private_interfaces)]
-
-use axum::{body::Body, extract::Request, middleware::Next, response::Response};
-use moka::future::Cache;
-use std::sync::Arc;
+use crate::config::SecurityConfig;
+use crate::middleware::idempotency_store::IdempotencyStore;
+use axum::{
+    body::Body,
+    extract::Request,
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use http_body_util::LengthLimitError;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tracing::debug;
-
-/// Deduplication configuration
-#[derive(Debug, Clone)]
-pub struct DeduplicationConfig {
-    /// Time window for deduplication
-    pub ttl: Duration,
-    /// Maximum number of tracked requests
-    pub max_entries: u64,
-}
-
-impl Default for DeduplicationConfig {
-    fn default() -> Self {
-        Self {
-            ttl: Duration::from_secs(60),
-            max_entries: 10000,
-        }
-    }
-}
+use tokio::sync::watch;
+use tracing::{debug, warn};
 
 /// Request deduplication key
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub(crate) struct DeduplicationKey {
     pub method: String,
     pub path: String,
-    pub idempotency_key: Option<String>,
+    pub query: Option<String>,
+    /// SHA-256 of the request's credential headers, hex encoded, so that
+    /// responses are only replayed to the caller that produced them
+    pub credentials_hash: String,
+    pub idempotency_key: String,
+    /// SHA-256 of the request body, hex encoded
+    pub body_hash: String,
+}
+
+impl DeduplicationKey {
+    /// Key the response is recorded under. The body is not part of it, so
+    /// that reusing an idempotency key with a different payload can be detected.
+    fn storage_key(&self) -> String {
+        format!(
+            "{} {}?{} cred:{} key:{}",
+            self.method,
+            self.path,
+            self.query.as_deref().unwrap_or(""),
+            self.credentials_hash,
+            self.idempotency_key
+        )
+    }
 }
 
 /// Cached response for deduplicated requests
-#[derive(Debug, Clone)]
-pub(crate) struct CachedResult {
-    pub(crate) status: u16,
-    pub(crate) headers: Vec<(String, String)>,
-    pub(crate) body: bytes::Bytes,
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedResult {
+    /// Hash of the body of the request that produced the response
+    pub body_hash: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: bytes::Bytes,
+}
+
+/// Participation in the execution of a request
+enum Flight {
+    /// This request is executed; duplicates wait until the guard is dropped
+    Leader(FlightGuard),
+    /// A duplicate is being executed; resolves when it is done
+    Follower(watch::Receiver<()>),
+}
+
+/// Marks a request as in flight until dropped
+struct FlightGuard {
+    flights: Arc<Mutex<HashMap<String, watch::Receiver<()>>>>,
+    key: String,
+    _done: watch::Sender<()>,
+}
+
+impl Drop for FlightGuard {
+    fn drop(&mut self) {
+        // Waiters wake up when the sender is dropped, after the entry is removed
+        self.flights
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.key);
+    }
 }
 
 /// Request deduplication middleware
 pub struct RequestDeduplicator {
-    pub(crate) cache: Cache<DeduplicationKey, CachedResult>,
+    store: Arc<dyn IdempotencyStore>,
+    ttl: Duration,
+    /// Headers identifying the caller: `Authorization` and the API key header
+    credential_headers: Vec<String>,
+    flights: Arc<Mutex<HashMap<String, watch::Receiver<()>>>>,
 }
 
 impl RequestDeduplicator {
-    /// Create a new request deduplicator
-    pub fn new(config: DeduplicationConfig) -> Self {
-        let cache = Cache::builder()
-            .max_capacity(config.max_entries)
-            .time_to_live(config.ttl)
-            .build();
-
-        Self { cache }
+    /// Create a deduplicator recording responses in a store for `ttl`, scoped
+    /// to the credentials configured in `security`
+    pub fn new(store: Arc<dyn IdempotencyStore>, ttl: Duration, security: &SecurityConfig) -> Self {
+        let api_key_header = security
+            .api_keys
+            .as_ref()
+            .map(|api_keys| api_keys.header.to_lowercase())
+            .unwrap_or_else(|| "x-api-key".to_string());
+
+        Self {
+            store,
+            ttl,
+            credential_headers: vec!["authorization".to_string(), api_key_header],
+            flights: Arc::new(Mutex::new(HashMap::new())),
+        }
     }
 
-    /// Check if request should be deduplicated and get cached response
-    pub async fn check(&self, request: &Request) -> Option<CachedResult> {
-        let key = self.extract_key(request)?;
-        self.cache.get(&key).await
+    /// Get the response recorded for a request, if any
+    pub(crate) async fn check(&self, key: &DeduplicationKey) -> Option<CachedResult> {
+        match self.store.get(&key.storage_key()).await {
+            Ok(result) => result,
+            Err(e) => {
+                warn!("Failed to read idempotency record: {}", e);
+                None
+            }
+        }
     }
 
-    /// Store response for deduplication
-    pub async fn store(&self, request: &Request, result: CachedResult) {
-        if let Some(key) = self.extract_key(request) {
-            self.cache.insert(key, result).await;
+    /// Record the response of a request
+    pub(crate) async fn store(&self, key: &DeduplicationKey, result: CachedResult) {
+        if let Err(e) = self.store.put(&key.storage_key(), &result, self.ttl).await {
+            warn!("Failed to write idempotency record: {}", e);
         }
     }
 
-    /// Extract deduplication key from request
-    fn extract_key(&self, request: &Request) -> Option<DeduplicationKey> {
-        // Only deduplicate idempotent methods or requests with Idempotency-Key header
-        let method = request.method().to_string();
-        let is_idempotent = matches!(method.as_str(), "GET" | "HEAD" | "PUT" | "DELETE");
+    fn join_flight(&self, key: &DeduplicationKey) -> Flight {
+        let key = key.storage_key();
+        let mut flights = self.flights.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(done) = flights.get(&key) {
+            return Flight::Follower(done.clone());
+        }
+
+        let (done, receiver) = watch::channel(());
+        flights.insert(key.clone(), receiver);
+        Flight::Leader(FlightGuard {
+            flights: self.flights.clone(),
+            key,
+            _done: done,
+        })
+    }
 
+    /// Extract deduplication key from a request and its body. Only requests
+    /// with an `Idempotency-Key` header are deduplicated.
+    fn extract_key(&self, request: &Request, body: &[u8]) -> Option<DeduplicationKey> {
         let idempotency_key = request
             .headers()
             .get("idempotency-key")
             .and_then(|v| v.to_str().ok())
-            .map(|s| s.to_string());
-
-        if !is_idempotent && idempotency_key.is_none() {
-            return None;
+            .filter(|v| !v.is_empty())?
+            .to_string();
+
+        let mut credentials = Sha256::new();
+        for name in &self.credential_headers {
+            for value in request.headers().get_all(name.as_str()) {
+                credentials.update(name.as_bytes());
+                credentials.update(b":");
+                credentials.update(value.as_bytes());
+                credentials.update(b"\n");
+            }
         }
 
         Some(DeduplicationKey {
-            method: method.clone(),
+            method: request.method().to_string(),
             path: request.uri().path().to_string(),
+            query: request.uri().query().map(str::to_string),
+            credentials_hash: hex(&credentials.finalize()),
             idempotency_key,
+            body_hash: hex(&Sha256::digest(body)),
         })
     }
 }
 
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
 /// Create deduplication middleware
 pub fn create_deduplication_middleware(
     dedup: Arc<RequestDeduplicator>,
@@ -111,46 +194,53 @@ async fn deduplication_middleware(
     request: Request,
     next: Next,
 ) -> Response {
-    // Extract request info first
-    let request_method = request.method().to_string();
-    let request_path = request.uri().path().to_string();
-    let idempotency_key = request
-        .headers()
-        .get("idempotency-key")
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.to_string());
-
-    // Check if we should deduplicate
-    let is_idempotent = matches!(request_method.as_str(), "GET" | "HEAD" | "PUT" | "DELETE");
-    if !is_idempotent && idempotency_key.is_none() {
+    if !request.headers().contains_key("idempotency-key") {
         return next.run(request).await;
     }
 
-    let dedup_key = DeduplicationKey {
-        method: request_method.clone(),
-        path: request_path.clone(),
-        idempotency_key: idempotency_key.clone(),
+    // The body is compared to the recorded one, so it is buffered first
+    let (parts, body) = request.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) if exceeds_length_limit(&e) => {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(json!({ "error": "Request body too large" })),
+            )
+                .into_response();
+        }
+        Err(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "Failed to read request body" })),
+            )
+                .into_response();
+        }
     };
+    let request = Request::from_parts(parts, Body::from(body.clone()));
 
-    // Check if we have a cached response
-    if let Some(cached) = dedup.cache.get(&dedup_key).await {
-        debug!("Request deduplicated: {} {}", request_method, request_path);
+    let Some(dedup_key) = dedup.extract_key(&request, &body) else {
+        return next.run(request).await;
+    };
 
-        // Build response from cache
-        let mut response = Response::builder()
-            .status(cached.status)
-            .header("X-Deduplicated", "true");
+    if let Some(cached) = dedup.check(&dedup_key).await {
+        return replay(&dedup_key, cached);
+    }
 
-        for (name, value) in &cached.headers {
-            if let Ok(header_value) = value.parse::<axum::http::HeaderValue>() {
-                response = response.header(name, header_value);
+    // Duplicates arriving while the request runs wait for its response
+    let flight = loop {
+        match dedup.join_flight(&dedup_key) {
+            Flight::Leader(flight) => break flight,
+            Flight::Follower(mut done) => {
+                let _ = done.changed().await;
+                if let Some(cached) = dedup.check(&dedup_key).await {
+                    return replay(&dedup_key, cached);
+                }
+                // The running request failed and recorded nothing, so one of
+                // its waiters runs next while the others keep waiting
             }
         }
-
-        return response
-            .body(Body::from(cached.body.clone()))
-            .unwrap_or_else(|_| Response::new(Body::empty()));
-    }
+    };
 
     // Execute request
     let response = next.run(request).await;
@@ -168,10 +258,11 @@ async fn deduplication_middleware(
     let body_bytes = match axum::body::to_bytes(body, usize::MAX).await {
         Ok(bytes) => bytes,
         Err(_) => {
-            return Response::builder()
-                .status(500)
-                .body(Body::from("Failed to process response"))
-                .unwrap();
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "error": "Failed to process response" })),
+            )
+                .into_response();
         }
     };
 
@@ -183,12 +274,14 @@ async fn deduplication_middleware(
         .collect();
 
     let cached_result = CachedResult {
+        body_hash: dedup_key.body_hash.clone(),
         status: parts.status.as_u16(),
         headers: headers.clone(),
         body: body_bytes.clone(),
     };
 
-    dedup.cache.insert(dedup_key, cached_result).await;
+    dedup.store(&dedup_key, cached_result).await;
+    drop(flight);
 
     // Build response with body
     let mut response = Response::builder().status(parts.status);
@@ -204,80 +297,295 @@ async fn deduplication_middleware(
         .unwrap_or_else(|_| Response::new(Body::empty()))
 }
 
+/// Whether reading a body failed because it exceeds the server's body limit
+fn exceeds_length_limit(error: &axum::Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error);
+    while let Some(error) = source {
+        if error.is::<LengthLimitError>() {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+/// Answer a duplicate with the recorded response, or with 422 when its
+/// idempotency key was first used with a different payload
+fn replay(dedup_key: &DeduplicationKey, cached: CachedResult) -> Response {
+    if cached.body_hash != dedup_key.body_hash {
+        debug!(
+            "Idempotency key reused with a different payload: {} {}",
+            dedup_key.method, dedup_key.path
+        );
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({
+                "error": "Idempotency key was already used with a different request payload",
+            })),
+        )
+            .into_response();
+    }
+
+    debug!(
+        "Request deduplicated: {} {}",
+        dedup_key.method, dedup_key.path
+    );
+
+    // Build response from cache
+    let mut response = Response::builder()
+        .status(cached.status)
+        .header("X-Deduplicated", "true");
+
+    for (name, value) in &cached.headers {
+        if let Ok(header_value) = value.parse::<axum::http::HeaderValue>() {
+            response = response.header(name, header_value);
+        }
+    }
+
+    response
+        .body(Body::from(cached.body))
+        .unwrap_or_else(|_| Response::new(Body::empty()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::middleware::idempotency_store::MemoryStore;
     use axum::http::Method;
+    use axum::routing::post;
+    use axum::Router;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+
+    fn deduplicator() -> RequestDeduplicator {
+        let ttl = Duration::from_secs(60);
+        RequestDeduplicator::new(
+            Arc::new(MemoryStore::new(10000, ttl)),
+            ttl,
+            &SecurityConfig::default(),
+        )
+    }
+
+    fn request(method: Method, uri: &str, headers: &[(&str, &str)]) -> Request {
+        let mut builder = Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
 
     #[tokio::test]
     async fn test_deduplicator_creation() {
-        let config = DeduplicationConfig::default();
-        let dedup = RequestDeduplicator::new(config);
-
-        let request = Request::builder()
-            .method(Method::GET)
-            .uri("/test")
-            .body(Body::empty())
+        let dedup = deduplicator();
+        let key = dedup
+            .extract_key(
+                &request(Method::GET, "/test", &[("idempotency-key", "k")]),
+                b"",
+            )
             .unwrap();
 
-        assert!(dedup.check(&request).await.is_none());
+        assert!(dedup.check(&key).await.is_none());
     }
 
     #[tokio::test]
     async fn test_deduplication_key_extraction() {
-        let config = DeduplicationConfig::default();
-        let dedup = RequestDeduplicator::new(config);
-
-        // GET request should have a key
-        let get_request = Request::builder()
-            .method(Method::GET)
-            .uri("/test")
-            .body(Body::empty())
-            .unwrap();
-
-        assert!(dedup.extract_key(&get_request).is_some());
-
-        // POST without idempotency key should not have a key
-        let post_request = Request::builder()
-            .method(Method::POST)
-            .uri("/test")
-            .body(Body::empty())
-            .unwrap();
+        let dedup = deduplicator();
+
+        // Requests without an idempotency key are never deduplicated
+        assert!(dedup
+            .extract_key(&request(Method::GET, "/test", &[]), b"")
+            .is_none());
+        assert!(dedup
+            .extract_key(&request(Method::PUT, "/test", &[]), b"")
+            .is_none());
+
+        // Any method with an idempotency key has a key
+        assert!(dedup
+            .extract_key(
+                &request(Method::POST, "/test", &[("idempotency-key", "abc123")]),
+                b""
+            )
+            .is_some());
+    }
 
-        assert!(dedup.extract_key(&post_request).is_none());
+    #[tokio::test]
+    async fn test_body_is_compared_not_keyed() {
+        let dedup = deduplicator();
+        let post = request(Method::POST, "/test", &[("idempotency-key", "abc123")]);
+        let first = dedup.extract_key(&post, b"{\"name\":\"a\"}").unwrap();
+        let second = dedup.extract_key(&post, b"{\"name\":\"b\"}").unwrap();
+        assert_eq!(first.storage_key(), second.storage_key());
+        assert_ne!(first.body_hash, second.body_hash);
+    }
 
-        // POST with idempotency key should have a key
-        let post_with_key = Request::builder()
-            .method(Method::POST)
-            .uri("/test")
-            .header("idempotency-key", "abc123")
-            .body(Body::empty())
-            .unwrap();
+    #[tokio::test]
+    async fn test_key_is_scoped_to_query_and_credentials() {
+        let dedup = deduplicator();
+        let key = |uri: &str, headers: &[(&str, &str)]| {
+            let mut headers = headers.to_vec();
+            headers.push(("idempotency-key", "k1"));
+            dedup
+                .extract_key(&request(Method::GET, uri, &headers), b"")
+                .unwrap()
+                .storage_key()
+        };
 
-        assert!(dedup.extract_key(&post_with_key).is_some());
+        assert_ne!(key("/search?q=a", &[]), key("/search?q=b", &[]));
+        assert_ne!(
+            key("/orders", &[("authorization", "Bearer a")]),
+            key("/orders", &[("authorization", "Bearer b")])
+        );
+        assert_ne!(key("/orders", &[("x-api-key", "a")]), key("/orders", &[]));
+        assert_eq!(
+            key("/orders", &[("x-api-key", "a")]),
+            key("/orders", &[("x-api-key", "a")])
+        );
     }
 
     #[tokio::test]
     async fn test_store_and_retrieve() {
-        let config = DeduplicationConfig::default();
-        let dedup = RequestDeduplicator::new(config);
-
-        let request = Request::builder()
-            .method(Method::GET)
-            .uri("/test")
-            .body(Body::empty())
+        let dedup = deduplicator();
+        let key = dedup
+            .extract_key(
+                &request(Method::GET, "/test", &[("idempotency-key", "k")]),
+                b"",
+            )
             .unwrap();
 
         let result = CachedResult {
+            body_hash: key.body_hash.clone(),
             status: 200,
             headers: vec![],
             body: bytes::Bytes::from("test response"),
         };
 
-        dedup.store(&request, result).await;
+        dedup.store(&key, result).await;
 
-        let cached = dedup.check(&request).await;
+        let cached = dedup.check(&key).await;
         assert!(cached.is_some());
         assert_eq!(cached.unwrap().status, 200);
     }
+
+    fn counting_app(calls: Arc<AtomicUsize>) -> Router {
+        Router::new()
+            .route(
+                "/orders",
+                post(move |body: String| {
+                    let calls = calls.clone();
+                    async move {
+                        let call = calls.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        (StatusCode::CREATED, format!("{} {}", call, body))
+                    }
+                }),
+            )
+            .layer(axum::middleware::from_fn(create_deduplication_middleware(
+                Arc::new(deduplicator()),
+            )))
+    }
+
+    async fn send(app: Router, idempotency_key: &str, body: &'static str) -> (StatusCode, String) {
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/orders")
+                    .header("idempotency-key", idempotency_key)
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_duplicates_wait_for_the_first() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = counting_app(calls.clone());
+
+        let (first, second) = tokio::join!(
+            send(app.clone(), "k1", "order"),
+            send(app.clone(), "k1", "order")
+        );
+        assert_eq!(first, (StatusCode::CREATED, "0 order".to_string()));
+        assert_eq!(second, first);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reused_idempotency_key_with_different_payload() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = counting_app(calls.clone());
+
+        assert_eq!(
+            send(app.clone(), "k1", "order").await.0,
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            send(app.clone(), "k1", "other").await.0,
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(send(app, "k2", "other").await.0, StatusCode::CREATED);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_duplicates_of_a_failed_request_run_once() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route(
+                "/orders",
+                post({
+                    let calls = calls.clone();
+                    move || async move {
+                        let call = calls.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        if call == 0 {
+                            StatusCode::INTERNAL_SERVER_ERROR
+                        } else {
+                            StatusCode::CREATED
+                        }
+                    }
+                }),
+            )
+            .layer(axum::middleware::from_fn(create_deduplication_middleware(
+                Arc::new(deduplicator()),
+            )));
+
+        let (first, second, third) = tokio::join!(
+            send(app.clone(), "k1", "order"),
+            send(app.clone(), "k1", "order"),
+            send(app.clone(), "k1", "order")
+        );
+        let mut statuses = [first.0, second.0, third.0];
+        statuses.sort();
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::CREATED,
+                StatusCode::CREATED,
+                StatusCode::INTERNAL_SERVER_ERROR
+            ]
+        );
+        // The waiters of the failed request do not all run it again
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_body_over_the_limit() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app =
+            counting_app(calls.clone()).layer(tower_http::limit::RequestBodyLimitLayer::new(4));
+
+        assert_eq!(
+            send(app, "k1", "a large order").await.0,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
 }
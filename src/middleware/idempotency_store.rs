@@ -0,0 +1,308 @@
+use crate::config::{
+    DeduplicationConfig, IdempotencyStoreConfig, RedisIdempotencyStoreConfig,
+    SqliteIdempotencyStoreConfig,
+};
+use crate::middleware::deduplication::CachedResult;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use moka::future::Cache;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, Client};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+use sqlx::Row;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// Longest wait for Redis before a request is executed as if it had no record
+const REDIS_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Storage of the responses recorded for deduplicated requests.
+///
+/// Errors are reported to the caller, which treats a failing store as a miss.
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync + std::fmt::Debug {
+    /// Get the record stored under a key
+    async fn get(&self, key: &str) -> Result<Option<CachedResult>>;
+
+    /// Store a record for the given time to live
+    async fn put(&self, key: &str, record: &CachedResult, ttl: Duration) -> Result<()>;
+}
+
+/// Create the idempotency store for a deduplication configuration
+pub async fn create_idempotency_store(
+    config: &DeduplicationConfig,
+) -> Result<Arc<dyn IdempotencyStore>> {
+    Ok(match &config.store {
+        IdempotencyStoreConfig::Memory => Arc::new(MemoryStore::new(
+            config.max_entries,
+            Duration::from_secs(config.ttl_seconds),
+        )),
+        IdempotencyStoreConfig::Redis(redis_config) => {
+            Arc::new(RedisStore::new(redis_config.clone()).await?)
+        }
+        IdempotencyStoreConfig::Sqlite(sqlite_config) => {
+            Arc::new(SqliteStore::new(sqlite_config).await?)
+        }
+    })
+}
+
+/// In-process store using moka; records are lost on restart
+#[derive(Debug)]
+pub struct MemoryStore {
+    cache: Cache<String, CachedResult>,
+}
+
+impl MemoryStore {
+    /// Create a store holding at most `max_capacity` records for `ttl`
+    pub fn new(max_capacity: u64, ttl: Duration) -> Self {
+        let cache = Cache::builder()
+            .max_capacity(max_capacity)
+            .time_to_live(ttl)
+            .build();
+
+        Self { cache }
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for MemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<CachedResult>> {
+        Ok(self.cache.get(key).await)
+    }
+
+    async fn put(&self, key: &str, record: &CachedResult, _ttl: Duration) -> Result<()> {
+        self.cache.insert(key.to_string(), record.clone()).await;
+        Ok(())
+    }
+}
+
+/// Store persisting records in Redis, shared by all gateway replicas
+pub struct RedisStore {
+    manager: ConnectionManager,
+    key_prefix: String,
+}
+
+impl std::fmt::Debug for RedisStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisStore")
+            .field("key_prefix", &self.key_prefix)
+            .finish()
+    }
+}
+
+impl RedisStore {
+    /// Connect to Redis
+    pub async fn new(config: RedisIdempotencyStoreConfig) -> Result<Self> {
+        info!("Creating Redis idempotency store");
+
+        let client = Client::open(config.connection_string.as_str())?;
+        let manager = ConnectionManager::new(client).await?;
+
+        Ok(Self {
+            manager,
+            key_prefix: config.key_prefix,
+        })
+    }
+
+    async fn read_record(&self, key: &str) -> Result<Option<CachedResult>> {
+        let mut conn = self.manager.clone();
+        let value: Option<Vec<u8>> = conn.get(format!("{}{}", self.key_prefix, key)).await?;
+        value.map(|bytes| decode_record(&bytes)).transpose()
+    }
+
+    async fn write_record(&self, key: &str, record: &CachedResult, ttl: Duration) -> Result<()> {
+        let mut conn = self.manager.clone();
+        let _: () = conn
+            .pset_ex(
+                format!("{}{}", self.key_prefix, key),
+                encode_record(record)?,
+                ttl.as_millis() as u64,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for RedisStore {
+    async fn get(&self, key: &str) -> Result<Option<CachedResult>> {
+        tokio::time::timeout(REDIS_TIMEOUT, self.read_record(key))
+            .await
+            .context("Redis idempotency record read timed out")?
+    }
+
+    async fn put(&self, key: &str, record: &CachedResult, ttl: Duration) -> Result<()> {
+        tokio::time::timeout(REDIS_TIMEOUT, self.write_record(key, record, ttl))
+            .await
+            .context("Redis idempotency record write timed out")?
+    }
+}
+
+/// Store persisting records in a local SQLite database
+#[derive(Debug)]
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    /// Open the database, creating it and its table if needed
+    pub async fn new(config: &SqliteIdempotencyStoreConfig) -> Result<Self> {
+        info!(
+            "Creating SQLite idempotency store at {}",
+            config.database_path
+        );
+
+        let options =
+            SqliteConnectOptions::from_str(&config.database_path)?.create_if_missing(true);
+        let pool = SqlitePool::connect_with(options).await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS idempotency_records (
+                key TEXT PRIMARY KEY,
+                record BLOB NOT NULL,
+                expires_at INTEGER NOT NULL
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for SqliteStore {
+    async fn get(&self, key: &str) -> Result<Option<CachedResult>> {
+        let row =
+            sqlx::query("SELECT record FROM idempotency_records WHERE key = ? AND expires_at > ?")
+                .bind(key)
+                .bind(unix_millis(SystemTime::now()))
+                .fetch_optional(&self.pool)
+                .await?;
+
+        row.map(|row| decode_record(&row.get::<Vec<u8>, _>("record")))
+            .transpose()
+    }
+
+    async fn put(&self, key: &str, record: &CachedResult, ttl: Duration) -> Result<()> {
+        let now = SystemTime::now();
+
+        // Expired records are only ever skipped by reads, so prune them on write
+        sqlx::query("DELETE FROM idempotency_records WHERE expires_at <= ?")
+            .bind(unix_millis(now))
+            .execute(&self.pool)
+            .await?;
+
+        sqlx::query(
+            "INSERT OR REPLACE INTO idempotency_records (key, record, expires_at) VALUES (?, ?, ?)",
+        )
+        .bind(key)
+        .bind(encode_record(record)?)
+        .bind(unix_millis(now + ttl))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+fn unix_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// Record fields other than the body, serialized as JSON
+#[derive(Serialize, Deserialize)]
+struct RecordMeta {
+    body_hash: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+}
+
+/// Encode a record as the metadata length (u32, big endian), the JSON
+/// metadata and the raw body
+fn encode_record(record: &CachedResult) -> Result<Vec<u8>> {
+    let meta = serde_json::to_vec(&RecordMeta {
+        body_hash: record.body_hash.clone(),
+        status: record.status,
+        headers: record.headers.clone(),
+    })?;
+
+    let mut bytes = Vec::with_capacity(4 + meta.len() + record.body.len());
+    bytes.extend_from_slice(&(meta.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&meta);
+    bytes.extend_from_slice(&record.body);
+    Ok(bytes)
+}
+
+/// Decode a record written by `encode_record`
+fn decode_record(bytes: &[u8]) -> Result<CachedResult> {
+    let length: [u8; 4] = bytes
+        .get(..4)
+        .and_then(|length| length.try_into().ok())
+        .context("Truncated idempotency record")?;
+    let length = u32::from_be_bytes(length) as usize;
+    let meta = bytes
+        .get(4..4 + length)
+        .context("Truncated idempotency record")?;
+    let meta: RecordMeta = serde_json::from_slice(meta)?;
+
+    Ok(CachedResult {
+        body_hash: meta.body_hash,
+        status: meta.status,
+        headers: meta.headers,
+        body: bytes::Bytes::copy_from_slice(&bytes[4 + length..]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> CachedResult {
+        CachedResult {
+            body_hash: "abc".to_string(),
+            status: 201,
+            headers: vec![("content-type".to_string(), "application/json".to_string())],
+            body: bytes::Bytes::from_static(b"{\"id\":1}"),
+        }
+    }
+
+    #[test]
+    fn test_record_encoding_roundtrip() {
+        let bytes = encode_record(&record()).unwrap();
+        assert_eq!(decode_record(&bytes).unwrap(), record());
+        assert!(decode_record(&bytes[..6]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store_survives_reopening() {
+        let path = std::env::temp_dir().join(format!("idempotency-{}.db", rand::random::<u64>()));
+        let config = SqliteIdempotencyStoreConfig {
+            database_path: format!("sqlite://{}", path.display()),
+        };
+
+        let store = SqliteStore::new(&config).await.unwrap();
+        store
+            .put("POST /orders key:1", &record(), Duration::from_secs(60))
+            .await
+            .unwrap();
+        store
+            .put("POST /orders key:2", &record(), Duration::ZERO)
+            .await
+            .unwrap();
+        drop(store);
+
+        let store = SqliteStore::new(&config).await.unwrap();
+        assert_eq!(
+            store.get("POST /orders key:1").await.unwrap(),
+            Some(record())
+        );
+        assert_eq!(store.get("POST /orders key:2").await.unwrap(), None);
+
+        let _ = std::fs::remove_file(path);
+    }
+}
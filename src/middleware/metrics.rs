@@ -1,5 +1,5 @@
 use axum::{extract::Request, middleware::Next, response::Response};
-use metrics::{counter, describe_counter, describe_histogram, histogram};
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram};
 use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
 use std::sync::OnceLock;
 use std::time::Instant;
@@ -35,6 +35,40 @@ pub fn init_metrics() -> PrometheusHandle {
         "http_request_duration_seconds",
         "HTTP request duration in seconds"
     );
+    describe_counter!(
+        "rate_limit_decisions_total",
+        "Rate limiter decisions by scope, decision and store"
+    );
+    describe_counter!(
+        "rate_limit_fallbacks_total",
+        "Rate limit checks that fell back to local state because Redis was unreachable"
+    );
+    describe_gauge!(
+        "concurrency_in_flight",
+        "Requests holding a concurrency slot, by scope"
+    );
+    describe_gauge!(
+        "concurrency_queued",
+        "Requests waiting for a concurrency slot, by scope"
+    );
+    describe_gauge!("concurrency_limit", "Current concurrency limit, by scope");
+    describe_counter!(
+        "concurrency_rejections_total",
+        "Requests shed by a concurrency limit, by scope and reason"
+    );
+    describe_counter!("quota_decisions_total", "Quota decisions by decision");
+    describe_counter!(
+        "quota_store_errors_total",
+        "Quota checks skipped because the usage store failed"
+    );
+    describe_counter!(
+        "subrequest_cache_hits_total",
+        "Subrequest results served from cache, by client"
+    );
+    describe_counter!(
+        "subrequest_cache_misses_total",
+        "Cached subrequests that had to be executed, by client"
+    );
 
     PROMETHEUS_HANDLE.set(handle.clone()).ok();
     handle
@@ -45,6 +79,23 @@ pub fn get_metrics_handle() -> Option<&'static PrometheusHandle> {
     PROMETHEUS_HANDLE.get()
 }
 
+/// Record the state of a concurrency limiter
+pub fn record_concurrency(scope: &str, in_flight: usize, queued: usize, limit: f64) {
+    gauge!("concurrency_in_flight", "scope" => scope.to_string()).set(in_flight as f64);
+    gauge!("concurrency_queued", "scope" => scope.to_string()).set(queued as f64);
+    gauge!("concurrency_limit", "scope" => scope.to_string()).set(limit.floor());
+}
+
+/// Count a request shed by a concurrency limiter
+pub fn record_concurrency_rejection(scope: &str, reason: &'static str) {
+    counter!(
+        "concurrency_rejections_total",
+        "scope" => scope.to_string(),
+        "reason" => reason
+    )
+    .increment(1);
+}
+
 /// Middleware to collect metrics for requests
 pub async fn metrics_middleware(request: Request, next: Next) -> Response {
     let start = Instant::now();
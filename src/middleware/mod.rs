@@ -1,9 +1,19 @@
 pub mod cache;
+pub mod cache_store;
 pub mod circuit_breaker;
+pub mod client_ip;
+pub mod concurrency;
+pub mod conditional;
+pub mod consumer;
 pub mod deduplication;
+pub mod idempotency_store;
+pub mod jwt;
 pub mod logging;
 pub mod metrics;
+pub mod quota;
+pub mod quota_store;
 pub mod rate_limit;
+pub mod rate_limit_store;
 pub mod request_id;
 pub mod security;
 pub mod tracing;
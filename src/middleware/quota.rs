@@ -0,0 +1,477 @@
+use anyhow::Result;
+use axum::{
+    extract::Request,
+    http::{HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::config::{QuotaConfig, QuotaKeyConfig, QuotaLimits, QuotaResetConfig, ServerConfig};
+use crate::middleware::consumer::{hash_api_key, Consumer, ConsumerResolver};
+use crate::middleware::quota_store::{QuotaCounter, QuotaStore};
+
+/// A period quotas are enforced over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaPeriod {
+    Daily,
+    Monthly,
+}
+
+impl QuotaPeriod {
+    pub const ALL: [QuotaPeriod; 2] = [QuotaPeriod::Daily, QuotaPeriod::Monthly];
+
+    fn name(self) -> &'static str {
+        match self {
+            QuotaPeriod::Daily => "daily",
+            QuotaPeriod::Monthly => "monthly",
+        }
+    }
+
+    fn limit(self, limits: &QuotaLimits) -> Option<u64> {
+        match self {
+            QuotaPeriod::Daily => limits.daily,
+            QuotaPeriod::Monthly => limits.monthly,
+        }
+    }
+
+    /// Start and end of the period containing `now`
+    pub fn window(
+        self,
+        reset: &QuotaResetConfig,
+        now: DateTime<Utc>,
+    ) -> (DateTime<Utc>, DateTime<Utc>) {
+        match self {
+            QuotaPeriod::Daily => {
+                let start = period_start(now.year(), now.month(), now.day(), reset.hour);
+                let start = if start > now {
+                    start - chrono::Duration::days(1)
+                } else {
+                    start
+                };
+                (start, start + chrono::Duration::days(1))
+            }
+            QuotaPeriod::Monthly => {
+                let day = reset.day_of_month;
+                let (mut year, mut month) = (now.year(), now.month());
+                if period_start(year, month, day, reset.hour) > now {
+                    (year, month) = if month == 1 {
+                        (year - 1, 12)
+                    } else {
+                        (year, month - 1)
+                    };
+                }
+                let (next_year, next_month) = if month == 12 {
+                    (year + 1, 1)
+                } else {
+                    (year, month + 1)
+                };
+                (
+                    period_start(year, month, day, reset.hour),
+                    period_start(next_year, next_month, day, reset.hour),
+                )
+            }
+        }
+    }
+}
+
+/// Time a period starts at. The day is at most 28, so it exists in every
+/// month (config validation rejects anything else).
+fn period_start(year: i32, month: u32, day: u32, hour: u32) -> DateTime<Utc> {
+    NaiveDate::from_ymd_opt(year, month, day.clamp(1, 28))
+        .and_then(|date| date.and_hms_opt(hour.min(23), 0, 0))
+        .unwrap()
+        .and_utc()
+}
+
+/// Usage of a consumer's quota over the current period
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct QuotaUsage {
+    pub period: QuotaPeriod,
+    /// Calls allowed in the period, if limited
+    pub limit: Option<u64>,
+    pub used: u64,
+    pub remaining: Option<u64>,
+    pub resets_at: DateTime<Utc>,
+}
+
+/// Outcome of counting a call, reported for the quota closest to being used up
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuotaDecision {
+    pub allowed: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    pub resets_at: DateTime<Utc>,
+}
+
+/// Enforces daily and monthly quotas per consumer
+#[derive(Debug)]
+pub struct QuotaManager {
+    key: QuotaKeyConfig,
+    consumers: ConsumerResolver,
+    /// Quotas of consumers without a tier of their own
+    default: QuotaLimits,
+    tiers: HashMap<String, QuotaLimits>,
+    reset: QuotaResetConfig,
+    store: Arc<dyn QuotaStore>,
+}
+
+impl QuotaManager {
+    /// Create a manager counting usage in `store`
+    pub fn new(config: &QuotaConfig, server: &ServerConfig, store: Arc<dyn QuotaStore>) -> Self {
+        Self {
+            key: config.key,
+            consumers: ConsumerResolver::new(server),
+            default: config.limits.clone(),
+            tiers: config.tiers.clone(),
+            reset: config.reset.clone(),
+            store,
+        }
+    }
+
+    /// Identifier of the consumer quotas are counted by, e.g. `api_key:` and
+    /// the hash of the API key
+    pub fn consumer_id(&self, consumer: &Consumer) -> Option<String> {
+        match self.key {
+            QuotaKeyConfig::ApiKey => consumer
+                .api_key_hash()
+                .map(|hash| format!("api_key:{}", hash)),
+            QuotaKeyConfig::JwtSubject => consumer.claim("sub").map(|sub| format!("sub:{}", sub)),
+        }
+    }
+
+    /// Identifier of a consumer named by an operator, who may give a
+    /// configured API key in place of its hash
+    pub fn resolve_consumer_id(&self, consumer: &str) -> String {
+        match consumer.strip_prefix("api_key:") {
+            Some(key) if self.consumers.is_known_api_key(key) => {
+                format!("api_key:{}", hash_api_key(key))
+            }
+            _ => consumer.to_string(),
+        }
+    }
+
+    fn limits_for(&self, tier: Option<&str>) -> &QuotaLimits {
+        tier.and_then(|tier| self.tiers.get(tier))
+            .unwrap_or(&self.default)
+    }
+
+    /// Key of a consumer's usage counter for the period starting at `start`
+    fn counter_key(consumer_id: &str, period: QuotaPeriod, start: DateTime<Utc>) -> String {
+        format!(
+            "{}:{}:{}",
+            consumer_id,
+            period.name(),
+            start.format("%Y-%m-%d")
+        )
+    }
+
+    /// Count a call against the consumer's quotas. Returns `None` when its
+    /// tier has no quota.
+    pub async fn consume(
+        &self,
+        consumer_id: &str,
+        tier: Option<&str>,
+    ) -> Result<Option<QuotaDecision>> {
+        let now = Utc::now();
+        let limits = self.limits_for(tier);
+        let periods: Vec<(u64, DateTime<Utc>, QuotaCounter)> = QuotaPeriod::ALL
+            .into_iter()
+            .filter_map(|period| {
+                let limit = period.limit(limits)?;
+                let (start, end) = period.window(&self.reset, now);
+                let counter = QuotaCounter {
+                    key: Self::counter_key(consumer_id, period, start),
+                    limit,
+                    expires_at: end.timestamp_millis(),
+                };
+                Some((limit, end, counter))
+            })
+            .collect();
+        if periods.is_empty() {
+            return Ok(None);
+        }
+
+        let counters: Vec<QuotaCounter> = periods
+            .iter()
+            .map(|(_, _, counter)| counter.clone())
+            .collect();
+        let consumption = self.store.consume(&counters).await?;
+
+        // Report the quota with the fewest calls left; of used up quotas, the
+        // one that resets last
+        let decision = periods
+            .iter()
+            .zip(&consumption.used)
+            .map(|((limit, end, _), used)| QuotaDecision {
+                allowed: consumption.allowed,
+                limit: *limit,
+                remaining: limit.saturating_sub(*used),
+                resets_at: *end,
+            })
+            .min_by_key(|decision| (decision.remaining, std::cmp::Reverse(decision.resets_at)));
+        Ok(decision)
+    }
+
+    /// Usage of every period, with the limits of the given tier
+    pub async fn usage(&self, consumer_id: &str, tier: Option<&str>) -> Result<Vec<QuotaUsage>> {
+        let now = Utc::now();
+        let limits = self.limits_for(tier);
+        let windows: Vec<_> = QuotaPeriod::ALL
+            .into_iter()
+            .map(|period| (period, period.window(&self.reset, now)))
+            .collect();
+        let keys: Vec<String> = windows
+            .iter()
+            .map(|(period, (start, _))| Self::counter_key(consumer_id, *period, *start))
+            .collect();
+        let used = self.store.usage(&keys).await?;
+
+        Ok(windows
+            .into_iter()
+            .zip(used)
+            .map(|((period, (_, end)), used)| {
+                let limit = period.limit(limits);
+                QuotaUsage {
+                    period,
+                    limit,
+                    used,
+                    remaining: limit.map(|limit| limit.saturating_sub(used)),
+                    resets_at: end,
+                }
+            })
+            .collect())
+    }
+
+    /// Reset the consumer's usage of the current periods
+    pub async fn reset(&self, consumer_id: &str, periods: &[QuotaPeriod]) -> Result<()> {
+        let now = Utc::now();
+        let keys: Vec<String> = periods
+            .iter()
+            .map(|period| {
+                let (start, _) = period.window(&self.reset, now);
+                Self::counter_key(consumer_id, *period, start)
+            })
+            .collect();
+        self.store.reset(&keys).await
+    }
+}
+
+/// Add the `X-Quota-*` headers, and `Retry-After` when the quota is used up
+fn add_quota_headers(headers: &mut HeaderMap, decision: &QuotaDecision) {
+    let reset = (decision.resets_at - Utc::now()).num_seconds().max(0);
+    let mut insert = |name: &'static str, value: String| {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
+    };
+
+    insert("x-quota-limit", decision.limit.to_string());
+    insert("x-quota-remaining", decision.remaining.to_string());
+    insert("x-quota-reset", reset.to_string());
+    if !decision.allowed {
+        insert("retry-after", reset.max(1).to_string());
+    }
+}
+
+/// Quota enforcement middleware
+pub async fn quota_middleware(
+    quotas: Arc<QuotaManager>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Response> {
+    let consumer = quotas.consumers.resolve(request.headers());
+    let Some(consumer_id) = quotas.consumer_id(&consumer) else {
+        return Ok(next.run(request).await);
+    };
+
+    let decision = match quotas.consume(&consumer_id, consumer.tier.as_deref()).await {
+        Ok(decision) => decision,
+        Err(e) => {
+            // Quotas are enforced over days and months, so a store outage
+            // lets calls through rather than failing them
+            warn!("Quota store error, letting the call through: {}", e);
+            counter!("quota_store_errors_total").increment(1);
+            None
+        }
+    };
+    let Some(decision) = decision else {
+        return Ok(next.run(request).await);
+    };
+
+    let outcome = if decision.allowed {
+        "allowed"
+    } else {
+        "rejected"
+    };
+    counter!("quota_decisions_total", "decision" => outcome).increment(1);
+
+    if !decision.allowed {
+        let mut response = (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(json!({"error": "Quota exceeded"})),
+        )
+            .into_response();
+        add_quota_headers(response.headers_mut(), &decision);
+        return Err(response);
+    }
+
+    let mut response = next.run(request).await;
+    add_quota_headers(response.headers_mut(), &decision);
+    Ok(response)
+}
+
+/// Create quota enforcement middleware
+pub fn create_quota_middleware(
+    quotas: Arc<QuotaManager>,
+) -> impl Fn(
+    Request,
+    Next,
+)
+    -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Response, Response>> + Send>>
+       + Clone {
+    move |request: Request, next: Next| {
+        let quotas = quotas.clone();
+        Box::pin(async move { quota_middleware(quotas, request, next).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ApiKeyConfig, QuotaStoreConfig, SecurityConfig, TierConfig};
+    use crate::middleware::quota_store::MemoryQuotaStore;
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn time(value: &str) -> DateTime<Utc> {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_period_windows() {
+        let reset = QuotaResetConfig {
+            hour: 6,
+            day_of_month: 15,
+        };
+
+        assert_eq!(
+            QuotaPeriod::Daily.window(&reset, time("2026-03-01T05:59:59Z")),
+            (time("2026-02-28T06:00:00Z"), time("2026-03-01T06:00:00Z"))
+        );
+        assert_eq!(
+            QuotaPeriod::Daily.window(&reset, time("2026-03-01T06:00:00Z")),
+            (time("2026-03-01T06:00:00Z"), time("2026-03-02T06:00:00Z"))
+        );
+        assert_eq!(
+            QuotaPeriod::Monthly.window(&reset, time("2026-01-10T00:00:00Z")),
+            (time("2025-12-15T06:00:00Z"), time("2026-01-15T06:00:00Z"))
+        );
+        assert_eq!(
+            QuotaPeriod::Monthly.window(&reset, time("2026-12-20T00:00:00Z")),
+            (time("2026-12-15T06:00:00Z"), time("2027-01-15T06:00:00Z"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_quota_middleware() {
+        let config = QuotaConfig {
+            limits: QuotaLimits {
+                daily: Some(2),
+                monthly: Some(100),
+            },
+            key: QuotaKeyConfig::ApiKey,
+            tiers: HashMap::from([(
+                "internal".to_string(),
+                QuotaLimits {
+                    daily: None,
+                    monthly: None,
+                },
+            )]),
+            reset: QuotaResetConfig::default(),
+            store: QuotaStoreConfig::Memory,
+        };
+        let server = ServerConfig {
+            security: SecurityConfig {
+                api_keys: Some(ApiKeyConfig {
+                    header: "x-api-key".to_string(),
+                    keys: vec!["partner".to_string(), "other".to_string()],
+                }),
+                ..Default::default()
+            },
+            tiers: vec![TierConfig {
+                name: "internal".to_string(),
+                api_keys: vec!["service".to_string()],
+                jwt_claims: HashMap::new(),
+            }],
+            ..Default::default()
+        };
+        let quotas = Arc::new(QuotaManager::new(
+            &config,
+            &server,
+            Arc::new(MemoryQuotaStore::default()),
+        ));
+        let app =
+            Router::new()
+                .route("/", get(|| async { "ok" }))
+                .layer(axum::middleware::from_fn(create_quota_middleware(
+                    quotas.clone(),
+                )));
+
+        let call = |api_key: Option<&'static str>| {
+            let mut builder = Request::builder().uri("/");
+            if let Some(api_key) = api_key {
+                builder = builder.header("x-api-key", api_key);
+            }
+            app.clone().oneshot(builder.body(Body::empty()).unwrap())
+        };
+        let header = |response: &Response, name: &str| {
+            response
+                .headers()
+                .get(name)
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+
+        let response = call(Some("partner")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, "x-quota-limit").as_deref(), Some("2"));
+        assert_eq!(header(&response, "x-quota-remaining").as_deref(), Some("1"));
+
+        call(Some("partner")).await.unwrap();
+        let response = call(Some("partner")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header(&response, "x-quota-remaining").as_deref(), Some("0"));
+        assert!(header(&response, "retry-after").is_some());
+
+        // Rejected calls are not counted against the monthly quota
+        let partner = format!("api_key:{}", hash_api_key("partner"));
+        let usage = quotas.usage(&partner, None).await.unwrap();
+        assert_eq!(usage[0].used, 2);
+        assert_eq!(usage[1].used, 2);
+        assert_eq!(usage[1].remaining, Some(98));
+
+        // Other consumers, unlimited tiers and anonymous calls are unaffected
+        assert_eq!(call(Some("other")).await.unwrap().status(), StatusCode::OK);
+        let response = call(Some("service")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, "x-quota-remaining"), None);
+        assert_eq!(call(None).await.unwrap().status(), StatusCode::OK);
+        let response = call(Some("unknown")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, "x-quota-remaining"), None);
+
+        quotas.reset(&partner, &[QuotaPeriod::Daily]).await.unwrap();
+        assert_eq!(
+            call(Some("partner")).await.unwrap().status(),
+            StatusCode::OK
+        );
+    }
+}
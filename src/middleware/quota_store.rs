@@ -0,0 +1,421 @@
+use crate::config::{QuotaConfig, QuotaStoreConfig, RedisQuotaStoreConfig, SqliteQuotaStoreConfig};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, Client, Script};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+use sqlx::Row;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::info;
+
+/// Longest wait for Redis before a call is let through uncounted
+const REDIS_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// A usage counter of one consumer for one quota period
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuotaCounter {
+    pub key: String,
+    /// Calls allowed in the period
+    pub limit: u64,
+    /// End of the period, as Unix milliseconds
+    pub expires_at: i64,
+}
+
+/// Outcome of counting a call against a consumer's counters
+#[derive(Debug, Clone, PartialEq)]
+pub struct Consumption {
+    /// Whether the call was counted; it is not when any counter is used up
+    pub allowed: bool,
+    /// Usage of each counter after the call
+    pub used: Vec<u64>,
+}
+
+/// Storage of quota usage counters.
+///
+/// Errors are reported to the caller, which lets the call through.
+#[async_trait]
+pub trait QuotaStore: Send + Sync + std::fmt::Debug {
+    /// Count a call against every counter, unless one of them is used up
+    async fn consume(&self, counters: &[QuotaCounter]) -> Result<Consumption>;
+
+    /// Get the usage of each counter
+    async fn usage(&self, keys: &[String]) -> Result<Vec<u64>>;
+
+    /// Reset counters to zero
+    async fn reset(&self, keys: &[String]) -> Result<()>;
+}
+
+/// Create the usage store for a quota configuration
+pub async fn create_quota_store(config: &QuotaConfig) -> Result<Arc<dyn QuotaStore>> {
+    Ok(match &config.store {
+        QuotaStoreConfig::Memory => Arc::new(MemoryQuotaStore::default()),
+        QuotaStoreConfig::Redis(redis_config) => {
+            Arc::new(RedisQuotaStore::new(redis_config.clone()).await?)
+        }
+        QuotaStoreConfig::Sqlite(sqlite_config) => {
+            Arc::new(SqliteQuotaStore::new(sqlite_config).await?)
+        }
+    })
+}
+
+/// In-process store; usage is lost on restart and not shared between replicas
+#[derive(Debug, Default)]
+pub struct MemoryQuotaStore {
+    /// Usage and expiry of each counter
+    counters: Mutex<HashMap<String, (u64, i64)>>,
+}
+
+#[async_trait]
+impl QuotaStore for MemoryQuotaStore {
+    async fn consume(&self, counters: &[QuotaCounter]) -> Result<Consumption> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut stored = self.counters.lock().unwrap_or_else(|e| e.into_inner());
+        stored.retain(|_, (_, expires_at)| *expires_at > now);
+
+        let used: Vec<u64> = counters
+            .iter()
+            .map(|counter| stored.get(&counter.key).map_or(0, |(used, _)| *used))
+            .collect();
+        let allowed = counters
+            .iter()
+            .zip(&used)
+            .all(|(counter, used)| *used < counter.limit);
+        if !allowed {
+            return Ok(Consumption { allowed, used });
+        }
+
+        let used = counters
+            .iter()
+            .map(|counter| {
+                let entry = stored
+                    .entry(counter.key.clone())
+                    .or_insert((0, counter.expires_at));
+                entry.0 += 1;
+                entry.0
+            })
+            .collect();
+        Ok(Consumption { allowed, used })
+    }
+
+    async fn usage(&self, keys: &[String]) -> Result<Vec<u64>> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let stored = self.counters.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(keys
+            .iter()
+            .map(|key| match stored.get(key) {
+                Some((used, expires_at)) if *expires_at > now => *used,
+                _ => 0,
+            })
+            .collect())
+    }
+
+    async fn reset(&self, keys: &[String]) -> Result<()> {
+        let mut stored = self.counters.lock().unwrap_or_else(|e| e.into_inner());
+        for key in keys {
+            stored.remove(key);
+        }
+        Ok(())
+    }
+}
+
+/// Checks every counter, then counts the call against all of them when none
+/// is used up.
+///
+/// KEYS: the counters
+/// ARGV: the limit and expiry (Unix milliseconds) of each counter
+/// Returns whether the call was counted, then the usage of each counter
+const CONSUME_SCRIPT: &str = r#"
+local allowed = 1
+local used = {}
+
+for i, key in ipairs(KEYS) do
+  used[i] = tonumber(redis.call('GET', key) or '0')
+  if used[i] >= tonumber(ARGV[2 * i - 1]) then
+    allowed = 0
+  end
+end
+
+if allowed == 1 then
+  for i, key in ipairs(KEYS) do
+    used[i] = redis.call('INCR', key)
+    redis.call('PEXPIREAT', key, ARGV[2 * i])
+  end
+end
+
+table.insert(used, 1, allowed)
+return used
+"#;
+
+/// Store persisting usage in Redis, shared by all gateway replicas
+pub struct RedisQuotaStore {
+    manager: ConnectionManager,
+    key_prefix: String,
+    script: Script,
+}
+
+impl std::fmt::Debug for RedisQuotaStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisQuotaStore")
+            .field("key_prefix", &self.key_prefix)
+            .finish()
+    }
+}
+
+impl RedisQuotaStore {
+    /// Connect to Redis
+    pub async fn new(config: RedisQuotaStoreConfig) -> Result<Self> {
+        info!("Creating Redis quota store");
+
+        let client = Client::open(config.connection_string.as_str())?;
+        let manager = ConnectionManager::new(client).await?;
+
+        Ok(Self {
+            manager,
+            key_prefix: config.key_prefix,
+            script: Script::new(CONSUME_SCRIPT),
+        })
+    }
+
+    fn redis_key(&self, key: &str) -> String {
+        format!("{}{}", self.key_prefix, key)
+    }
+}
+
+impl RedisQuotaStore {
+    async fn consume_counters(&self, counters: &[QuotaCounter]) -> Result<Consumption> {
+        let mut invocation = self.script.prepare_invoke();
+        for counter in counters {
+            invocation
+                .key(self.redis_key(&counter.key))
+                .arg(counter.limit)
+                .arg(counter.expires_at);
+        }
+
+        let results: Vec<u64> = invocation.invoke_async(&mut self.manager.clone()).await?;
+        match results.split_first() {
+            Some((allowed, used)) if used.len() == counters.len() => Ok(Consumption {
+                allowed: *allowed == 1,
+                used: used.to_vec(),
+            }),
+            _ => anyhow::bail!("Unexpected Redis quota result: {:?}", results),
+        }
+    }
+
+    async fn counter_usage(&self, keys: &[String]) -> Result<Vec<u64>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut conn = self.manager.clone();
+        let keys: Vec<String> = keys.iter().map(|key| self.redis_key(key)).collect();
+        let used: Vec<Option<u64>> = redis::cmd("MGET").arg(&keys).query_async(&mut conn).await?;
+        Ok(used.into_iter().map(Option::unwrap_or_default).collect())
+    }
+
+    async fn reset_counters(&self, keys: &[String]) -> Result<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = self.manager.clone();
+        let keys: Vec<String> = keys.iter().map(|key| self.redis_key(key)).collect();
+        let _: () = conn.del(keys).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl QuotaStore for RedisQuotaStore {
+    async fn consume(&self, counters: &[QuotaCounter]) -> Result<Consumption> {
+        tokio::time::timeout(REDIS_TIMEOUT, self.consume_counters(counters))
+            .await
+            .context("Redis quota consumption timed out")?
+    }
+
+    async fn usage(&self, keys: &[String]) -> Result<Vec<u64>> {
+        tokio::time::timeout(REDIS_TIMEOUT, self.counter_usage(keys))
+            .await
+            .context("Redis quota usage read timed out")?
+    }
+
+    async fn reset(&self, keys: &[String]) -> Result<()> {
+        tokio::time::timeout(REDIS_TIMEOUT, self.reset_counters(keys))
+            .await
+            .context("Redis quota reset timed out")?
+    }
+}
+
+/// Store persisting usage in a local SQLite database
+#[derive(Debug)]
+pub struct SqliteQuotaStore {
+    pool: SqlitePool,
+    /// Serializes consumption, so checking and counting are atomic
+    lock: tokio::sync::Mutex<()>,
+}
+
+impl SqliteQuotaStore {
+    /// Open the database, creating it and its table if needed
+    pub async fn new(config: &SqliteQuotaStoreConfig) -> Result<Self> {
+        info!("Creating SQLite quota store at {}", config.database_path);
+
+        let options =
+            SqliteConnectOptions::from_str(&config.database_path)?.create_if_missing(true);
+        let pool = SqlitePool::connect_with(options).await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS quota_usage (
+                key TEXT PRIMARY KEY,
+                used INTEGER NOT NULL,
+                expires_at INTEGER NOT NULL
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(Self {
+            pool,
+            lock: tokio::sync::Mutex::new(()),
+        })
+    }
+}
+
+#[async_trait]
+impl QuotaStore for SqliteQuotaStore {
+    async fn consume(&self, counters: &[QuotaCounter]) -> Result<Consumption> {
+        let _guard = self.lock.lock().await;
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut tx = self.pool.begin().await?;
+
+        // Counters of past periods are never read again
+        sqlx::query("DELETE FROM quota_usage WHERE expires_at <= ?")
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+
+        let mut used = Vec::with_capacity(counters.len());
+        for counter in counters {
+            let row = sqlx::query("SELECT used FROM quota_usage WHERE key = ?")
+                .bind(&counter.key)
+                .fetch_optional(&mut *tx)
+                .await?;
+            used.push(row.map_or(0, |row| row.get::<i64, _>("used") as u64));
+        }
+
+        let allowed = counters
+            .iter()
+            .zip(&used)
+            .all(|(counter, used)| *used < counter.limit);
+        if !allowed {
+            return Ok(Consumption { allowed, used });
+        }
+
+        for counter in counters {
+            sqlx::query(
+                "INSERT INTO quota_usage (key, used, expires_at) VALUES (?, 1, ?)
+                 ON CONFLICT(key) DO UPDATE SET used = used + 1",
+            )
+            .bind(&counter.key)
+            .bind(counter.expires_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(Consumption {
+            allowed,
+            used: used.into_iter().map(|used| used + 1).collect(),
+        })
+    }
+
+    async fn usage(&self, keys: &[String]) -> Result<Vec<u64>> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut used = Vec::with_capacity(keys.len());
+        for key in keys {
+            let row = sqlx::query("SELECT used FROM quota_usage WHERE key = ? AND expires_at > ?")
+                .bind(key)
+                .bind(now)
+                .fetch_optional(&self.pool)
+                .await?;
+            used.push(row.map_or(0, |row| row.get::<i64, _>("used") as u64));
+        }
+        Ok(used)
+    }
+
+    async fn reset(&self, keys: &[String]) -> Result<()> {
+        for key in keys {
+            sqlx::query("DELETE FROM quota_usage WHERE key = ?")
+                .bind(key)
+                .execute(&self.pool)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counters(limits: &[u64]) -> Vec<QuotaCounter> {
+        let expires_at = chrono::Utc::now().timestamp_millis() + 60_000;
+        limits
+            .iter()
+            .enumerate()
+            .map(|(i, limit)| QuotaCounter {
+                key: format!("api_key:partner:{}", i),
+                limit: *limit,
+                expires_at,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_counts_all_counters_or_none() {
+        let store = MemoryQuotaStore::default();
+        let counters = counters(&[1, 5]);
+
+        let consumption = store.consume(&counters).await.unwrap();
+        assert!(consumption.allowed);
+        assert_eq!(consumption.used, vec![1, 1]);
+
+        // The first counter is used up, so the second one is not counted either
+        let consumption = store.consume(&counters).await.unwrap();
+        assert!(!consumption.allowed);
+        assert_eq!(consumption.used, vec![1, 1]);
+
+        let keys: Vec<String> = counters.iter().map(|c| c.key.clone()).collect();
+        store.reset(&keys[..1]).await.unwrap();
+        assert_eq!(store.usage(&keys).await.unwrap(), vec![0, 1]);
+        assert!(store.consume(&counters).await.unwrap().allowed);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store_survives_reopening() {
+        let path = std::env::temp_dir().join(format!("quotas-{}.db", rand::random::<u64>()));
+        let config = SqliteQuotaStoreConfig {
+            database_path: format!("sqlite://{}", path.display()),
+        };
+        let counters = counters(&[2]);
+
+        let store = SqliteQuotaStore::new(&config).await.unwrap();
+        assert_eq!(store.consume(&counters).await.unwrap().used, vec![1]);
+        drop(store);
+
+        let store = SqliteQuotaStore::new(&config).await.unwrap();
+        assert_eq!(store.consume(&counters).await.unwrap().used, vec![2]);
+        let consumption = store.consume(&counters).await.unwrap();
+        assert!(!consumption.allowed);
+        assert_eq!(consumption.used, vec![2]);
+
+        store.reset(&[counters[0].key.clone()]).await.unwrap();
+        assert_eq!(
+            store.usage(&[counters[0].key.clone()]).await.unwrap(),
+            vec![0]
+        );
+
+        let _ = std::fs::remove_file(path);
+    }
+}
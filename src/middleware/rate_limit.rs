@@ -1,31 +1,330 @@
 use axum::{
     extract::Request,
-    http::StatusCode,
+    http::{HeaderMap, HeaderValue, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
     Json,
 };
 use governor::{
-    clock::DefaultClock,
-    state::{InMemoryState, NotKeyed},
+    clock::{Clock, DefaultClock},
+    middleware::StateInformationMiddleware,
+    state::keyed::DefaultKeyedStateStore,
     Quota, RateLimiter,
 };
 use serde_json::json;
+use std::collections::HashMap;
 use std::num::NonZeroU32;
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::config::RateLimitConfig;
+use crate::config::{
+    RateLimitConfig, RateLimitKeyConfig, RateLimitStoreConfig, RateLimits, ServerConfig,
+};
+use crate::middleware::client_ip::ClientIpResolver;
+use crate::middleware::consumer::{Consumer, ConsumerResolver};
+use crate::middleware::rate_limit_store::{RedisLimit, RedisRateLimitStore};
+use metrics::counter;
+use tracing::warn;
+
+pub type AppRateLimiter = Arc<KeyedRateLimiter>;
+
+/// Number of tracked keys above which idle keys are dropped
+const MAX_IDLE_KEYS: usize = 10_000;
+
+type KeyedLimiter =
+    RateLimiter<String, DefaultKeyedStateStore<String>, DefaultClock, StateInformationMiddleware>;
+
+/// One limit applied to every key, e.g. requests per second
+struct Limit {
+    quota: Quota,
+    /// Window of the limit in seconds
+    window: u64,
+    limiter: KeyedLimiter,
+}
+
+/// The limits applied to each key of a consumer tier
+struct LimitSet {
+    limits: Vec<Limit>,
+    /// `RateLimit-Policy` header value listing every limit, e.g. `10;w=1, 300;w=60`
+    policy: String,
+}
+
+/// Outcome of checking a request against the limits of its key, reported
+/// for the most restrictive limit
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    /// Requests allowed in the reported limit's window
+    pub limit: u32,
+    /// Requests left before the reported limit is reached
+    pub remaining: u32,
+    /// Time until the reported limit is fully replenished
+    pub reset: Duration,
+    /// Time until a rejected request may be retried
+    pub retry_after: Option<Duration>,
+}
+
+/// Rate limiter keeping separate limits per request key
+pub struct KeyedRateLimiter {
+    /// Where the limiter is configured, e.g. `server` or a route
+    scope: String,
+    key: RateLimitKeyConfig,
+    client_ip: ClientIpResolver,
+    consumers: ConsumerResolver,
+    clock: DefaultClock,
+    /// Limits of consumers without a tier of their own
+    default: LimitSet,
+    tiers: HashMap<String, LimitSet>,
+    /// Shared state in Redis; the local limits are used when it is unreachable
+    redis: Option<RedisRateLimitStore>,
+}
+
+/// Create a rate limiter from config; `scope` names where it is configured
+pub fn create_rate_limiter(
+    config: &RateLimitConfig,
+    server: &ServerConfig,
+    scope: &str,
+) -> AppRateLimiter {
+    let clock = DefaultClock::default();
+
+    let redis = match &config.store {
+        RateLimitStoreConfig::Memory => None,
+        RateLimitStoreConfig::Redis(redis_config) => match RedisRateLimitStore::new(redis_config) {
+            Ok(store) => Some(store),
+            Err(e) => {
+                warn!("Rate limit of {} keeps local state: {}", scope, e);
+                None
+            }
+        },
+    };
+
+    Arc::new(KeyedRateLimiter {
+        scope: scope.to_string(),
+        key: config.key.clone(),
+        client_ip: ClientIpResolver::new(&server.client_ip),
+        consumers: ConsumerResolver::new(server),
+        default: LimitSet::new(&config.limits, &clock),
+        tiers: config
+            .tiers
+            .iter()
+            .map(|(tier, limits)| (tier.clone(), LimitSet::new(limits, &clock)))
+            .collect(),
+        clock,
+        redis,
+    })
+}
+
+impl LimitSet {
+    fn new(limits: &RateLimits, clock: &DefaultClock) -> Self {
+        let per_second = NonZeroU32::new(limits.requests_per_second.try_into().unwrap_or(10))
+            .unwrap_or(NonZeroU32::MIN);
+        let burst = NonZeroU32::new(limits.burst_size).unwrap_or(NonZeroU32::MIN);
+
+        let mut quotas = vec![(Quota::per_second(per_second).allow_burst(burst), 1)];
+        if let Some(per_minute) = limits
+            .requests_per_minute
+            .and_then(|per_minute| NonZeroU32::new(per_minute.try_into().unwrap_or(u32::MAX)))
+        {
+            quotas.push((Quota::per_minute(per_minute), 60));
+        }
+
+        let policy = quotas
+            .iter()
+            .map(|(quota, window)| format!("{};w={}", quota.burst_size(), window))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let limits = quotas
+            .into_iter()
+            .map(|(quota, window)| Limit {
+                quota,
+                window,
+                limiter: KeyedLimiter::new(quota, DefaultKeyedStateStore::default(), clock),
+            })
+            .collect();
+
+        Self { limits, policy }
+    }
+
+    fn check(&self, key: &str, clock: &DefaultClock) -> Decision {
+        if self
+            .limits
+            .iter()
+            .any(|limit| limit.limiter.len() > MAX_IDLE_KEYS)
+        {
+            for limit in &self.limits {
+                limit.limiter.retain_recent();
+            }
+        }
+
+        let key = key.to_string();
+        let decisions = self.limits.iter().map(|limit| {
+            let burst = limit.quota.burst_size().get();
+            match limit.limiter.check_key(&key) {
+                Ok(snapshot) => {
+                    let remaining = snapshot.remaining_burst_capacity();
+                    Decision {
+                        allowed: true,
+                        limit: burst,
+                        remaining,
+                        reset: limit.quota.replenish_interval() * (burst - remaining),
+                        retry_after: None,
+                    }
+                }
+                Err(not_until) => Decision {
+                    allowed: false,
+                    limit: burst,
+                    remaining: 0,
+                    reset: limit.quota.replenish_interval() * burst,
+                    retry_after: Some(not_until.wait_time_from(clock.now())),
+                },
+            }
+        });
+
+        most_restrictive(decisions)
+    }
+
+    /// The limits of a key as checked in Redis
+    fn redis_limits(&self, redis: &RedisRateLimitStore, key: &str) -> Vec<RedisLimit> {
+        self.limits
+            .iter()
+            .map(|limit| RedisLimit {
+                key: redis.redis_key(&format!("{}:{}", limit.window, key)),
+                interval: limit.quota.replenish_interval(),
+                burst: limit.quota.burst_size().get(),
+            })
+            .collect()
+    }
+}
+
+/// The decision to report among those of several limits: a rejection with
+/// the longest wait wins, then the fewest remaining requests
+fn most_restrictive(decisions: impl IntoIterator<Item = Decision>) -> Decision {
+    decisions
+        .into_iter()
+        .reduce(|reported, decision| {
+            let more_restrictive = match (reported.allowed, decision.allowed) {
+                (true, false) => true,
+                (false, true) => false,
+                (false, false) => decision.retry_after > reported.retry_after,
+                (true, true) => decision.remaining < reported.remaining,
+            };
+            if more_restrictive {
+                decision
+            } else {
+                reported
+            }
+        })
+        .unwrap_or(Decision {
+            allowed: true,
+            limit: 0,
+            remaining: 0,
+            reset: Duration::ZERO,
+            retry_after: None,
+        })
+}
+
+impl KeyedRateLimiter {
+    /// Key a request is counted under
+    pub fn key_for(&self, request: &Request, consumer: &Consumer) -> String {
+        let headers = request.headers();
+        let key = match &self.key {
+            RateLimitKeyConfig::Global => return "global".to_string(),
+            RateLimitKeyConfig::Ip => None,
+            RateLimitKeyConfig::ApiKey => consumer
+                .api_key_hash()
+                .map(|hash| format!("api_key:{}", hash)),
+            RateLimitKeyConfig::JwtClaim { claim } => consumer
+                .claim(claim)
+                .map(|value| format!("claim:{}", value)),
+            RateLimitKeyConfig::Header { name } => headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .filter(|value| !value.is_empty())
+                .map(|value| format!("header:{}", value)),
+        };
+
+        key.unwrap_or_else(|| match self.client_ip.resolve(request) {
+            Some(ip) => format!("ip:{}", ip),
+            None => "ip:unknown".to_string(),
+        })
+    }
+
+    /// Count a request against every limit of its key, using the limits of
+    /// the consumer's tier when it has its own
+    pub async fn check(&self, key: &str, tier: Option<&str>) -> Decision {
+        let limits = self.limits_for(tier);
+
+        let (decision, store) = match &self.redis {
+            Some(redis) => {
+                let shared_key = format!("{}:{}:{}", self.scope, tier.unwrap_or("-"), key);
+                match redis.check(&limits.redis_limits(redis, &shared_key)).await {
+                    Ok(decisions) => (most_restrictive(decisions), "redis"),
+                    Err(e) => {
+                        warn!(
+                            "Rate limit of {} falls back to local state: {}",
+                            self.scope, e
+                        );
+                        counter!("rate_limit_fallbacks_total", "scope" => self.scope.clone())
+                            .increment(1);
+                        (limits.check(key, &self.clock), "memory")
+                    }
+                }
+            }
+            None => (limits.check(key, &self.clock), "memory"),
+        };
+
+        let outcome = if decision.allowed {
+            "allowed"
+        } else {
+            "rejected"
+        };
+        counter!(
+            "rate_limit_decisions_total",
+            "scope" => self.scope.clone(),
+            "decision" => outcome,
+            "store" => store
+        )
+        .increment(1);
+
+        decision
+    }
+
+    fn limits_for(&self, tier: Option<&str>) -> &LimitSet {
+        tier.and_then(|tier| self.tiers.get(tier))
+            .unwrap_or(&self.default)
+    }
+}
+
+/// Whole seconds, rounded up
+fn seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
 
-pub type AppRateLimiter = Arc<RateLimiter<NotKeyed, InMemoryState, DefaultClock>>;
+/// Add the `RateLimit-*` headers, and `Retry-After` when rejected. A limit
+/// checked further in (e.g. a route's) keeps its headers when it has fewer
+/// requests remaining.
+fn add_rate_limit_headers(headers: &mut HeaderMap, decision: &Decision, policy: &str) {
+    let inner_remaining = headers
+        .get("ratelimit-remaining")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u32>().ok());
+    if inner_remaining.is_some_and(|remaining| remaining <= decision.remaining) {
+        return;
+    }
 
-/// Create a rate limiter from config
-pub fn create_rate_limiter(config: &RateLimitConfig) -> AppRateLimiter {
-    let quota = Quota::per_second(
-        NonZeroU32::new(config.requests_per_second.try_into().unwrap_or(10)).unwrap(),
-    )
-    .allow_burst(NonZeroU32::new(config.burst_size).unwrap());
+    let mut insert = |name: &'static str, value: String| {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
+    };
 
-    Arc::new(RateLimiter::direct(quota))
+    insert("ratelimit-limit", decision.limit.to_string());
+    insert("ratelimit-remaining", decision.remaining.to_string());
+    insert("ratelimit-reset", seconds(decision.reset).to_string());
+    insert("ratelimit-policy", policy.to_string());
+    if let Some(retry_after) = decision.retry_after {
+        insert("retry-after", seconds(retry_after).max(1).to_string());
+    }
 }
 
 /// Rate limiting middleware
@@ -34,14 +333,25 @@ pub async fn rate_limit_middleware(
     request: Request,
     next: Next,
 ) -> Result<Response, Response> {
-    match limiter.check() {
-        Ok(_) => Ok(next.run(request).await),
-        Err(_) => Err((
+    let consumer = limiter.consumers.resolve(request.headers());
+    let key = limiter.key_for(&request, &consumer);
+    let tier = consumer.tier.as_deref();
+    let decision = limiter.check(&key, tier).await;
+    let policy = limiter.limits_for(tier).policy.clone();
+
+    if !decision.allowed {
+        let mut response = (
             StatusCode::TOO_MANY_REQUESTS,
             Json(json!({"error": "Rate limit exceeded"})),
         )
-            .into_response()),
+            .into_response();
+        add_rate_limit_headers(response.headers_mut(), &decision, &policy);
+        return Err(response);
     }
+
+    let mut response = next.run(request).await;
+    add_rate_limit_headers(response.headers_mut(), &decision, &policy);
+    Ok(response)
 }
 
 /// Create rate limiting middleware with limiter
@@ -58,3 +368,241 @@ pub fn create_rate_limit_middleware(
         Box::pin(async move { rate_limit_middleware(limiter, request, next).await })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ApiKeyConfig, ClientIpConfig, SecurityConfig, TierConfig};
+    use crate::middleware::consumer::hash_api_key;
+    use axum::{body::Body, extract::ConnectInfo, routing::get, Router};
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    fn config(key: RateLimitKeyConfig) -> RateLimitConfig {
+        RateLimitConfig {
+            limits: limits(1, 2, None),
+            key,
+            tiers: HashMap::new(),
+            store: RateLimitStoreConfig::Memory,
+        }
+    }
+
+    fn limits(per_second: u64, burst: u32, per_minute: Option<u64>) -> RateLimits {
+        RateLimits {
+            requests_per_second: per_second,
+            burst_size: burst,
+            requests_per_minute: per_minute,
+        }
+    }
+
+    fn key_for(limiter: &KeyedRateLimiter, request: &Request) -> String {
+        let consumer = limiter.consumers.resolve(request.headers());
+        limiter.key_for(request, &consumer)
+    }
+
+    fn request(headers: &[(&str, &str)], peer: &str) -> Request {
+        let mut builder = Request::builder().uri("/");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let mut request = builder.body(Body::empty()).unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        request
+    }
+
+    #[test]
+    fn test_key_extraction() {
+        let server = ServerConfig {
+            security: SecurityConfig {
+                api_keys: Some(ApiKeyConfig {
+                    header: "x-key".to_string(),
+                    keys: vec!["secret".to_string()],
+                }),
+                ..Default::default()
+            },
+            client_ip: ClientIpConfig {
+                trusted_proxies: vec!["10.0.0.0/8".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let limiter = create_rate_limiter(&config(RateLimitKeyConfig::Global), &server, "test");
+        assert_eq!(key_for(&limiter, &request(&[], "192.0.2.1:1000")), "global");
+
+        let limiter = create_rate_limiter(&config(RateLimitKeyConfig::Ip), &server, "test");
+        assert_eq!(
+            key_for(
+                &limiter,
+                &request(&[("x-forwarded-for", "203.0.113.9")], "10.0.0.1:1000")
+            ),
+            "ip:203.0.113.9"
+        );
+
+        let limiter = create_rate_limiter(&config(RateLimitKeyConfig::ApiKey), &server, "test");
+        assert_eq!(
+            key_for(&limiter, &request(&[("x-key", "secret")], "192.0.2.1:1000")),
+            format!("api_key:{}", hash_api_key("secret"))
+        );
+        // Requests without the attribute fall back to the client IP
+        assert_eq!(
+            key_for(&limiter, &request(&[], "192.0.2.1:1000")),
+            "ip:192.0.2.1"
+        );
+        // Unknown API keys share the bucket of the client IP
+        for unknown in ["random-1", "random-2"] {
+            assert_eq!(
+                key_for(&limiter, &request(&[("x-key", unknown)], "192.0.2.1:1000")),
+                "ip:192.0.2.1"
+            );
+        }
+
+        let limiter = create_rate_limiter(
+            &config(RateLimitKeyConfig::Header {
+                name: "x-tenant".to_string(),
+            }),
+            &server,
+            "test",
+        );
+        assert_eq!(
+            key_for(
+                &limiter,
+                &request(&[("x-tenant", "acme")], "192.0.2.1:1000")
+            ),
+            "header:acme"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keys_have_separate_limits() {
+        let limiter = create_rate_limiter(
+            &config(RateLimitKeyConfig::Ip),
+            &ServerConfig::default(),
+            "test",
+        );
+
+        let first = limiter.check("ip:192.0.2.1", None).await;
+        assert!(first.allowed);
+        assert_eq!((first.limit, first.remaining), (2, 1));
+        assert!(limiter.check("ip:192.0.2.1", None).await.allowed);
+
+        let rejected = limiter.check("ip:192.0.2.1", None).await;
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.remaining, 0);
+        assert!(rejected.retry_after.is_some());
+
+        assert!(limiter.check("ip:192.0.2.2", None).await.allowed);
+    }
+
+    #[tokio::test]
+    async fn test_most_restrictive_limit_is_reported() {
+        let limiter = create_rate_limiter(
+            &RateLimitConfig {
+                limits: limits(100, 100, Some(2)),
+                ..config(RateLimitKeyConfig::Global)
+            },
+            &ServerConfig::default(),
+            "test",
+        );
+        assert_eq!(limiter.limits_for(None).policy, "100;w=1, 2;w=60");
+
+        let first = limiter.check("global", None).await;
+        assert_eq!((first.limit, first.remaining), (2, 1));
+        assert!(limiter.check("global", None).await.allowed);
+
+        let rejected = limiter.check("global", None).await;
+        assert!(!rejected.allowed);
+        assert!(rejected.retry_after.unwrap() > Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_tiers_have_their_own_limits() {
+        let server = ServerConfig {
+            tiers: vec![TierConfig {
+                name: "pro".to_string(),
+                api_keys: vec!["partner".to_string()],
+                jwt_claims: HashMap::new(),
+            }],
+            ..Default::default()
+        };
+        let mut rate_limit = config(RateLimitKeyConfig::ApiKey);
+        rate_limit
+            .tiers
+            .insert("pro".to_string(), limits(10, 5, None));
+        let limiter = create_rate_limiter(&rate_limit, &server, "test");
+
+        let pro = request(&[("x-api-key", "partner")], "192.0.2.1:1000");
+        let consumer = limiter.consumers.resolve(pro.headers());
+        assert_eq!(consumer.tier.as_deref(), Some("pro"));
+
+        let key = limiter.key_for(&pro, &consumer);
+        for _ in 0..5 {
+            assert!(limiter.check(&key, Some("pro")).await.allowed);
+        }
+        assert!(!limiter.check(&key, Some("pro")).await.allowed);
+
+        // Consumers without a tier, or of a tier without limits, get the defaults
+        assert_eq!(limiter.check("api_key:free", None).await.limit, 2);
+        assert_eq!(
+            limiter.check("api_key:free", Some("internal")).await.limit,
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_redis_falls_back_to_local_limits() {
+        use crate::config::RedisRateLimitStoreConfig;
+
+        let limiter = create_rate_limiter(
+            &RateLimitConfig {
+                store: RateLimitStoreConfig::Redis(RedisRateLimitStoreConfig {
+                    connection_string: "redis://127.0.0.1:1".to_string(),
+                    key_prefix: "test:".to_string(),
+                }),
+                ..config(RateLimitKeyConfig::Global)
+            },
+            &ServerConfig::default(),
+            "test",
+        );
+
+        assert!(limiter.check("global", None).await.allowed);
+        assert!(limiter.check("global", None).await.allowed);
+        assert!(!limiter.check("global", None).await.allowed);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_headers() {
+        let limiter = create_rate_limiter(
+            &config(RateLimitKeyConfig::Ip),
+            &ServerConfig::default(),
+            "test",
+        );
+        let app =
+            Router::new()
+                .route("/", get(|| async { "ok" }))
+                .layer(axum::middleware::from_fn(create_rate_limit_middleware(
+                    limiter,
+                )));
+
+        let response = app
+            .clone()
+            .oneshot(request(&[], "192.0.2.1:1000"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["ratelimit-limit"], "2");
+        assert_eq!(response.headers()["ratelimit-remaining"], "1");
+        assert_eq!(response.headers()["ratelimit-policy"], "2;w=1");
+
+        app.clone()
+            .oneshot(request(&[], "192.0.2.1:1000"))
+            .await
+            .unwrap();
+        let response = app.oneshot(request(&[], "192.0.2.1:1000")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["ratelimit-remaining"], "0");
+        assert_eq!(response.headers()["retry-after"], "1");
+    }
+}
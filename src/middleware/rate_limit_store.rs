@@ -0,0 +1,193 @@
+use anyhow::{Context, Result};
+use redis::aio::ConnectionManager;
+use redis::{Client, Script};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tracing::{info, warn};
+
+use crate::config::RedisRateLimitStoreConfig;
+use crate::middleware::rate_limit::Decision;
+
+/// Longest a limiter decision may wait for Redis before falling back to local state
+const REDIS_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// How long Redis is skipped after a failure before it is tried again
+const RETRY_BACKOFF: Duration = Duration::from_secs(5);
+
+/// GCRA over every limit of a key at once, using the Redis server clock so
+/// that replicas agree. A request is only counted when all limits allow it.
+///
+/// KEYS: the theoretical arrival time key of each limit
+/// ARGV: the emission interval (microseconds) and burst of each limit
+/// Returns allowed, remaining, reset (us) and retry after (us) for each limit
+const GCRA_SCRIPT: &str = r#"
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000000 + tonumber(time[2])
+local results = {}
+local tats = {}
+local allowed = true
+
+for i, key in ipairs(KEYS) do
+  local interval = tonumber(ARGV[2 * i - 1])
+  local burst = tonumber(ARGV[2 * i])
+  local tat = math.max(tonumber(redis.call('GET', key) or now), now)
+  local new_tat = tat + interval
+  local allow_at = new_tat - burst * interval
+
+  if now < allow_at then
+    allowed = false
+    table.insert(results, 0)
+    table.insert(results, 0)
+    table.insert(results, tat - now)
+    table.insert(results, allow_at - now)
+  else
+    table.insert(results, 1)
+    table.insert(results, math.floor((now - allow_at) / interval))
+    table.insert(results, new_tat - now)
+    table.insert(results, 0)
+  end
+  tats[i] = new_tat
+end
+
+if allowed then
+  for i, key in ipairs(KEYS) do
+    redis.call('SET', key, tats[i], 'PX', math.ceil((tats[i] - now) / 1000))
+  end
+end
+return results
+"#;
+
+/// A limit as checked in Redis
+pub struct RedisLimit {
+    pub key: String,
+    pub interval: Duration,
+    pub burst: u32,
+}
+
+/// Rate limiter state kept in Redis, connected on first use
+pub struct RedisRateLimitStore {
+    client: Client,
+    manager: OnceCell<ConnectionManager>,
+    key_prefix: String,
+    script: Script,
+    /// When Redis last failed; it is not used again until the backoff has passed
+    failed_at: Mutex<Option<Instant>>,
+}
+
+impl RedisRateLimitStore {
+    /// Create a store; the connection is only opened when first needed
+    pub fn new(config: &RedisRateLimitStoreConfig) -> Result<Self> {
+        Ok(Self {
+            client: Client::open(config.connection_string.as_str())?,
+            manager: OnceCell::new(),
+            key_prefix: config.key_prefix.clone(),
+            script: Script::new(GCRA_SCRIPT),
+            failed_at: Mutex::new(None),
+        })
+    }
+
+    /// Redis key of a limiter key
+    pub fn redis_key(&self, key: &str) -> String {
+        format!("{}{}", self.key_prefix, key)
+    }
+
+    /// Count a request against each limit, or none when any of them rejects it.
+    /// After a failure, Redis is skipped for a while instead of being waited on
+    /// by every request.
+    pub async fn check(&self, limits: &[RedisLimit]) -> Result<Vec<Decision>> {
+        if let Some(failed_at) = *self.failed_at.lock().unwrap_or_else(|e| e.into_inner()) {
+            if failed_at.elapsed() < RETRY_BACKOFF {
+                anyhow::bail!("Redis rate limit store is backing off after a failure");
+            }
+        }
+
+        let result = tokio::time::timeout(REDIS_TIMEOUT, self.check_limits(limits))
+            .await
+            .context("Redis rate limit check timed out")
+            .and_then(|result| result);
+
+        let mut failed_at = self.failed_at.lock().unwrap_or_else(|e| e.into_inner());
+        match &result {
+            Ok(_) => *failed_at = None,
+            Err(e) => {
+                if failed_at.is_none() {
+                    warn!(
+                        "Redis rate limit store failed, retrying in {:?}: {}",
+                        RETRY_BACKOFF, e
+                    );
+                }
+                *failed_at = Some(Instant::now());
+            }
+        }
+        result
+    }
+
+    async fn check_limits(&self, limits: &[RedisLimit]) -> Result<Vec<Decision>> {
+        let manager = self
+            .manager
+            .get_or_try_init(|| async {
+                info!("Connecting Redis rate limit store");
+                ConnectionManager::new(self.client.clone()).await
+            })
+            .await?;
+
+        let mut invocation = self.script.prepare_invoke();
+        for limit in limits {
+            invocation
+                .key(&limit.key)
+                .arg(limit.interval.as_micros() as u64)
+                .arg(limit.burst);
+        }
+        let results: Vec<i64> = invocation.invoke_async(&mut manager.clone()).await?;
+        if results.len() != limits.len() * 4 {
+            anyhow::bail!("Unexpected Redis rate limit result: {:?}", results);
+        }
+
+        let micros = |value: i64| Duration::from_micros(value.max(0) as u64);
+        Ok(results
+            .chunks(4)
+            .zip(limits)
+            .map(|(result, limit)| Decision {
+                allowed: result[0] == 1,
+                limit: limit.burst,
+                remaining: result[1].clamp(0, limit.burst as i64) as u32,
+                reset: micros(result[2]),
+                retry_after: (result[0] != 1).then(|| micros(result[3])),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unreachable_redis_is_skipped_during_backoff() {
+        let store = RedisRateLimitStore::new(&RedisRateLimitStoreConfig {
+            connection_string: "redis://127.0.0.1:1".to_string(),
+            key_prefix: "test:".to_string(),
+        })
+        .unwrap();
+        let limits = [RedisLimit {
+            key: store.redis_key("global"),
+            interval: Duration::from_secs(1),
+            burst: 1,
+        }];
+
+        let error = store.check(&limits).await.unwrap_err();
+        assert!(!error.to_string().contains("backing off"));
+
+        // Later requests fail at once, without trying to connect again
+        let started = Instant::now();
+        let error = store.check(&limits).await.unwrap_err();
+        assert!(error.to_string().contains("backing off"));
+        assert!(started.elapsed() < Duration::from_millis(50));
+
+        // Once the backoff has passed, Redis is tried again
+        *store.failed_at.lock().unwrap() = Some(Instant::now() - RETRY_BACKOFF);
+        let error = store.check(&limits).await.unwrap_err();
+        assert!(!error.to_string().contains("backing off"));
+    }
+}
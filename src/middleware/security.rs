@@ -1,91 +1,151 @@
 use axum::{
-    extract::{ConnectInfo, Request},
-    http::{HeaderMap, StatusCode},
+    extract::Request,
+    http::{header, HeaderMap, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
     Json,
 };
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
-use serde::{Deserialize, Serialize};
+use ipnet::IpNet;
 use serde_json::json;
-use std::net::SocketAddr;
+use std::net::IpAddr;
 use std::sync::Arc;
 
-use crate::config::{ApiKeyConfig, IpFilterConfig, JwtConfig, SecurityConfig};
+use crate::config::{ApiKeyConfig, AuthPolicy, IpFilterConfig, SecurityConfig};
+use crate::middleware::client_ip::{parse_networks, ClientIpResolver};
+use crate::middleware::jwt::{create_jwt_validator, JwtValidator};
 
-#[allow(dead_code)]
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    exp: usize,
+/// Allow and block lists of client networks
+#[derive(Debug, Clone)]
+pub struct IpFilter {
+    allowlist: Vec<IpNet>,
+    blocklist: Vec<IpNet>,
+    client_ip: ClientIpResolver,
 }
 
-/// Security middleware that validates API keys, JWTs, and IP filters
-#[allow(dead_code)]
-pub async fn security_middleware(
-    config: Arc<SecurityConfig>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+impl IpFilter {
+    /// Create a filter; invalid entries are rejected by config validation
+    pub fn new(config: &IpFilterConfig, client_ip: ClientIpResolver) -> Self {
+        Self {
+            allowlist: parse_networks(&config.allowlist),
+            blocklist: parse_networks(&config.blocklist),
+            client_ip,
+        }
+    }
+
+    /// Whether a client may connect: blocked clients never may, and with an
+    /// allowlist only allowlisted ones may. Unknown clients only pass
+    /// filters without an allowlist.
+    fn is_allowed(&self, ip: Option<IpAddr>) -> bool {
+        let listed =
+            |networks: &[IpNet]| ip.is_some_and(|ip| networks.iter().any(|net| net.contains(&ip)));
+
+        if listed(&self.blocklist) {
+            return false;
+        }
+        self.allowlist.is_empty() || listed(&self.allowlist)
+    }
+}
+
+/// IP filter middleware: requests from clients the filter does not allow
+/// are rejected with 403
+pub async fn ip_filter_middleware(
+    filter: Arc<IpFilter>,
     request: Request,
     next: Next,
 ) -> Result<Response, Response> {
-    // Check IP filter
-    if let Some(ref ip_filter) = config.ip_filter {
-        if !is_ip_allowed(&addr.ip().to_string(), ip_filter) {
-            return Err((
-                StatusCode::FORBIDDEN,
-                Json(json!({"error": "IP address blocked"})),
-            )
-                .into_response());
-        }
+    if !filter.is_allowed(filter.client_ip.resolve(&request)) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(json!({"error": "IP address blocked"})),
+        )
+            .into_response());
     }
 
-    // Check API key
-    if let Some(ref api_key_config) = config.api_keys {
-        if !validate_api_key(request.headers(), api_key_config) {
-            return Err((
-                StatusCode::UNAUTHORIZED,
-                Json(json!({"error": "Invalid or missing API key"})),
-            )
-                .into_response());
+    Ok(next.run(request).await)
+}
+
+/// Verifies request credentials against auth policies
+#[derive(Debug, Clone)]
+pub struct Authenticator {
+    api_keys: Option<ApiKeyConfig>,
+    jwt: Option<Arc<JwtValidator>>,
+}
+
+impl Authenticator {
+    /// Create an authenticator from the configured credentials
+    pub fn new(config: &SecurityConfig) -> Self {
+        Self {
+            api_keys: config.api_keys.clone(),
+            jwt: create_jwt_validator(config.jwt.as_ref()),
         }
     }
 
-    // Check JWT
-    if let Some(ref jwt_config) = config.jwt {
-        if !validate_jwt(request.headers(), jwt_config) {
-            return Err((
-                StatusCode::UNAUTHORIZED,
-                Json(json!({"error": "Invalid or missing JWT token"})),
-            )
-                .into_response());
+    /// Whether the request's credentials satisfy a policy. Credentials that
+    /// are not configured are never valid.
+    pub fn satisfies(&self, headers: &HeaderMap, policy: &AuthPolicy) -> bool {
+        match policy {
+            AuthPolicy::None => true,
+            AuthPolicy::ApiKey => self
+                .api_keys
+                .as_ref()
+                .is_some_and(|config| validate_api_key(headers, config)),
+            AuthPolicy::Jwt => self.jwt.as_ref().is_some_and(|jwt| jwt.is_valid(headers)),
+            AuthPolicy::AnyOf(policies) => policies
+                .iter()
+                .any(|policy| self.satisfies(headers, policy)),
+            AuthPolicy::AllOf(policies) => policies
+                .iter()
+                .all(|policy| self.satisfies(headers, policy)),
         }
     }
+}
 
-    Ok(next.run(request).await)
+/// Error message of a request that does not satisfy a policy
+fn auth_error(policy: &AuthPolicy) -> &'static str {
+    match policy {
+        AuthPolicy::ApiKey => "Invalid or missing API key",
+        AuthPolicy::Jwt => "Invalid or missing JWT token",
+        _ => "Invalid or missing credentials",
+    }
 }
 
-#[allow(dead_code)]
-fn is_ip_allowed(ip: &str, config: &IpFilterConfig) -> bool {
-    // If allowlist is set, only those IPs are allowed
-    if !config.allowlist.is_empty() {
-        return config
-            .allowlist
-            .iter()
-            .any(|allowed| ip.starts_with(allowed));
+/// Whether a JWT may satisfy the policy, so clients are told to send one
+fn accepts_jwt(policy: &AuthPolicy) -> bool {
+    match policy {
+        AuthPolicy::Jwt => true,
+        AuthPolicy::AnyOf(policies) | AuthPolicy::AllOf(policies) => {
+            policies.iter().any(accepts_jwt)
+        }
+        AuthPolicy::None | AuthPolicy::ApiKey => false,
     }
+}
 
-    // Check blocklist
-    if !config.blocklist.is_empty() {
-        return !config
-            .blocklist
-            .iter()
-            .any(|blocked| ip.starts_with(blocked));
+/// Auth middleware: requests whose credentials do not satisfy the policy
+/// are rejected with 401
+pub async fn auth_middleware(
+    authenticator: Arc<Authenticator>,
+    policy: Arc<AuthPolicy>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Response> {
+    if !authenticator.satisfies(request.headers(), &policy) {
+        let mut response = (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"error": auth_error(&policy)})),
+        )
+            .into_response();
+        if accepts_jwt(&policy) {
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                header::HeaderValue::from_static("Bearer"),
+            );
+        }
+        return Err(response);
     }
 
-    true
+    Ok(next.run(request).await)
 }
 
-#[allow(dead_code)]
 fn validate_api_key(headers: &HeaderMap, config: &ApiKeyConfig) -> bool {
     if let Some(api_key) = headers.get(&config.header) {
         if let Ok(key_str) = api_key.to_str() {
@@ -95,47 +155,37 @@ fn validate_api_key(headers: &HeaderMap, config: &ApiKeyConfig) -> bool {
     false
 }
 
-#[allow(dead_code)]
-fn validate_jwt(headers: &HeaderMap, config: &JwtConfig) -> bool {
-    if let Some(auth_header) = headers.get("authorization") {
-        if let Ok(auth_str) = auth_header.to_str() {
-            if let Some(token) = auth_str.strip_prefix("Bearer ") {
-                let algorithm = match config.algorithm.as_str() {
-                    "HS256" => Algorithm::HS256,
-                    "HS384" => Algorithm::HS384,
-                    "HS512" => Algorithm::HS512,
-                    "RS256" => Algorithm::RS256,
-                    _ => Algorithm::HS256,
-                };
-
-                let mut validation = Validation::new(algorithm);
-                validation.validate_exp = config.validate_exp;
-
-                let key = DecodingKey::from_secret(config.secret.as_bytes());
-
-                return decode::<Claims>(token, &key, &validation).is_ok();
-            }
-        }
+/// Create IP filter middleware
+pub fn create_ip_filter_middleware(
+    filter: IpFilter,
+) -> impl Fn(
+    Request,
+    Next,
+)
+    -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Response, Response>> + Send>>
+       + Clone {
+    let filter = Arc::new(filter);
+    move |request: Request, next: Next| {
+        let filter = filter.clone();
+        Box::pin(async move { ip_filter_middleware(filter, request, next).await })
     }
-    false
 }
 
-/// Create security middleware with config
-#[allow(dead_code)]
-#[allow(clippy::type_complexity)]
-pub fn create_security_middleware(
-    config: SecurityConfig,
+/// Create auth middleware enforcing a policy
+pub fn create_auth_middleware(
+    authenticator: Arc<Authenticator>,
+    policy: AuthPolicy,
 ) -> impl Fn(
-    ConnectInfo<SocketAddr>,
     Request,
     Next,
 )
     -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Response, Response>> + Send>>
        + Clone {
-    let config = Arc::new(config);
-    move |addr: ConnectInfo<SocketAddr>, request: Request, next: Next| {
-        let config = config.clone();
-        Box::pin(async move { security_middleware(config, addr, request, next).await })
+    let policy = Arc::new(policy);
+    move |request: Request, next: Next| {
+        let authenticator = authenticator.clone();
+        let policy = policy.clone();
+        Box::pin(async move { auth_middleware(authenticator, policy, request, next).await })
     }
 }
 
@@ -143,36 +193,90 @@ pub fn create_security_middleware(
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_auth_policies() {
+        let authenticator = Authenticator::new(&SecurityConfig {
+            api_keys: Some(ApiKeyConfig {
+                header: "x-api-key".to_string(),
+                keys: vec!["secret".to_string()],
+            }),
+            ..Default::default()
+        });
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", "secret".parse().unwrap());
+
+        assert!(authenticator.satisfies(&HeaderMap::new(), &AuthPolicy::None));
+        assert!(authenticator.satisfies(&headers, &AuthPolicy::ApiKey));
+        assert!(!authenticator.satisfies(&HeaderMap::new(), &AuthPolicy::ApiKey));
+
+        // JWTs are never valid when JWT validation is not configured
+        assert!(!authenticator.satisfies(&headers, &AuthPolicy::Jwt));
+        assert!(authenticator.satisfies(
+            &headers,
+            &AuthPolicy::AnyOf(vec![AuthPolicy::Jwt, AuthPolicy::ApiKey])
+        ));
+        assert!(!authenticator.satisfies(
+            &headers,
+            &AuthPolicy::AllOf(vec![AuthPolicy::Jwt, AuthPolicy::ApiKey])
+        ));
+
+        assert!(accepts_jwt(&AuthPolicy::AnyOf(vec![AuthPolicy::Jwt])));
+        assert!(!accepts_jwt(&AuthPolicy::ApiKey));
+    }
+
+    fn ip(addr: &str) -> Option<IpAddr> {
+        Some(addr.parse().unwrap())
+    }
+
+    fn filter(allowlist: &[&str], blocklist: &[&str]) -> IpFilter {
+        let entries = |list: &[&str]| list.iter().map(|entry| entry.to_string()).collect();
+        IpFilter::new(
+            &IpFilterConfig {
+                allowlist: entries(allowlist),
+                blocklist: entries(blocklist),
+            },
+            ClientIpResolver::default(),
+        )
+    }
+
     #[test]
     fn test_ip_allowlist() {
-        let config = IpFilterConfig {
-            allowlist: vec!["192.168.1.".to_string()],
-            blocklist: vec![],
-        };
+        let filter = filter(&["192.168.1.0/24", "10.0.0.1", "2001:db8::/32"], &[]);
 
-        assert!(is_ip_allowed("192.168.1.100", &config));
-        assert!(!is_ip_allowed("10.0.0.1", &config));
+        assert!(filter.is_allowed(ip("192.168.1.100")));
+        assert!(filter.is_allowed(ip("10.0.0.1")));
+        assert!(filter.is_allowed(ip("2001:db8::1")));
+        // Single addresses are not prefixes
+        assert!(!filter.is_allowed(ip("10.0.0.100")));
+        assert!(!filter.is_allowed(ip("2001:db9::1")));
+        assert!(!filter.is_allowed(None));
     }
 
     #[test]
     fn test_ip_blocklist() {
-        let config = IpFilterConfig {
-            allowlist: vec![],
-            blocklist: vec!["192.168.1.".to_string()],
-        };
+        let filter = filter(&[], &["192.168.1.0/24", "2001:db8::1"]);
+
+        assert!(!filter.is_allowed(ip("192.168.1.100")));
+        assert!(!filter.is_allowed(ip("2001:db8::1")));
+        assert!(filter.is_allowed(ip("10.0.0.1")));
+        assert!(filter.is_allowed(ip("2001:db8::2")));
+        assert!(filter.is_allowed(None));
+    }
+
+    #[test]
+    fn test_ip_blocklist_takes_precedence() {
+        let filter = filter(&["10.0.0.0/8"], &["10.0.0.66"]);
 
-        assert!(!is_ip_allowed("192.168.1.100", &config));
-        assert!(is_ip_allowed("10.0.0.1", &config));
+        assert!(filter.is_allowed(ip("10.0.0.65")));
+        assert!(!filter.is_allowed(ip("10.0.0.66")));
+        assert!(!filter.is_allowed(ip("192.168.1.1")));
     }
 
     #[test]
     fn test_no_ip_filter() {
-        let config = IpFilterConfig {
-            allowlist: vec![],
-            blocklist: vec![],
-        };
+        let filter = filter(&[], &[]);
 
-        assert!(is_ip_allowed("192.168.1.100", &config));
-        assert!(is_ip_allowed("10.0.0.1", &config));
+        assert!(filter.is_allowed(ip("192.168.1.100")));
+        assert!(filter.is_allowed(ip("10.0.0.1")));
     }
 }
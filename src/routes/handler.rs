@@ -1,40 +1,94 @@
 use crate::clients::ClientManager;
 use crate::conditions::evaluate_condition;
 use crate::config::{
-    Config, ExecutionMode, MongodbSubrequestConfig, RedisSubrequestConfig, SqlSubrequestConfig,
-    SubrequestConfig, SubrequestTypeConfig,
+    Config, DependencyPolicy, ErrorPolicy, ExecutionMode, MongodbSubrequestConfig,
+    RedisSubrequestConfig, RouteConfig, SqlSubrequestConfig, SubrequestConfig,
+    SubrequestTypeConfig,
 };
 use crate::interpolation::InterpolationContext;
-use crate::transform::apply_transformation;
+use crate::middleware::cache::{CacheDirectives, CacheRegistry, UpstreamCacheHints};
+use crate::middleware::conditional::with_etag;
+use crate::middleware::quota::QuotaManager;
+use crate::routes::response::build_response;
+use crate::transform::{apply_transformation, merge_results};
 use axum::{
     extract::{Path, Query, State},
-    http::{HeaderMap, Method, StatusCode},
+    http::{HeaderMap, HeaderValue, Method, StatusCode},
     response::{IntoResponse, Response},
+    Extension,
 };
+use futures::stream::{self, FuturesUnordered, StreamExt, TryStreamExt};
+use serde::Serialize;
 use serde_json::{json, Value};
 use std::collections::HashMap;
 use std::sync::Arc;
-use tracing::{debug, error, info};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn};
+
+/// Header naming the subrequests that failed when a merged response is returned
+const PARTIAL_FAILURE_HEADER: &str = "x-partial-failure";
 
 /// Shared application state
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub config: Arc<Config>,
     pub client_manager: Arc<ClientManager>,
+    /// Response caches of the routes
+    pub caches: Arc<CacheRegistry>,
+    /// Consumption quotas, charged by every route
+    pub quotas: Option<Arc<QuotaManager>>,
+}
+
+/// A subrequest failure that did not abort the route
+#[derive(Debug, Clone, Serialize)]
+pub struct SubrequestFailure {
+    /// Subrequest name, if it has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subrequest: Option<String>,
+    /// Client the subrequest was sent to
+    pub client_id: String,
+    /// Error message
+    pub error: String,
+    /// How the failure was handled
+    pub handling: FailureHandling,
 }
 
-/// Generic route handler that processes subrequests
+/// How a subrequest failure was handled
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FailureHandling {
+    /// The configured fallback value was used as the result
+    Fallback,
+    /// The subrequest is optional and was left out of the results
+    Skipped,
+    /// The subrequest was required; the route policy decides the outcome
+    Failed,
+}
+
+/// Results of executing a route's subrequests
+#[derive(Debug, Default)]
+struct ExecutionOutcome {
+    results: Vec<Value>,
+    failures: Vec<SubrequestFailure>,
+}
+
+/// Generic route handler that processes the subrequests of the matched route
 pub async fn handle_route(
     State(state): State<AppState>,
+    Extension(route_config): Extension<Arc<RouteConfig>>,
     method: Method,
-    Path(path_params): Path<HashMap<String, String>>,
+    path_params: Option<Path<HashMap<String, String>>>,
     Query(query_params): Query<HashMap<String, String>>,
     headers: HeaderMap,
     body: String,
 ) -> Result<Response, AppError> {
+    let path_params = path_params.map(|Path(params)| params).unwrap_or_default();
+
     info!(
-        "Handling request: {} with {} path params, {} query params",
+        "Handling request: {} {} with {} path params, {} query params",
         method,
+        route_config.path,
         path_params.len(),
         query_params.len()
     );
@@ -48,191 +102,378 @@ pub async fn handle_route(
         method.clone(),
     );
 
-    // For demonstration, let's find the first route that matches the method
-    // A more sophisticated implementation would do proper path matching
-    if let Some(route_config) = state.config.routes.first() {
-        let results = match route_config.execution_mode {
-            ExecutionMode::Sequential => {
-                execute_sequential(&state, &route_config.subrequests, &mut context).await?
-            }
-            ExecutionMode::Parallel => {
-                execute_parallel(&state, &route_config.subrequests, &context).await?
-            }
-        };
-
-        // Apply response transformation if configured
-        let mut response_data = json!({
-            "subrequests": results,
-            "count": results.len(),
-        });
+    // The route deadline bounds every subrequest, so later ones get what is left of it
+    let deadline = route_config
+        .timeout_ms
+        .map(|ms| Instant::now() + Duration::from_millis(ms));
+
+    let ExecutionOutcome { results, failures } =
+        execute_graph(&state, &route_config, deadline, &mut context).await?;
+
+    // With collect-errors, any required failure fails the whole route
+    if route_config.on_error == ErrorPolicy::CollectErrors
+        && failures
+            .iter()
+            .any(|failure| failure.handling == FailureHandling::Failed)
+    {
+        return Err(AppError::PartialFailure(failures));
+    }
 
-        if let Some(transform) = &route_config.response_transform {
-            response_data = apply_transformation(response_data, transform, &context);
+    // Shape the response: merged results, or the subrequests list envelope
+    let mut response_data = match &route_config.merge {
+        Some(merge) if merge.subrequests.is_empty() => merge_results(
+            merge,
+            route_config
+                .subrequests
+                .iter()
+                .filter_map(|sr| sr.name.as_deref()),
+            &context,
+        ),
+        Some(merge) => merge_results(
+            merge,
+            merge.subrequests.iter().map(String::as_str),
+            &context,
+        ),
+        None => {
+            let mut envelope = json!({
+                "subrequests": results,
+                "count": results.len(),
+            });
+            if !failures.is_empty() {
+                envelope["errors"] = json!(failures);
+            }
+            envelope
         }
+    };
 
-        Ok((StatusCode::OK, axum::Json(response_data)).into_response())
-    } else {
-        Err(AppError::RouteNotFound)
+    // Apply response transformation if configured
+    if let Some(transform) = &route_config.response_transform {
+        response_data = apply_transformation(response_data, transform, &context);
     }
-}
 
-/// Execute subrequests sequentially (allows data dependencies)
-async fn execute_sequential(
-    state: &AppState,
-    subrequests: &[SubrequestConfig],
-    context: &mut InterpolationContext,
-) -> Result<Vec<Value>, AppError> {
-    let mut results = Vec::new();
+    let mut response = build_response(
+        route_config.response.as_ref(),
+        &context,
+        &results,
+        response_data,
+    );
 
-    for subrequest in subrequests {
-        // Check condition if present
-        if let Some(condition) = &subrequest.condition {
-            if !evaluate_condition(condition, context) {
-                debug!(
-                    "Skipping subrequest {:?} - condition not met",
-                    subrequest.name
-                );
-                continue;
-            }
+    // Merged responses have no room for an errors list, so failed parts are named in a header
+    if route_config.merge.is_some() && !failures.is_empty() {
+        let failed = failures
+            .iter()
+            .map(|failure| failure.subrequest.as_deref().unwrap_or(&failure.client_id))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if let Ok(value) = HeaderValue::from_str(&failed) {
+            response.headers_mut().insert(PARTIAL_FAILURE_HEADER, value);
         }
+    }
 
-        debug!(
-            "Executing subrequest {:?} for client: {}",
-            subrequest.name, subrequest.client_id
-        );
+    // Let the route cache honour upstream Cache-Control and Vary headers
+    if route_config.cache.is_some() {
+        response
+            .extensions_mut()
+            .insert(upstream_cache_hints(&results));
+    }
 
-        let result = execute_single_subrequest(state, subrequest, context).await?;
+    Ok(with_etag(response).await)
+}
 
-        // Store result in context if the subrequest has a name
-        if let Some(name) = &subrequest.name {
-            context.add_subrequest_result(name.clone(), result.clone());
-        }
+/// Combine the Cache-Control and Vary headers of the HTTP subrequest results
+fn upstream_cache_hints(results: &[Value]) -> UpstreamCacheHints {
+    let mut hints = UpstreamCacheHints::default();
+
+    let responses = results.iter().flat_map(|result| match result {
+        Value::Array(items) => items.iter().collect::<Vec<_>>(),
+        other => vec![other],
+    });
 
-        results.push(result);
+    for response in responses.filter(|r| r["type"] == "http") {
+        if let Some(cache_control) = response["headers"]["cache-control"].as_str() {
+            hints.directives = std::mem::take(&mut hints.directives)
+                .merge(CacheDirectives::parse([cache_control]));
+        }
+        if let Some(vary) = response["headers"]["vary"].as_str() {
+            hints
+                .vary
+                .extend(vary.split(',').map(|name| name.trim().to_string()));
+        }
     }
 
-    Ok(results)
+    hints
 }
 
-/// Execute subrequests in parallel (for independent requests)
-async fn execute_parallel(
+/// Execute the route's subrequests as a dependency graph.
+///
+/// Each subrequest starts as soon as all of its `depends_on` subrequests have
+/// finished, in declaration order among those that are ready, with at most
+/// `max_concurrency` running at once (one in sequential mode). Conditions are
+/// evaluated when a subrequest becomes ready. A subrequest whose dependency was
+/// skipped or produced no result follows its `on_dependency_skipped` policy.
+async fn execute_graph(
     state: &AppState,
-    subrequests: &[SubrequestConfig],
-    context: &InterpolationContext,
-) -> Result<Vec<Value>, AppError> {
-    // Build dependency graph and execution order
-    let execution_order = build_execution_order(subrequests)?;
+    route: &RouteConfig,
+    deadline: Option<Instant>,
+    context: &mut InterpolationContext,
+) -> Result<ExecutionOutcome, AppError> {
+    let subrequests = &route.subrequests;
+    let dependencies = route
+        .dependencies()
+        .map_err(|e| AppError::InvalidConfig(e.to_string()))?;
+    let max_concurrency = match route.execution_mode {
+        ExecutionMode::Sequential => 1,
+        ExecutionMode::Parallel => route.max_concurrency.unwrap_or(usize::MAX).max(1),
+    };
+
+    // None while pending or running, then whether the subrequest produced a result
+    let mut finished: Vec<Option<bool>> = vec![None; subrequests.len()];
+    let mut started = vec![false; subrequests.len()];
+    let mut running = FuturesUnordered::new();
+    let mut results = Vec::new();
+    let mut failures = Vec::new();
 
-    let mut all_results = Vec::new();
-    let mut context_clone = context.clone();
+    loop {
+        // Start every ready subrequest; skipping one may make others ready
+        let mut progressed = true;
+        while progressed {
+            progressed = false;
 
-    // Execute in waves based on dependencies
-    for wave in execution_order {
-        let mut wave_futures = Vec::new();
+            for (idx, subrequest) in subrequests.iter().enumerate() {
+                if started[idx] || running.len() >= max_concurrency {
+                    continue;
+                }
+                if dependencies[idx].iter().any(|&dep| finished[dep].is_none()) {
+                    continue;
+                }
 
-        for idx in wave {
-            let subrequest = &subrequests[idx];
+                started[idx] = true;
+                progressed = true;
 
-            // Check condition if present
-            if let Some(condition) = &subrequest.condition {
-                if !evaluate_condition(condition, &context_clone) {
+                let missing_dependency = dependencies[idx]
+                    .iter()
+                    .any(|&dep| finished[dep] == Some(false));
+                if missing_dependency && subrequest.on_dependency_skipped == DependencyPolicy::Skip
+                {
                     debug!(
-                        "Skipping subrequest {:?} - condition not met",
+                        "Skipping subrequest {:?} - a dependency was skipped",
                         subrequest.name
                     );
+                    finished[idx] = Some(false);
                     continue;
                 }
-            }
 
-            let state_clone = state.clone();
-            let subrequest_clone = subrequest.clone();
-            let context_for_task = context_clone.clone();
-
-            wave_futures.push(async move {
-                (
-                    idx,
-                    subrequest_clone.name.clone(),
-                    execute_single_subrequest(&state_clone, &subrequest_clone, &context_for_task)
-                        .await,
-                )
-            });
+                if let Some(condition) = &subrequest.condition {
+                    if !evaluate_condition(condition, context) {
+                        debug!(
+                            "Skipping subrequest {:?} - condition not met",
+                            subrequest.name
+                        );
+                        finished[idx] = Some(false);
+                        continue;
+                    }
+                }
+
+                debug!(
+                    "Executing subrequest {:?} for client: {}",
+                    subrequest.name, subrequest.client_id
+                );
+
+                let task_context = context.clone();
+                running.push(async move {
+                    (
+                        idx,
+                        execute_subrequest(state, subrequest, &task_context, deadline).await,
+                    )
+                });
+            }
         }
 
-        // Execute this wave in parallel
-        let wave_results = futures::future::join_all(wave_futures).await;
+        let Some((idx, result)) = running.next().await else {
+            break;
+        };
 
-        // Collect results and update context
-        for (idx, name, result) in wave_results {
-            match result {
-                Ok(value) => {
-                    if let Some(subreq_name) = name {
-                        context_clone.add_subrequest_result(subreq_name, value.clone());
-                    }
-                    all_results.push((idx, value));
-                }
-                Err(e) => return Err(e),
+        let subrequest = &subrequests[idx];
+        let value = match result {
+            Ok(value) => Some(value),
+            Err(e) => resolve_failure(subrequest, e, route.on_error, context, &mut failures)?,
+        };
+
+        finished[idx] = Some(value.is_some());
+        if let Some(value) = value {
+            if let Some(name) = &subrequest.name {
+                context.add_subrequest_result(name.clone(), value.clone());
             }
+            results.push((idx, value));
         }
     }
 
+    // Cycles are rejected when the configuration is validated
+    if started.contains(&false) {
+        return Err(AppError::CircularDependency);
+    }
+
     // Sort results by original order
-    all_results.sort_by_key(|(idx, _)| *idx);
-    Ok(all_results.into_iter().map(|(_, v)| v).collect())
+    results.sort_by_key(|(idx, _)| *idx);
+    Ok(ExecutionOutcome {
+        results: results.into_iter().map(|(_, value)| value).collect(),
+        failures,
+    })
 }
 
-/// Build execution order based on dependencies
-/// Returns waves of subrequest indices that can be executed in parallel
-fn build_execution_order(subrequests: &[SubrequestConfig]) -> Result<Vec<Vec<usize>>, AppError> {
-    let mut waves: Vec<Vec<usize>> = Vec::new();
-    let mut executed: std::collections::HashSet<String> = std::collections::HashSet::new();
-
-    // Create name to index mapping
-    let _name_to_idx: HashMap<String, usize> = subrequests
-        .iter()
-        .enumerate()
-        .filter_map(|(idx, sr)| sr.name.as_ref().map(|name| (name.clone(), idx)))
-        .collect();
+/// Handle a failed subrequest using its fallback/optional settings and the route policy.
+/// Returns the fallback result to use, None to continue without a result, or an error
+/// when the route must abort.
+fn resolve_failure(
+    subrequest: &SubrequestConfig,
+    error: AppError,
+    on_error: ErrorPolicy,
+    context: &InterpolationContext,
+    failures: &mut Vec<SubrequestFailure>,
+) -> Result<Option<Value>, AppError> {
+    let (handling, fallback) = if let Some(fallback) = &subrequest.fallback {
+        (
+            FailureHandling::Fallback,
+            Some(render_fallback(fallback, context)),
+        )
+    } else if subrequest.optional {
+        (FailureHandling::Skipped, None)
+    } else if on_error == ErrorPolicy::FailFast {
+        return Err(error);
+    } else {
+        (FailureHandling::Failed, None)
+    };
 
-    loop {
-        let mut current_wave = Vec::new();
+    warn!(
+        "Subrequest {:?} for client {} failed ({:?}): {}",
+        subrequest.name, subrequest.client_id, handling, error
+    );
 
-        for (idx, subrequest) in subrequests.iter().enumerate() {
-            // Skip if already executed
-            if let Some(name) = &subrequest.name {
-                if executed.contains(name) {
-                    continue;
-                }
-            } else if waves.iter().any(|wave| wave.contains(&idx)) {
-                continue;
-            }
+    failures.push(SubrequestFailure {
+        subrequest: subrequest.name.clone(),
+        client_id: subrequest.client_id.clone(),
+        error: error.to_string(),
+        handling,
+    });
 
-            // Check if all dependencies are met
-            let deps_met = subrequest
-                .depends_on
-                .iter()
-                .all(|dep| executed.contains(dep));
+    Ok(fallback)
+}
 
-            if deps_met {
-                current_wave.push(idx);
-                if let Some(name) = &subrequest.name {
-                    executed.insert(name.clone());
-                }
-            }
+/// Render a fallback value, interpolating every string it contains.
+/// A top-level string that renders to valid JSON is parsed.
+fn render_fallback(fallback: &Value, context: &InterpolationContext) -> Value {
+    fn interpolate_strings(value: &Value, context: &InterpolationContext) -> Value {
+        match value {
+            Value::String(s) => Value::String(context.interpolate(s)),
+            Value::Array(items) => Value::Array(
+                items
+                    .iter()
+                    .map(|item| interpolate_strings(item, context))
+                    .collect(),
+            ),
+            Value::Object(map) => Value::Object(
+                map.iter()
+                    .map(|(k, v)| (k.clone(), interpolate_strings(v, context)))
+                    .collect(),
+            ),
+            other => other.clone(),
         }
+    }
 
-        if current_wave.is_empty() {
-            break;
+    match fallback {
+        Value::String(template) => {
+            let rendered = context.interpolate(template);
+            serde_json::from_str(&rendered).unwrap_or(Value::String(rendered))
         }
-
-        waves.push(current_wave);
+        other => interpolate_strings(other, context),
     }
+}
 
-    // Check if all subrequests were scheduled
-    if waves.iter().map(|w| w.len()).sum::<usize>() != subrequests.len() {
-        return Err(AppError::CircularDependency);
+/// Execute a subrequest within its own timeout and what remains of the route deadline
+async fn execute_subrequest(
+    state: &AppState,
+    subrequest: &SubrequestConfig,
+    context: &InterpolationContext,
+    deadline: Option<Instant>,
+) -> Result<Value, AppError> {
+    let own = subrequest.timeout_ms.map(Duration::from_millis);
+    let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+
+    let budget = match (own, remaining) {
+        (Some(own), Some(remaining)) => Some(own.min(remaining)),
+        (own, remaining) => own.or(remaining),
+    };
+    let Some(budget) = budget else {
+        return execute_iterations(state, subrequest, context).await;
+    };
+
+    let label = subrequest.name.as_deref().unwrap_or(&subrequest.client_id);
+    if budget.is_zero() {
+        return Err(AppError::GatewayTimeout(format!(
+            "route deadline exceeded before subrequest {} started",
+            label
+        )));
     }
 
-    Ok(waves)
+    tokio::time::timeout(budget, execute_iterations(state, subrequest, context))
+        .await
+        .map_err(|_| {
+            AppError::GatewayTimeout(format!(
+                "subrequest {} timed out after {}ms",
+                label,
+                budget.as_millis()
+            ))
+        })?
+}
+
+/// Execute a subrequest once, or once per item when it has a for_each source
+async fn execute_iterations(
+    state: &AppState,
+    subrequest: &SubrequestConfig,
+    context: &InterpolationContext,
+) -> Result<Value, AppError> {
+    let Some(for_each) = &subrequest.for_each else {
+        return execute_single_subrequest(state, subrequest, context).await;
+    };
+
+    let items = match context.resolve_value(for_each) {
+        Some(Value::Array(items)) => items,
+        // A string holding a JSON array (e.g., a raw HTTP body) is parsed
+        Some(Value::String(raw)) => match serde_json::from_str(&raw) {
+            Ok(Value::Array(items)) => items,
+            _ => return Err(for_each_error(for_each)),
+        },
+        None | Some(Value::Null) => {
+            debug!("for_each source {} is empty, skipping iteration", for_each);
+            Vec::new()
+        }
+        Some(_) => return Err(for_each_error(for_each)),
+    };
+
+    debug!(
+        "Executing subrequest {:?} for {} items (concurrency {})",
+        subrequest.name,
+        items.len(),
+        subrequest.for_each_concurrency
+    );
+
+    let results: Vec<Value> = stream::iter(items.into_iter().enumerate())
+        .map(|(index, item)| {
+            let item_context = context.with_item(index, item);
+            async move { execute_single_subrequest(state, subrequest, &item_context).await }
+        })
+        .buffered(subrequest.for_each_concurrency.max(1))
+        .try_collect()
+        .await?;
+
+    Ok(Value::Array(results))
+}
+
+fn for_each_error(expr: &str) -> AppError {
+    AppError::SubrequestFailed(format!(
+        "for_each expression {} did not resolve to an array",
+        expr
+    ))
 }
 
 /// Execute a single subrequest
@@ -240,6 +481,72 @@ async fn execute_single_subrequest(
     state: &AppState,
     subrequest: &SubrequestConfig,
     context: &InterpolationContext,
+) -> Result<Value, AppError> {
+    let Some(cache) = &subrequest.cache else {
+        return execute_uncached_subrequest(state, subrequest, context).await;
+    };
+
+    let key = match &cache.key {
+        Some(template) => context.interpolate(template),
+        None => render_subrequest(&subrequest.config, context).to_string(),
+    };
+    let results = state.caches.subrequests();
+    if let Some(result) = results.get(&subrequest.client_id, &key).await {
+        debug!("Subrequest cache hit for {}: {}", subrequest.client_id, key);
+        return Ok(result);
+    }
+
+    let result = execute_uncached_subrequest(state, subrequest, context).await?;
+
+    // HTTP error responses are results too, but are not worth keeping
+    let cacheable = result
+        .get("status")
+        .and_then(Value::as_u64)
+        .is_none_or(|status| status < 400);
+    if cacheable {
+        let ttl = Duration::from_secs(cache.ttl_seconds);
+        results
+            .put(&subrequest.client_id, &key, result.clone(), ttl)
+            .await;
+    }
+
+    Ok(result)
+}
+
+/// Execute a single subrequest against its client, within the client's bulkhead
+async fn execute_uncached_subrequest(
+    state: &AppState,
+    subrequest: &SubrequestConfig,
+    context: &InterpolationContext,
+) -> Result<Value, AppError> {
+    let Some(limiter) = state
+        .client_manager
+        .get_concurrency_limiter(&subrequest.client_id)
+    else {
+        return dispatch_subrequest(state, subrequest, context).await;
+    };
+
+    let permit = limiter.acquire().await.map_err(|rejection| {
+        AppError::Overloaded(format!("client {}: {}", subrequest.client_id, rejection))
+    })?;
+    let result = dispatch_subrequest(state, subrequest, context).await;
+
+    let overloaded = match &result {
+        Ok(value) => value
+            .get("status")
+            .and_then(Value::as_u64)
+            .is_some_and(|status| status >= 500),
+        Err(_) => true,
+    };
+    permit.complete(overloaded);
+    result
+}
+
+/// Execute a single subrequest with the client of its type
+async fn dispatch_subrequest(
+    state: &AppState,
+    subrequest: &SubrequestConfig,
+    context: &InterpolationContext,
 ) -> Result<Value, AppError> {
     match &subrequest.config {
         SubrequestTypeConfig::Http(http_config) => {
@@ -294,29 +601,17 @@ async fn execute_http_subrequest(
         .get_http_client(client_id)
         .ok_or_else(|| AppError::ClientNotFound(client_id.to_string()))?;
 
-    // Interpolate URI
-    let uri = context.interpolate(&config.uri);
-
-    // Interpolate headers
-    let headers: HashMap<String, String> = config
-        .headers
-        .iter()
-        .map(|(k, v)| (k.clone(), context.interpolate(v)))
-        .collect();
-
-    // Interpolate body
-    let body = config.body.as_ref().map(|b| context.interpolate(b));
-
-    // Interpolate query params
-    let query_params: HashMap<String, String> = config
-        .query_params
-        .iter()
-        .map(|(k, v)| (k.clone(), context.interpolate(v)))
-        .collect();
+    let RenderedHttpRequest {
+        method,
+        uri,
+        headers,
+        query_params,
+        body,
+    } = RenderedHttpRequest::render(config, context);
 
     // Execute the HTTP request
     let response = client
-        .execute_request(&config.method, &uri, headers, body, query_params)
+        .execute_request(&method, &uri, headers, body, query_params)
         .await
         .map_err(|e| AppError::SubrequestFailed(e.to_string()))?;
 
@@ -324,11 +619,90 @@ async fn execute_http_subrequest(
         "client_id": client_id,
         "type": "http",
         "status": response.status,
-        "body": response.body,
+        "body": parse_body(&response.headers, response.body),
         "headers": response.headers,
     }))
 }
 
+/// HTTP subrequest with its URI, headers, query parameters and body interpolated
+#[derive(Debug, Clone, Serialize)]
+pub struct RenderedHttpRequest {
+    pub method: String,
+    pub uri: String,
+    pub headers: HashMap<String, String>,
+    pub query_params: HashMap<String, String>,
+    pub body: Option<String>,
+}
+
+impl RenderedHttpRequest {
+    /// Interpolate an HTTP subrequest against the context
+    pub fn render(
+        config: &crate::config::HttpSubrequestConfig,
+        context: &InterpolationContext,
+    ) -> Self {
+        Self {
+            method: config.method.clone(),
+            uri: context.interpolate(&config.uri),
+            headers: config
+                .headers
+                .iter()
+                .map(|(k, v)| (k.clone(), context.interpolate(v)))
+                .collect(),
+            query_params: config
+                .query_params
+                .iter()
+                .map(|(k, v)| (k.clone(), context.interpolate(v)))
+                .collect(),
+            body: config.body.as_ref().map(|b| context.interpolate(b)),
+        }
+    }
+}
+
+/// Render a subrequest as it would be sent, without executing it
+pub fn render_subrequest(config: &SubrequestTypeConfig, context: &InterpolationContext) -> Value {
+    match config {
+        SubrequestTypeConfig::Http(http_config) => {
+            json!(RenderedHttpRequest::render(http_config, context))
+        }
+        SubrequestTypeConfig::Postgres(sql_config)
+        | SubrequestTypeConfig::Mysql(sql_config)
+        | SubrequestTypeConfig::Sqlite(sql_config) => {
+            let (query, params) = render_sql(sql_config, context);
+            json!({ "query": query, "params": params })
+        }
+        SubrequestTypeConfig::Mongodb(mongo_config) => json!({
+            "collection": mongo_config.collection,
+            "operation": interpolate_mongo_operation(&mongo_config.operation, context),
+        }),
+        SubrequestTypeConfig::Redis(redis_config) => json!({
+            "operation": interpolate_redis_operation(&redis_config.operation, context),
+        }),
+    }
+}
+
+/// Parse a response body according to its Content-Type: JSON media types
+/// (`application/json`, `application/*+json`) become structured values,
+/// anything else (or invalid JSON) is kept as a string
+fn parse_body(headers: &HashMap<String, String>, body: String) -> Value {
+    let is_json = headers
+        .get("content-type")
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|media_type| {
+            let media_type = media_type.trim().to_ascii_lowercase();
+            media_type == "application/json" || media_type.ends_with("+json")
+        })
+        .unwrap_or(false);
+
+    if is_json && !body.is_empty() {
+        if let Ok(value) = serde_json::from_str(&body) {
+            return value;
+        }
+        debug!("Upstream body declared as JSON could not be parsed, keeping it as text");
+    }
+
+    Value::String(body)
+}
+
 /// Execute a SQL subrequest
 async fn execute_sql_subrequest(
     client_manager: &ClientManager,
@@ -340,15 +714,7 @@ async fn execute_sql_subrequest(
         .get_sql_client(client_id)
         .ok_or_else(|| AppError::ClientNotFound(client_id.to_string()))?;
 
-    // Interpolate query
-    let query = context.interpolate(&config.query);
-
-    // Interpolate parameters
-    let params: Vec<String> = config
-        .params
-        .iter()
-        .map(|p| context.interpolate(p))
-        .collect();
+    let (query, params) = render_sql(config, context);
 
     // Execute the query
     let response = client
@@ -364,6 +730,20 @@ async fn execute_sql_subrequest(
     }))
 }
 
+/// Interpolate a SQL query and its parameters
+fn render_sql(
+    config: &SqlSubrequestConfig,
+    context: &InterpolationContext,
+) -> (String, Vec<String>) {
+    let query = context.interpolate(&config.query);
+    let params = config
+        .params
+        .iter()
+        .map(|p| context.interpolate(p))
+        .collect();
+    (query, params)
+}
+
 /// Execute a MongoDB subrequest
 async fn execute_mongodb_subrequest(
     client_manager: &ClientManager,
@@ -498,14 +878,22 @@ pub enum AppError {
     SubrequestFailed(String),
 
     #[error("Invalid configuration: {0}")]
-    #[allow(dead_code)]
     InvalidConfig(String),
 
-    #[error("Route not found")]
-    RouteNotFound,
-
     #[error("Circular dependency detected in subrequests")]
     CircularDependency,
+
+    #[error("{} subrequest(s) failed", .0.len())]
+    PartialFailure(Vec<SubrequestFailure>),
+
+    #[error("Gateway timeout: {0}")]
+    GatewayTimeout(String),
+
+    #[error("Service overloaded: {0}")]
+    Overloaded(String),
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
 }
 
 impl IntoResponse for AppError {
@@ -514,19 +902,26 @@ impl IntoResponse for AppError {
             AppError::ClientNotFound(ref msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
             AppError::SubrequestFailed(ref msg) => (StatusCode::BAD_GATEWAY, msg.clone()),
             AppError::InvalidConfig(ref msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
-            AppError::RouteNotFound => (StatusCode::NOT_FOUND, "Route not found".to_string()),
             AppError::CircularDependency => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Circular dependency detected in subrequests".to_string(),
             ),
+            AppError::PartialFailure(_) => (StatusCode::BAD_GATEWAY, self.to_string()),
+            AppError::GatewayTimeout(_) => (StatusCode::GATEWAY_TIMEOUT, self.to_string()),
+            AppError::Overloaded(_) => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            AppError::InvalidRequest(_) => (StatusCode::BAD_REQUEST, self.to_string()),
         };
 
         error!("Request failed: {}", error_message);
 
-        let body = json!({
+        let mut body = json!({
             "error": error_message,
         });
 
+        if let AppError::PartialFailure(failures) = &self {
+            body["errors"] = json!(failures);
+        }
+
         (status, axum::Json(body)).into_response()
     }
 }
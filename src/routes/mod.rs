@@ -1,11 +1,31 @@
 pub mod handler;
+pub mod plan;
+pub mod proxy;
+pub mod response;
 
+use crate::config::{AuthPolicy, RouteConfig};
+use crate::middleware::cache::{
+    create_cache_middleware, create_invalidation_middleware, CacheConfig,
+};
+use crate::middleware::client_ip::ClientIpResolver;
+use crate::middleware::concurrency::{create_concurrency_middleware, ConcurrencyLimiter};
+use crate::middleware::conditional::{create_conditional_middleware, EtagTracker};
+use crate::middleware::quota::create_quota_middleware;
+use crate::middleware::rate_limit::{create_rate_limit_middleware, create_rate_limiter};
+use crate::middleware::security::{
+    create_auth_middleware, create_ip_filter_middleware, Authenticator, IpFilter,
+};
 use axum::{
-    routing::{any, get},
-    Router,
+    handler::Handler,
+    middleware,
+    routing::{any, get, on, MethodRouter},
+    Extension, Router,
 };
 use handler::AppState;
-use tracing::debug;
+use proxy::ProxyTarget;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tracing::{debug, warn};
 
 /// Build the router from configuration
 pub fn build_router(state: AppState) -> Router {
@@ -18,15 +38,1307 @@ pub fn build_router(state: AppState) -> Router {
         .route("/ready", get(crate::health::readiness_check))
         .route("/metrics", get(crate::middleware::metrics::metrics_handler));
 
-    // Register each route from configuration
+    // Group routes by path so that several methods can share the same path,
+    // each one carrying its own RouteConfig. Axum answers unmatched methods
+    // with 405 Method Not Allowed and an Allow header.
+    let mut method_routers: BTreeMap<String, MethodRouter<AppState>> = BTreeMap::new();
+    let etags = Arc::new(EtagTracker::default());
+    let authenticator = Arc::new(Authenticator::new(&config.server.security));
+    let default_auth = config.server.security.default_policy();
+
     for route in &config.routes {
-        let path = route.path.clone();
-        debug!("Registering route: {} {}", route.method, path);
+        debug!("Registering route: {} {}", route.method, route.path);
+
+        let method_router = match &route.proxy {
+            Some(proxy_config) => match ProxyTarget::new(
+                proxy_config,
+                ClientIpResolver::new(&config.server.client_ip),
+            ) {
+                Ok(target) => route_method_router(route, proxy::handle_proxy)
+                    .map(|router| router.layer(Extension(Arc::new(target)))),
+                Err(e) => {
+                    warn!("Skipping proxy route {}: {}", route.path, e);
+                    continue;
+                }
+            },
+            None => route_method_router(route, handler::handle_route)
+                .map(|router| router.layer(Extension(Arc::new(route.clone())))),
+        };
+
+        let Some(method_router) = method_router else {
+            warn!(
+                "Skipping route {} with unsupported method: {}",
+                route.path, route.method
+            );
+            continue;
+        };
+
+        // The bulkhead sits inside the cache, so cached responses do not take a slot
+        let method_router = match &route.max_concurrent {
+            Some(limit) => {
+                let limiter = ConcurrencyLimiter::new(
+                    format!("route {} {}", route.method, route.path),
+                    limit,
+                );
+                method_router.layer(middleware::from_fn(create_concurrency_middleware(limiter)))
+            }
+            None => method_router,
+        };
+
+        let method_router = match &route.cache {
+            Some(cache_config) => {
+                let cache_config = CacheConfig::for_route(cache_config, &state.config.server);
+                let cache = state
+                    .caches
+                    .create(format!("{} {}", route.method, route.path), cache_config);
+                method_router.layer(middleware::from_fn(create_cache_middleware(cache)))
+            }
+            None => method_router,
+        };
+
+        // Successful responses of write routes invalidate the cache tags they declare
+        let method_router = match route.invalidate_tags.is_empty() {
+            true => method_router,
+            false => method_router.layer(middleware::from_fn(create_invalidation_middleware(
+                state.caches.clone(),
+                route.invalidate_tags.clone(),
+            ))),
+        };
+
+        // Conditional requests are evaluated outside the cache, so cached responses get 304s too.
+        // Proxy routes forward `If-Match` and leave it to the backend.
+        let method_router = method_router.layer(middleware::from_fn(
+            create_conditional_middleware(etags.clone(), route.proxy.is_none()),
+        ));
+
+        // Quotas are charged inside auth and rate limits, so calls rejected by
+        // either are not counted
+        let method_router = match &state.quotas {
+            Some(quotas) => {
+                method_router.layer(middleware::from_fn(create_quota_middleware(quotas.clone())))
+            }
+            None => method_router,
+        };
 
-        // For now, we'll use a simple any() matcher and filter by method in the handler
-        // A more sophisticated implementation would use proper method routing
-        router = router.route(&path, any(handler::handle_route));
+        // Requests over the route's rate limit are rejected before any other work
+        let method_router = match &route.rate_limit {
+            Some(rate_limit) => {
+                let limiter = create_rate_limiter(
+                    rate_limit,
+                    &state.config.server,
+                    &format!("{} {}", route.method, route.path),
+                );
+                method_router.layer(middleware::from_fn(create_rate_limit_middleware(limiter)))
+            }
+            None => method_router,
+        };
+
+        // Unauthenticated requests are rejected before they count against any limit
+        let method_router = match route.auth.as_ref().unwrap_or(&default_auth) {
+            AuthPolicy::None => method_router,
+            policy => method_router.layer(middleware::from_fn(create_auth_middleware(
+                authenticator.clone(),
+                policy.clone(),
+            ))),
+        };
+
+        // Clients outside the route's networks are rejected before anything else
+        let method_router = match &route.ip_filter {
+            Some(ip_filter) => {
+                method_router.layer(middleware::from_fn(create_ip_filter_middleware(
+                    IpFilter::new(ip_filter, ClientIpResolver::new(&config.server.client_ip)),
+                )))
+            }
+            None => method_router,
+        };
+
+        let merged = match method_routers.remove(&route.path) {
+            Some(existing) => existing.merge(method_router),
+            None => method_router,
+        };
+        method_routers.insert(route.path.clone(), merged);
+    }
+
+    for (path, method_router) in method_routers {
+        router = router.route(&path, method_router);
     }
 
     router.with_state(state)
 }
+
+/// Register a handler for the route's configured method
+fn route_method_router<H, T>(route: &RouteConfig, handler: H) -> Option<MethodRouter<AppState>>
+where
+    H: Handler<T, AppState>,
+    T: 'static,
+{
+    match route.method_filter() {
+        Some(filter) => Some(on(filter, handler)),
+        None if route.matches_any_method() => Some(any(handler)),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::ClientManager;
+    use crate::config::{Config, ExecutionMode, ResponseTransform, ServerConfig};
+    use crate::middleware::quota::QuotaManager;
+    use crate::middleware::quota_store::create_quota_store;
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use std::collections::HashMap;
+    use tower::ServiceExt;
+
+    fn route(method: &str, path: &str) -> RouteConfig {
+        RouteConfig {
+            method: method.to_string(),
+            path: path.to_string(),
+            subrequests: vec![],
+            response_transform: None,
+            execution_mode: ExecutionMode::Parallel,
+            traffic_split: None,
+            traffic_mirror: None,
+            proxy: None,
+            response: None,
+            on_error: crate::config::ErrorPolicy::FailFast,
+            merge: None,
+            timeout_ms: None,
+            max_concurrency: None,
+            cache: None,
+            invalidate_tags: vec![],
+            rate_limit: None,
+            max_concurrent: None,
+            auth: None,
+            ip_filter: None,
+        }
+    }
+
+    async fn test_router(routes: Vec<RouteConfig>) -> Router {
+        router_for(Config {
+            clients: HashMap::new(),
+            routes,
+            server: ServerConfig::default(),
+        })
+        .await
+    }
+
+    async fn yaml_router(yaml: &str) -> Router {
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        config.validate().unwrap();
+        router_for(config).await
+    }
+
+    async fn router_for(config: Config) -> Router {
+        let client_manager = ClientManager::from_config(&config).await.unwrap();
+        let quotas = match &config.server.quota {
+            Some(quota) => Some(Arc::new(QuotaManager::new(
+                quota,
+                &config.server,
+                create_quota_store(quota).await.unwrap(),
+            ))),
+            None => None,
+        };
+
+        build_router(AppState {
+            config: Arc::new(config),
+            client_manager: Arc::new(client_manager),
+            caches: Default::default(),
+            quotas,
+        })
+    }
+
+    fn templated_route(method: &str, path: &str, template: &str) -> RouteConfig {
+        RouteConfig {
+            response_transform: Some(ResponseTransform {
+                filter: None,
+                field_mappings: HashMap::new(),
+                include_fields: vec![],
+                exclude_fields: vec![],
+                template: Some(template.to_string()),
+            }),
+            ..route(method, path)
+        }
+    }
+
+    async fn body_json(response: axum::response::Response) -> serde_json::Value {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_to_matched_route() {
+        let router = test_router(vec![
+            templated_route("GET", "/first", r#"{"route": "first"}"#),
+            templated_route("GET", "/second/:id", r#"{"route": "${request.path.id}"}"#),
+            templated_route("POST", "/second/:id", r#"{"route": "post"}"#),
+        ])
+        .await;
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/second/42")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(body_json(response).await["route"], "42");
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/second/42")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(body_json(response).await["route"], "post");
+
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .uri("/first")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(body_json(response).await["route"], "first");
+    }
+
+    #[tokio::test]
+    async fn test_method_not_allowed() {
+        let router = test_router(vec![route("GET", "/items"), route("POST", "/items")]).await;
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/items")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/items")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let allow = response.headers()[header::ALLOW].to_str().unwrap();
+        assert!(allow.contains("GET"));
+        assert!(allow.contains("POST"));
+    }
+
+    /// Config with a dashboard route whose subrequests call an unreachable backend
+    fn failing_config(on_error: &str, extra: &str) -> String {
+        format!(
+            r#"
+clients:
+  down:
+    type: http
+    base_url: "http://127.0.0.1:1"
+    timeout: 2
+
+routes:
+  - method: GET
+    path: /dashboard
+    on_error: {on_error}
+    subrequests:
+      - name: profile
+        client_id: down
+        type: http
+        uri: /profile
+{extra}
+"#
+        )
+    }
+
+    async fn send_get(router: Router, uri: &str) -> (StatusCode, serde_json::Value) {
+        let response = router
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        (status, body_json(response).await)
+    }
+
+    #[tokio::test]
+    async fn test_fail_fast_aborts_route() {
+        let router = yaml_router(&failing_config("fail-fast", "")).await;
+        let (status, body) = send_get(router, "/dashboard").await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert!(body.get("errors").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_best_effort_reports_failures() {
+        let extra = r#"
+      - name: banner
+        client_id: down
+        type: http
+        uri: /banner
+        fallback:
+          text: "Hello ${request.query.name}"
+      - name: ads
+        client_id: down
+        type: http
+        uri: /ads
+        optional: true"#;
+        let router = yaml_router(&failing_config("best-effort", extra)).await;
+        let (status, body) = send_get(router, "/dashboard?name=Ada").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["count"], 1);
+        assert_eq!(body["subrequests"][0]["text"], "Hello Ada");
+
+        let errors = body["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0]["subrequest"], "profile");
+        assert_eq!(errors[0]["handling"], "failed");
+        assert_eq!(errors[1]["handling"], "fallback");
+        assert_eq!(errors[2]["handling"], "skipped");
+    }
+
+    #[tokio::test]
+    async fn test_collect_errors_fails_with_all_errors() {
+        let extra = r#"
+      - name: settings
+        client_id: down
+        type: http
+        uri: /settings"#;
+        let router = yaml_router(&failing_config("collect-errors", extra)).await;
+        let (status, body) = send_get(router, "/dashboard").await;
+
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body["errors"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_with_fail_fast() {
+        let config = failing_config("fail-fast", "").replace(
+            "        uri: /profile",
+            "        uri: /profile\n        fallback: {\"name\": \"anonymous\"}",
+        );
+        let router = yaml_router(&config).await;
+        let (status, body) = send_get(router, "/dashboard").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["subrequests"][0]["name"], "anonymous");
+        assert_eq!(body["errors"][0]["handling"], "fallback");
+    }
+
+    /// Serve a backend router on a random local port and return its base URL
+    async fn spawn_backend(backend: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, backend).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_for_each_fan_out() {
+        let backend =
+            Router::new()
+                .route(
+                    "/users",
+                    get(|| async {
+                        axum::Json(serde_json::json!([{"id": 1}, {"id": 2}, {"id": 3}]))
+                    }),
+                )
+                .route(
+                    "/users/:id",
+                    get(
+                        |axum::extract::Path(id): axum::extract::Path<u32>,
+                         axum::extract::Query(query): axum::extract::Query<
+                            HashMap<String, String>,
+                        >| async move {
+                            format!("user-{}-at-{}", id, query["pos"])
+                        },
+                    ),
+                );
+        let base_url = spawn_backend(backend).await;
+
+        let yaml = format!(
+            r#"
+clients:
+  api:
+    type: http
+    base_url: "{base_url}"
+
+routes:
+  - method: GET
+    path: /users
+    execution_mode: sequential
+    subrequests:
+      - name: list
+        client_id: api
+        type: http
+        uri: /users
+      - name: details
+        client_id: api
+        type: http
+        uri: /users/${{item.id}}
+        query_params:
+          pos: "${{index}}"
+        for_each: subrequest.list.body
+        for_each_concurrency: 2
+"#
+        );
+        let router = yaml_router(&yaml).await;
+        let (status, body) = send_get(router, "/users").await;
+
+        assert_eq!(status, StatusCode::OK);
+        let details = body["subrequests"][1].as_array().unwrap();
+        assert_eq!(details.len(), 3);
+        assert_eq!(details[0]["body"], "user-1-at-0");
+        assert_eq!(details[2]["body"], "user-3-at-2");
+    }
+
+    #[tokio::test]
+    async fn test_merge_parsed_json_bodies() {
+        let backend = Router::new()
+            .route(
+                "/users/:id",
+                get(|| async { axum::Json(serde_json::json!({"id": 7, "name": "Ada"})) }),
+            )
+            .route(
+                "/users/:id/settings",
+                get(|| async {
+                    (
+                        [("content-type", "application/vnd.api+json; charset=utf-8")],
+                        r#"{"theme": "dark"}"#,
+                    )
+                }),
+            )
+            .route("/motd", get(|| async { r#"{"not": "json"}"# }));
+        let base_url = spawn_backend(backend).await;
+
+        let yaml = format!(
+            r#"
+clients:
+  api:
+    type: http
+    base_url: "{base_url}"
+  down:
+    type: http
+    base_url: "http://127.0.0.1:1"
+    timeout: 2
+
+routes:
+  - method: GET
+    path: /profile/:id
+    execution_mode: sequential
+    on_error: best-effort
+    merge:
+      strategy: deep
+    subrequests:
+      - name: user
+        client_id: api
+        type: http
+        uri: /users/${{request.path.id}}
+      - name: settings
+        client_id: api
+        type: http
+        uri: /users/${{subrequest.user.body.id}}/settings
+      - name: motd
+        client_id: api
+        type: http
+        uri: /motd
+      - name: ads
+        client_id: down
+        type: http
+        uri: /ads
+        optional: true
+"#
+        );
+        let router = yaml_router(&yaml).await;
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .uri("/profile/7")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-partial-failure"], "ads");
+
+        // The plain-text motd body is not an object, so the deep merge ignores it
+        let body = body_json(response).await;
+        assert_eq!(
+            body,
+            serde_json::json!({"id": 7, "name": "Ada", "theme": "dark"})
+        );
+
+        let yaml = yaml.replace("strategy: deep", "strategy: keyed");
+        let (_, keyed) = send_get(yaml_router(&yaml).await, "/profile/7").await;
+        assert_eq!(keyed["user"]["id"], 7);
+        assert_eq!(keyed["settings"]["theme"], "dark");
+        assert_eq!(keyed["motd"], r#"{"not": "json"}"#);
+        assert!(keyed["ads"].is_null());
+    }
+
+    /// Config with a route calling a backend that answers after `delay_ms`
+    async fn slow_config(delay_ms: u64, route_extra: &str, subrequests: &str) -> String {
+        let backend = Router::new().route(
+            "/slow",
+            get(move || async move {
+                tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
+                "done"
+            }),
+        );
+        let base_url = spawn_backend(backend).await;
+
+        format!(
+            r#"
+clients:
+  api:
+    type: http
+    base_url: "{base_url}"
+
+routes:
+  - method: GET
+    path: /slow
+    execution_mode: sequential
+{route_extra}
+    subrequests:
+{subrequests}
+"#
+        )
+    }
+
+    #[tokio::test]
+    async fn test_subrequest_timeout_returns_gateway_timeout() {
+        let subrequests = r#"
+      - name: first
+        client_id: api
+        type: http
+        uri: /slow
+        timeout_ms: 50"#;
+        let router = yaml_router(&slow_config(500, "", subrequests).await).await;
+        let (status, body) = send_get(router, "/slow").await;
+
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert!(body["error"].as_str().unwrap().contains("first"));
+    }
+
+    #[tokio::test]
+    async fn test_route_deadline_shrinks_later_budgets() {
+        let subrequests = r#"
+      - name: first
+        client_id: api
+        type: http
+        uri: /slow
+      - name: second
+        client_id: api
+        type: http
+        uri: /slow
+        timeout_ms: 10000
+      - name: third
+        client_id: api
+        type: http
+        uri: /slow
+        optional: true"#;
+        let config = slow_config(150, "    timeout_ms: 250", subrequests).await;
+
+        let router = yaml_router(&config).await;
+        let (status, body) = send_get(router, "/slow").await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert!(body["error"].as_str().unwrap().contains("second"));
+
+        // Optional subrequests that run out of time are skipped
+        let config = config.replace("timeout_ms: 10000", "optional: true");
+        let router = yaml_router(&config).await;
+        let (status, body) = send_get(router, "/slow").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["count"], 1);
+        assert_eq!(body["errors"].as_array().unwrap().len(), 2);
+    }
+
+    /// Backend whose `/track/:name?delay=ms` endpoint answers after the delay with
+    /// the name, its completion order and the highest number of concurrent calls seen
+    async fn tracking_backend() -> String {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let completed = Arc::new(AtomicUsize::new(0));
+
+        let backend =
+            Router::new().route(
+                "/track/:name",
+                get(
+                    move |axum::extract::Path(name): axum::extract::Path<String>,
+                          axum::extract::Query(query): axum::extract::Query<
+                        HashMap<String, u64>,
+                    >| {
+                        let (in_flight, peak, completed) =
+                            (in_flight.clone(), peak.clone(), completed.clone());
+                        async move {
+                            let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                            peak.fetch_max(current, Ordering::SeqCst);
+                            let delay = query.get("delay").copied().unwrap_or(20);
+                            tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+                            in_flight.fetch_sub(1, Ordering::SeqCst);
+                            axum::Json(serde_json::json!({
+                                "name": name,
+                                "order": completed.fetch_add(1, Ordering::SeqCst),
+                                "peak": peak.load(Ordering::SeqCst),
+                            }))
+                        }
+                    },
+                ),
+            );
+        spawn_backend(backend).await
+    }
+
+    fn graph_config(base_url: &str, route_extra: &str, subrequests: &[&str]) -> String {
+        let subrequests: String = subrequests
+            .iter()
+            .map(|sr| format!("      - client_id: api\n        type: http\n{}\n", sr))
+            .collect();
+        format!(
+            r#"
+clients:
+  api:
+    type: http
+    base_url: "{base_url}"
+
+routes:
+  - method: GET
+    path: /graph
+{route_extra}
+    subrequests:
+{subrequests}"#
+        )
+    }
+
+    #[tokio::test]
+    async fn test_graph_starts_dependents_without_waiting_for_waves() {
+        let base_url = tracking_backend().await;
+        let config = graph_config(
+            &base_url,
+            "",
+            &[
+                "        name: slow\n        uri: /track/slow?delay=300",
+                "        name: fast\n        uri: /track/fast?delay=10",
+                "        name: after_fast\n        uri: /track/after_fast?delay=10\n        depends_on: [fast]",
+            ],
+        );
+        let (status, body) = send_get(yaml_router(&config).await, "/graph").await;
+
+        assert_eq!(status, StatusCode::OK);
+        let results = body["subrequests"].as_array().unwrap();
+        assert_eq!(results[0]["body"]["name"], "slow");
+        assert!(
+            results[2]["body"]["order"].as_u64().unwrap()
+                < results[0]["body"]["order"].as_u64().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_graph_max_concurrency() {
+        let base_url = tracking_backend().await;
+        let subrequests: Vec<String> = (0..5)
+            .map(|i| format!("        name: s{i}\n        uri: /track/s{i}?delay=50"))
+            .collect();
+        let subrequests: Vec<&str> = subrequests.iter().map(String::as_str).collect();
+        let config = graph_config(&base_url, "    max_concurrency: 2", &subrequests);
+        let (status, body) = send_get(yaml_router(&config).await, "/graph").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["count"], 5);
+        let peak = body["subrequests"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["body"]["peak"].as_u64().unwrap())
+            .max();
+        assert_eq!(peak, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_sequential_mode_honours_depends_on() {
+        let base_url = tracking_backend().await;
+        let config = graph_config(
+            &base_url,
+            "    execution_mode: sequential",
+            &[
+                "        name: first\n        uri: /track/first\n        depends_on: [second]",
+                "        name: second\n        uri: /track/second",
+            ],
+        );
+        let (_, body) = send_get(yaml_router(&config).await, "/graph").await;
+
+        let results = body["subrequests"].as_array().unwrap();
+        assert_eq!(results[0]["body"]["order"], 1);
+        assert_eq!(results[1]["body"]["order"], 0);
+        assert_eq!(results[0]["body"]["peak"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_skipped_dependencies() {
+        let base_url = tracking_backend().await;
+        let config = graph_config(
+            &base_url,
+            "",
+            &[
+                "        name: admin\n        uri: /track/admin\n        condition:\n          type: headerexists\n          header: X-Admin",
+                "        name: audit\n        uri: /track/audit\n        depends_on: [admin]",
+                "        name: report\n        uri: /track/report\n        depends_on: [audit]",
+                "        name: summary\n        uri: /track/summary\n        depends_on: [admin]\n        on_dependency_skipped: run",
+            ],
+        );
+        let (status, body) = send_get(yaml_router(&config).await, "/graph").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["count"], 1);
+        assert_eq!(body["subrequests"][0]["body"]["name"], "summary");
+    }
+
+    #[tokio::test]
+    async fn test_route_cache() {
+        let base_url = tracking_backend().await;
+        let config = graph_config(
+            &base_url,
+            "    cache:\n      ttl_seconds: 30\n      key:\n        query_params: [q]",
+            &["        uri: /track/cached"],
+        );
+        let router = yaml_router(&config).await;
+
+        let send = |uri: &'static str| {
+            let router = router.clone();
+            async move {
+                let response = router
+                    .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let cache_status = response.headers()["x-cache"].to_str().unwrap().to_string();
+                let body = body_json(response).await;
+                (
+                    cache_status,
+                    body["subrequests"][0]["body"]["order"].clone(),
+                )
+            }
+        };
+
+        assert_eq!(send("/graph?q=a").await, ("MISS".to_string(), 0.into()));
+        assert_eq!(
+            send("/graph?q=a&utm=1").await,
+            ("HIT".to_string(), 0.into())
+        );
+        assert_eq!(send("/graph?q=b").await, ("MISS".to_string(), 1.into()));
+    }
+
+    #[tokio::test]
+    async fn test_subrequest_result_cache() {
+        let base_url = tracking_backend().await;
+        let config = format!(
+            r#"
+clients:
+  api:
+    type: http
+    base_url: "{base_url}"
+
+routes:
+  - method: GET
+    path: /first/:id
+    subrequests:
+      - name: shared
+        client_id: api
+        type: http
+        uri: /track/${{request.path.id}}
+        cache:
+          ttl_seconds: 30
+  - method: GET
+    path: /second/:id
+    subrequests:
+      - name: shared
+        client_id: api
+        type: http
+        uri: /track/${{request.path.id}}
+        cache:
+          ttl_seconds: 30
+  - method: GET
+    path: /keyed
+    subrequests:
+      - name: keyed
+        client_id: api
+        type: http
+        uri: /track/keyed?delay=${{request.query.delay}}
+        cache:
+          key: "keyed"
+"#
+        );
+        let router = yaml_router(&config).await;
+        let order = |body: serde_json::Value| body["subrequests"][0]["body"]["order"].clone();
+
+        let (_, first) = send_get(router.clone(), "/first/a").await;
+        let (_, second) = send_get(router.clone(), "/second/a").await;
+        assert_eq!(order(first), order(second));
+
+        let (_, other) = send_get(router.clone(), "/second/b").await;
+        assert_eq!(order(other), serde_json::json!(1));
+
+        let (_, keyed) = send_get(router.clone(), "/keyed?delay=1").await;
+        let (_, same_key) = send_get(router, "/keyed?delay=2").await;
+        assert_eq!(order(keyed), serde_json::json!(2));
+        assert_eq!(order(same_key), serde_json::json!(2));
+    }
+
+    #[tokio::test]
+    async fn test_route_rate_limit() {
+        let router = yaml_router(
+            r#"
+clients: {}
+routes:
+  - method: GET
+    path: /expensive
+    rate_limit:
+      requests_per_second: 1
+      burst_size: 1
+  - method: GET
+    path: /cheap
+"#,
+        )
+        .await;
+
+        let (status, _) = send_get(router.clone(), "/expensive").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send_get(router.clone(), "/expensive").await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+        // Other routes keep their own budget
+        let (status, _) = send_get(router, "/cheap").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_route_auth_policies() {
+        let router = yaml_router(
+            r#"
+clients: {}
+routes:
+  - method: GET
+    path: /default
+  - method: GET
+    path: /public
+    auth: none
+  - method: GET
+    path: /either
+    auth:
+      any_of: [api_key, jwt]
+server:
+  security:
+    api_keys:
+      keys: ["secret"]
+    jwt:
+      secret: "jwt-secret"
+"#,
+        )
+        .await;
+
+        let token = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &serde_json::json!({"sub": "user-1", "exp": 4102444800u64}),
+            &jsonwebtoken::EncodingKey::from_secret(b"jwt-secret"),
+        )
+        .unwrap();
+        let status = |path: &str, headers: &[(&str, &str)]| {
+            let mut builder = Request::builder().uri(path);
+            for (name, value) in headers {
+                builder = builder.header(*name, *value);
+            }
+            let request = builder.body(Body::empty()).unwrap();
+            let router = router.clone();
+            async move { router.oneshot(request).await.unwrap() }
+        };
+        let bearer = format!("Bearer {}", token);
+
+        assert_eq!(status("/public", &[]).await.status(), StatusCode::OK);
+
+        // Without a policy of their own, routes need every configured credential
+        let response = status("/default", &[("x-api-key", "secret")]).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        let response = status(
+            "/default",
+            &[("x-api-key", "secret"), ("authorization", &bearer)],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert_eq!(
+            status("/either", &[("x-api-key", "secret")]).await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status("/either", &[("authorization", &bearer)])
+                .await
+                .status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status("/either", &[("x-api-key", "wrong")]).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_route_quota_is_charged_after_auth() {
+        let router = yaml_router(
+            r#"
+clients: {}
+routes:
+  - method: GET
+    path: /partner
+  - method: GET
+    path: /strict
+    auth:
+      all_of: [api_key, jwt]
+server:
+  security:
+    api_keys:
+      keys: ["partner"]
+    jwt:
+      secret: "jwt-secret"
+    default_auth: api_key
+  quota:
+    daily: 1
+"#,
+        )
+        .await;
+
+        let status = |path: &'static str| {
+            let request = Request::builder()
+                .uri(path)
+                .header("x-api-key", "partner")
+                .body(Body::empty())
+                .unwrap();
+            let router = router.clone();
+            async move { router.oneshot(request).await.unwrap().status() }
+        };
+
+        // Calls rejected by auth do not use up the quota
+        assert_eq!(status("/strict").await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status("/strict").await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status("/partner").await, StatusCode::OK);
+        assert_eq!(status("/partner").await, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_route_ip_filter() {
+        let router = yaml_router(
+            r#"
+clients: {}
+routes:
+  - method: GET
+    path: /open
+  - method: GET
+    path: /internal
+    ip_filter:
+      allowlist: ["10.0.0.0/8", "2001:db8::/32"]
+server:
+  client_ip:
+    trusted_proxies: ["192.0.2.1"]
+"#,
+        )
+        .await;
+
+        let status = |path: &str, peer: &str, forwarded_for: Option<&str>| {
+            let mut builder = Request::builder().uri(path);
+            if let Some(forwarded_for) = forwarded_for {
+                builder = builder.header("x-forwarded-for", forwarded_for);
+            }
+            let mut request = builder.body(Body::empty()).unwrap();
+            request.extensions_mut().insert(axum::extract::ConnectInfo(
+                peer.parse::<std::net::SocketAddr>().unwrap(),
+            ));
+            let router = router.clone();
+            async move { router.oneshot(request).await.unwrap().status() }
+        };
+
+        assert_eq!(
+            status("/open", "203.0.113.9:1234", None).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status("/internal", "10.1.2.3:1234", None).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status("/internal", "[2001:db8::1]:1234", None).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status("/internal", "203.0.113.9:1234", None).await,
+            StatusCode::FORBIDDEN
+        );
+
+        // Behind the trusted proxy, the forwarded client is filtered
+        assert_eq!(
+            status("/internal", "192.0.2.1:1234", Some("10.1.2.3")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status("/internal", "192.0.2.1:1234", Some("203.0.113.9")).await,
+            StatusCode::FORBIDDEN
+        );
+        // Untrusted peers cannot claim another address
+        assert_eq!(
+            status("/internal", "203.0.113.9:1234", Some("10.1.2.3")).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limits_shed_load() {
+        let backend = Router::new().route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                "done"
+            }),
+        );
+        let base_url = spawn_backend(backend).await;
+        let router = yaml_router(&format!(
+            r#"
+clients:
+  api:
+    type: http
+    base_url: "{base_url}"
+  limited:
+    type: http
+    base_url: "{base_url}"
+    max_concurrent:
+      limit: 1
+routes:
+  - method: GET
+    path: /route-limit
+    max_concurrent:
+      limit: 1
+    subrequests:
+      - client_id: api
+        type: http
+        uri: /slow
+  - method: GET
+    path: /client-limit
+    subrequests:
+      - client_id: limited
+        type: http
+        uri: /slow
+"#
+        ))
+        .await;
+
+        for path in ["/route-limit", "/client-limit"] {
+            let (first, second) = tokio::join!(
+                send_get(router.clone(), path),
+                send_get(router.clone(), path)
+            );
+            let mut statuses = [first.0, second.0];
+            statuses.sort();
+            assert_eq!(
+                statuses,
+                [StatusCode::OK, StatusCode::SERVICE_UNAVAILABLE],
+                "{}",
+                path
+            );
+
+            // The slot is free again once the first request is done
+            let (status, _) = send_get(router.clone(), path).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_route_cache_honours_upstream_no_store() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let backend = Router::new().route(
+            "/private",
+            get(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async { ([(header::CACHE_CONTROL, "no-store")], "secret") }
+            }),
+        );
+        let base_url = spawn_backend(backend).await;
+        let config = graph_config(&base_url, "    cache: {}", &["        uri: /private"]);
+        let router = yaml_router(&config).await;
+
+        send_get(router.clone(), "/graph").await;
+        send_get(router, "/graph").await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_write_routes_invalidate_cache_tags() {
+        let base_url = tracking_backend().await;
+        let config = format!(
+            r#"
+clients:
+  api:
+    type: http
+    base_url: "{base_url}"
+
+routes:
+  - method: GET
+    path: /users/:id
+    cache:
+      tags: ["user:${{request.path.id}}"]
+    subrequests:
+      - client_id: api
+        type: http
+        uri: /track/${{request.path.id}}
+  - method: PUT
+    path: /users/:id
+    invalidate_tags: ["user:${{request.path.id}}"]
+    subrequests:
+      - client_id: api
+        type: http
+        uri: /track/update
+"#
+        );
+        let router = yaml_router(&config).await;
+
+        let send = |method: &'static str, uri: &'static str| {
+            let router = router.clone();
+            async move {
+                let response = router
+                    .oneshot(
+                        Request::builder()
+                            .method(method)
+                            .uri(uri)
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                response
+                    .headers()
+                    .get("x-cache")
+                    .map(|value| value.to_str().unwrap().to_string())
+            }
+        };
+
+        send("GET", "/users/1").await;
+        send("GET", "/users/2").await;
+        assert_eq!(send("GET", "/users/1").await.as_deref(), Some("HIT"));
+
+        send("PUT", "/users/1").await;
+        assert_eq!(send("GET", "/users/1").await.as_deref(), Some("MISS"));
+        assert_eq!(send("GET", "/users/2").await.as_deref(), Some("HIT"));
+    }
+
+    #[tokio::test]
+    async fn test_conditional_requests() {
+        let base_url = tracking_backend().await;
+        let config = format!(
+            r#"
+clients:
+  api:
+    type: http
+    base_url: "{base_url}"
+
+routes:
+  - method: GET
+    path: /users/:id
+    subrequests:
+      - client_id: api
+        type: http
+        uri: /track/user?delay=0
+    response_transform:
+      include_fields: [count]
+  - method: PUT
+    path: /users/:id
+    subrequests:
+      - client_id: api
+        type: http
+        uri: /track/update?delay=0
+"#
+        );
+        let router = yaml_router(&config).await;
+
+        let send_to = |method: &'static str,
+                       uri: &'static str,
+                       header: Option<(header::HeaderName, String)>| {
+            let router = router.clone();
+            async move {
+                let mut request = Request::builder().method(method).uri(uri);
+                if let Some((name, value)) = header {
+                    request = request.header(name, value);
+                }
+                router
+                    .oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap()
+            }
+        };
+        let send = |method: &'static str, header: Option<(header::HeaderName, String)>| {
+            send_to(method, "/users/1", header)
+        };
+
+        // Resources the gateway has not read cannot be verified, so they fail closed
+        let response = send_to("PUT", "/users/2", Some((header::IF_MATCH, "*".to_string()))).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+        let response = send("GET", None).await;
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(etag.starts_with('"'));
+
+        let response = send("GET", Some((header::IF_NONE_MATCH, etag.clone()))).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag.as_str());
+
+        let response = send("PUT", Some((header::IF_MATCH, "\"stale\"".to_string()))).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+        let response = send("PUT", Some((header::IF_MATCH, etag.clone()))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The resource changed, so its old tag no longer satisfies If-Match
+        let response = send("PUT", Some((header::IF_MATCH, etag))).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+        // Concurrent writes with the same tag are serialized: only one succeeds
+        let response = send("GET", None).await;
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let (first, second) = tokio::join!(
+            send("PUT", Some((header::IF_MATCH, etag.clone()))),
+            send("PUT", Some((header::IF_MATCH, etag)))
+        );
+        let mut statuses = [first.status(), second.status()];
+        statuses.sort();
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::PRECONDITION_FAILED]);
+    }
+}
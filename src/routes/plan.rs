@@ -0,0 +1,414 @@
+use crate::conditions::evaluate_condition;
+use crate::config::{
+    Condition, DependencyPolicy, ErrorPolicy, ExecutionMode, RouteConfig, SubrequestConfig,
+};
+use crate::interpolation::InterpolationContext;
+use crate::routes::handler::render_subrequest;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Execution plan of a route: its subrequests and their dependency graph
+#[derive(Debug, Clone, Serialize)]
+pub struct RoutePlan {
+    pub method: String,
+    pub path: String,
+    pub execution_mode: ExecutionMode,
+    pub max_concurrency: Option<usize>,
+    pub timeout_ms: Option<u64>,
+    pub on_error: ErrorPolicy,
+    pub subrequests: Vec<PlanNode>,
+    /// Subrequests grouped by dependency depth; each level can start once the previous ones finish
+    pub levels: Vec<Vec<String>>,
+    /// Graphviz DOT rendering of the dependency graph
+    pub dot: String,
+}
+
+/// A subrequest in the execution plan
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanNode {
+    pub index: usize,
+    /// Subrequest name, or `#<index>` for unnamed subrequests
+    pub label: String,
+    pub client_id: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub depends_on: Vec<String>,
+    pub level: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub condition: Option<Condition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub for_each: Option<String>,
+    pub optional: bool,
+    pub has_fallback: bool,
+    pub on_dependency_skipped: DependencyPolicy,
+}
+
+/// Sample request evaluated by a dry run
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DryRunRequest {
+    /// Request method (defaults to the route method, or GET for ANY routes)
+    #[serde(default)]
+    pub method: Option<String>,
+    #[serde(default)]
+    pub path_params: HashMap<String, String>,
+    #[serde(default)]
+    pub query: HashMap<String, String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Request body: a string is used as-is, other JSON values are serialized
+    #[serde(default)]
+    pub body: Option<Value>,
+    /// Simulated subrequest results, by name, made available to later subrequests
+    #[serde(default)]
+    pub results: HashMap<String, Value>,
+}
+
+/// Outcome of evaluating one subrequest in a dry run
+#[derive(Debug, Clone, Serialize)]
+pub struct DryRunStep {
+    pub index: usize,
+    pub label: String,
+    pub client_id: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub execute: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip_reason: Option<&'static str>,
+    /// Number of for_each items, when the subrequest fans out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub for_each_items: Option<usize>,
+    /// Rendered request (for fan-out subrequests, rendered with the first item)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request: Option<Value>,
+}
+
+/// Build the execution plan of a route
+pub fn route_plan(route: &RouteConfig) -> anyhow::Result<RoutePlan> {
+    let dependencies = route.dependencies()?;
+    let labels: Vec<String> = route
+        .subrequests
+        .iter()
+        .enumerate()
+        .map(|(idx, subrequest)| label(idx, subrequest))
+        .collect();
+
+    let order = start_order(&dependencies)?;
+    let mut node_levels = vec![0; route.subrequests.len()];
+    for &idx in &order {
+        node_levels[idx] = dependencies[idx]
+            .iter()
+            .map(|&dep| node_levels[dep] + 1)
+            .max()
+            .unwrap_or(0);
+    }
+
+    let mut levels: Vec<Vec<String>> = Vec::new();
+    for (idx, &level) in node_levels.iter().enumerate() {
+        if levels.len() <= level {
+            levels.resize(level + 1, Vec::new());
+        }
+        levels[level].push(labels[idx].clone());
+    }
+
+    let subrequests: Vec<PlanNode> = route
+        .subrequests
+        .iter()
+        .enumerate()
+        .map(|(idx, subrequest)| PlanNode {
+            index: idx,
+            label: labels[idx].clone(),
+            client_id: subrequest.client_id.clone(),
+            kind: subrequest.config.kind(),
+            depends_on: subrequest.depends_on.clone(),
+            level: node_levels[idx],
+            condition: subrequest.condition.clone(),
+            for_each: subrequest.for_each.clone(),
+            optional: subrequest.optional,
+            has_fallback: subrequest.fallback.is_some(),
+            on_dependency_skipped: subrequest.on_dependency_skipped,
+        })
+        .collect();
+
+    let dot = to_dot(route, &subrequests);
+
+    Ok(RoutePlan {
+        method: route.method.clone(),
+        path: route.path.clone(),
+        execution_mode: route.execution_mode,
+        max_concurrency: route.max_concurrency,
+        timeout_ms: route.timeout_ms,
+        on_error: route.on_error,
+        subrequests,
+        levels,
+        dot,
+    })
+}
+
+/// Evaluate a sample request against a route without calling any backend:
+/// conditions, dependency skips, for_each sources and rendered requests
+pub fn dry_run(route: &RouteConfig, request: DryRunRequest) -> anyhow::Result<Vec<DryRunStep>> {
+    let dependencies = route.dependencies()?;
+    let order = start_order(&dependencies)?;
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in &request.headers {
+        let name = HeaderName::from_bytes(name.as_bytes())?;
+        headers.insert(name, HeaderValue::from_str(value)?);
+    }
+
+    let method = match &request.method {
+        Some(method) => method.to_uppercase(),
+        None if route.matches_any_method() => "GET".to_string(),
+        None => route.method.to_uppercase(),
+    };
+    let body = request.body.map(|body| match body {
+        Value::String(body) => body,
+        other => other.to_string(),
+    });
+
+    let mut context = InterpolationContext::new(
+        headers,
+        request.path_params,
+        request.query,
+        body,
+        Method::from_bytes(method.as_bytes())?,
+    );
+
+    // Whether each subrequest would run; dependencies without a simulated result still count as run
+    let mut executes = vec![false; route.subrequests.len()];
+    let mut steps = Vec::with_capacity(route.subrequests.len());
+
+    for idx in order {
+        let subrequest = &route.subrequests[idx];
+        let mut step = DryRunStep {
+            index: idx,
+            label: label(idx, subrequest),
+            client_id: subrequest.client_id.clone(),
+            kind: subrequest.config.kind(),
+            execute: false,
+            skip_reason: None,
+            for_each_items: None,
+            request: None,
+        };
+
+        let dependency_skipped = dependencies[idx].iter().any(|&dep| !executes[dep]);
+        if dependency_skipped && subrequest.on_dependency_skipped == DependencyPolicy::Skip {
+            step.skip_reason = Some("dependency skipped");
+        } else if subrequest
+            .condition
+            .as_ref()
+            .is_some_and(|condition| !evaluate_condition(condition, &context))
+        {
+            step.skip_reason = Some("condition not met");
+        } else {
+            step.execute = true;
+            executes[idx] = true;
+
+            let item = match &subrequest.for_each {
+                Some(source) => {
+                    let items = match context.resolve_value(source) {
+                        Some(Value::Array(items)) => items,
+                        Some(Value::String(raw)) => match serde_json::from_str(&raw) {
+                            Ok(Value::Array(items)) => items,
+                            _ => Vec::new(),
+                        },
+                        _ => Vec::new(),
+                    };
+                    step.for_each_items = Some(items.len());
+                    items.into_iter().next()
+                }
+                None => None,
+            };
+
+            let rendered = match item {
+                Some(item) => render_subrequest(&subrequest.config, &context.with_item(0, item)),
+                None => render_subrequest(&subrequest.config, &context),
+            };
+            step.request = Some(rendered);
+
+            if let Some(name) = &subrequest.name {
+                if let Some(result) = request.results.get(name) {
+                    context.add_subrequest_result(name.clone(), result.clone());
+                }
+            }
+        }
+
+        steps.push(step);
+    }
+
+    steps.sort_by_key(|step| step.index);
+    Ok(steps)
+}
+
+/// Order in which subrequests start when run one at a time: the first ready
+/// subrequest in declaration order goes next
+fn start_order(dependencies: &[Vec<usize>]) -> anyhow::Result<Vec<usize>> {
+    let mut done = vec![false; dependencies.len()];
+    let mut order = Vec::with_capacity(dependencies.len());
+
+    while order.len() < dependencies.len() {
+        let next = (0..dependencies.len())
+            .find(|&idx| !done[idx] && dependencies[idx].iter().all(|&dep| done[dep]))
+            .ok_or_else(|| anyhow::anyhow!("Circular dependency detected in subrequests"))?;
+        done[next] = true;
+        order.push(next);
+    }
+
+    Ok(order)
+}
+
+fn label(idx: usize, subrequest: &SubrequestConfig) -> String {
+    subrequest
+        .name
+        .clone()
+        .unwrap_or_else(|| format!("#{}", idx))
+}
+
+/// Render the dependency graph in Graphviz DOT format
+fn to_dot(route: &RouteConfig, nodes: &[PlanNode]) -> String {
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+
+    let mut dot = format!(
+        "digraph {} {{\n  rankdir=LR;\n",
+        quote(&format!("{} {}", route.method, route.path))
+    );
+
+    for node in nodes {
+        let mut details = format!("{} ({})", node.client_id, node.kind);
+        if node.condition.is_some() {
+            details.push_str("\\nconditional");
+        }
+        if let Some(for_each) = &node.for_each {
+            details.push_str(&format!("\\nfor_each {}", for_each));
+        }
+        let style = if node.optional || node.has_fallback {
+            ", style=dashed"
+        } else {
+            ""
+        };
+        dot.push_str(&format!(
+            "  {} [label={}{}];\n",
+            quote(&node.label),
+            quote(&format!("{}\\n{}", node.label, details)),
+            style
+        ));
+    }
+
+    for node in nodes {
+        for dep in &node.depends_on {
+            dot.push_str(&format!("  {} -> {};\n", quote(dep), quote(&node.label)));
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn route() -> RouteConfig {
+        let yaml = r#"
+clients:
+  api:
+    type: http
+    base_url: "https://api.example.com"
+  db:
+    type: postgres
+    connection_string: "postgres://localhost/test"
+
+routes:
+  - method: GET
+    path: /users/:id
+    subrequests:
+      - name: user
+        client_id: api
+        type: http
+        uri: /users/${request.path.id}
+        headers:
+          Authorization: '${request.headers["authorization"]}'
+      - name: orders
+        client_id: db
+        type: postgres
+        query: "SELECT * FROM orders WHERE user_id = $1"
+        params: ["${subrequest.user.body.id}"]
+        depends_on: [user]
+      - name: admin
+        client_id: api
+        type: http
+        uri: /admin/${request.path.id}
+        condition:
+          type: queryexists
+          param: admin
+      - name: audit
+        client_id: api
+        type: http
+        uri: /audit/${item.id}
+        depends_on: [admin, orders]
+        for_each: subrequest.orders.rows
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        config.validate().unwrap();
+        config.routes[0].clone()
+    }
+
+    #[test]
+    fn test_route_plan_levels_and_dot() {
+        let plan = route_plan(&route()).unwrap();
+
+        assert_eq!(
+            plan.levels,
+            vec![
+                vec!["user".to_string(), "admin".to_string()],
+                vec!["orders".to_string()],
+                vec!["audit".to_string()],
+            ]
+        );
+        assert_eq!(plan.subrequests[1].kind, "postgres");
+        assert_eq!(plan.subrequests[3].level, 2);
+        assert!(plan.dot.starts_with("digraph \"GET /users/:id\""));
+        assert!(plan.dot.contains("\"user\" -> \"orders\";"));
+        assert!(plan.dot.contains("\"orders\" -> \"audit\";"));
+    }
+
+    #[test]
+    fn test_dry_run_renders_requests() {
+        let request = DryRunRequest {
+            path_params: HashMap::from([("id".to_string(), "7".to_string())]),
+            headers: HashMap::from([("Authorization".to_string(), "Bearer t".to_string())]),
+            results: HashMap::from([
+                ("user".to_string(), serde_json::json!({"body": {"id": 70}})),
+                (
+                    "orders".to_string(),
+                    serde_json::json!({"rows": [{"id": 1}, {"id": 2}]}),
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        let steps = dry_run(&route(), request.clone()).unwrap();
+        assert_eq!(steps[0].request.as_ref().unwrap()["uri"], "/users/7");
+        assert_eq!(
+            steps[0].request.as_ref().unwrap()["headers"]["Authorization"],
+            "Bearer t"
+        );
+        assert_eq!(steps[1].request.as_ref().unwrap()["params"][0], "70");
+        assert!(!steps[2].execute);
+        assert_eq!(steps[2].skip_reason, Some("condition not met"));
+        assert_eq!(steps[3].skip_reason, Some("dependency skipped"));
+
+        // With the condition met, the fan-out is rendered with its first item
+        let request = DryRunRequest {
+            query: HashMap::from([("admin".to_string(), "1".to_string())]),
+            ..request
+        };
+        let steps = dry_run(&route(), request).unwrap();
+        assert!(steps[2].execute);
+        assert_eq!(steps[3].for_each_items, Some(2));
+        assert_eq!(steps[3].request.as_ref().unwrap()["uri"], "/audit/1");
+    }
+}
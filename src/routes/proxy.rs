@@ -0,0 +1,503 @@
+use crate::config::ProxyConfig;
+use crate::middleware::client_ip::{peer_ip, ClientIpResolver};
+use crate::routes::handler::{AppError, AppState};
+use axum::{
+    body::{Body, HttpBody},
+    extract::{Request, State},
+    http::HeaderMap,
+    response::Response,
+    Extension,
+};
+use regex::Regex;
+use std::net::IpAddr;
+use std::sync::Arc;
+use sync_wrapper::SyncStream;
+use tracing::debug;
+
+/// Hop-by-hop headers that only apply to a single connection and are never forwarded
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Largest request body buffered so that the request can be retried; larger
+/// bodies and bodies of unknown size are streamed
+const RETRYABLE_BODY_SIZE: u64 = 64 * 1024;
+
+/// Proxy target for a route, with its path rewrite compiled once at startup
+#[derive(Debug, Clone)]
+pub struct ProxyTarget {
+    client_id: String,
+    strip_prefix: Option<String>,
+    rewrite: Option<(Regex, String)>,
+    client_ip: ClientIpResolver,
+}
+
+impl ProxyTarget {
+    /// Create a proxy target from configuration; `client_ip` tells which
+    /// peers may report the scheme the client used
+    pub fn new(config: &ProxyConfig, client_ip: ClientIpResolver) -> Result<Self, regex::Error> {
+        let rewrite = config
+            .rewrite
+            .as_ref()
+            .map(|rewrite| {
+                Regex::new(&rewrite.pattern).map(|regex| (regex, rewrite.replacement.clone()))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            client_id: config.client_id.clone(),
+            strip_prefix: config.strip_prefix.clone(),
+            rewrite,
+            client_ip,
+        })
+    }
+
+    /// Compute the upstream path for an incoming request path
+    pub fn upstream_path(&self, path: &str) -> String {
+        let mut upstream = match &self.strip_prefix {
+            // Only whole segments are stripped: `/api` does not strip `/apiv2`
+            Some(prefix) => match path.strip_prefix(prefix.as_str()) {
+                Some(rest) if rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/') => {
+                    rest
+                }
+                _ => path,
+            },
+            None => path,
+        }
+        .to_string();
+
+        if let Some((regex, replacement)) = &self.rewrite {
+            upstream = regex.replace(&upstream, replacement.as_str()).to_string();
+        }
+
+        if !upstream.starts_with('/') {
+            upstream.insert(0, '/');
+        }
+
+        upstream
+    }
+}
+
+/// Transparent reverse-proxy handler: streams the request to the backend and
+/// streams the upstream status, headers and body back unchanged
+pub async fn handle_proxy(
+    State(state): State<AppState>,
+    Extension(target): Extension<Arc<ProxyTarget>>,
+    request: Request,
+) -> Result<Response, AppError> {
+    let client = state
+        .client_manager
+        .get_http_client(&target.client_id)
+        .ok_or_else(|| AppError::ClientNotFound(target.client_id.clone()))?;
+
+    let peer = peer_ip(&request);
+    let (parts, body) = request.into_parts();
+
+    // Small bodies of known size are buffered so the request stays retryable
+    let upstream_body = match body.size_hint().exact() {
+        Some(size) if size <= RETRYABLE_BODY_SIZE => reqwest::Body::from(
+            axum::body::to_bytes(body, RETRYABLE_BODY_SIZE as usize)
+                .await
+                .map_err(|e| AppError::InvalidRequest(e.to_string()))?,
+        ),
+        _ => reqwest::Body::wrap_stream(SyncStream::new(body.into_data_stream())),
+    };
+
+    let permit = match state
+        .client_manager
+        .get_concurrency_limiter(&target.client_id)
+    {
+        Some(limiter) => Some(limiter.acquire().await.map_err(|rejection| {
+            AppError::Overloaded(format!("client {}: {}", target.client_id, rejection))
+        })?),
+        None => None,
+    };
+
+    let path = target.upstream_path(parts.uri.path());
+    let path_and_query = match parts.uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path,
+    };
+
+    debug!(
+        "Proxying {} {} to client {} as {}",
+        parts.method,
+        parts.uri.path(),
+        target.client_id,
+        path_and_query
+    );
+
+    let method = reqwest::Method::from_bytes(parts.method.as_str().as_bytes())
+        .map_err(|e| AppError::SubrequestFailed(e.to_string()))?;
+
+    let mut headers = upstream_request_headers(&parts.headers);
+    add_forwarded_headers(&mut headers, &parts.headers, peer, &target.client_ip);
+
+    let upstream = client
+        .proxy_request(method, &path_and_query, headers, upstream_body)
+        .await
+        .map_err(|e| AppError::SubrequestFailed(e.to_string()));
+
+    // The slot covers the upstream call until its response headers arrive
+    if let Some(permit) = permit {
+        let overloaded = upstream
+            .as_ref()
+            .map_or(true, |upstream| upstream.status().is_server_error());
+        permit.complete(overloaded);
+    }
+    let upstream = upstream?;
+
+    let mut response = Response::builder().status(upstream.status().as_u16());
+    let connection = connection_headers(
+        upstream
+            .headers()
+            .get_all(reqwest::header::CONNECTION)
+            .iter()
+            .map(|value| value.as_bytes()),
+    );
+    for (name, value) in upstream.headers() {
+        if !is_hop_by_hop(name.as_str(), &connection) {
+            response = response.header(name.as_str(), value.as_bytes());
+        }
+    }
+
+    response
+        .body(Body::from_stream(upstream.bytes_stream()))
+        .map_err(|e| AppError::SubrequestFailed(e.to_string()))
+}
+
+/// Convert incoming request headers for the upstream request, dropping
+/// hop-by-hop headers and the Host header (set from the backend URL)
+fn upstream_request_headers(headers: &HeaderMap) -> reqwest::header::HeaderMap {
+    let mut upstream = reqwest::header::HeaderMap::new();
+    let connection = connection_headers(
+        headers
+            .get_all(axum::http::header::CONNECTION)
+            .iter()
+            .map(|value| value.as_bytes()),
+    );
+
+    for (name, value) in headers {
+        if is_hop_by_hop(name.as_str(), &connection) || name == axum::http::header::HOST {
+            continue;
+        }
+
+        if let (Ok(name), Ok(value)) = (
+            reqwest::header::HeaderName::from_bytes(name.as_str().as_bytes()),
+            reqwest::header::HeaderValue::from_bytes(value.as_bytes()),
+        ) {
+            upstream.append(name, value);
+        }
+    }
+
+    upstream
+}
+
+/// Tell the backend who the client is: the peer is appended to
+/// `X-Forwarded-For` and `Forwarded`, and `X-Forwarded-Proto` is kept only
+/// when a trusted proxy set it
+fn add_forwarded_headers(
+    upstream: &mut reqwest::header::HeaderMap,
+    incoming: &HeaderMap,
+    peer: Option<IpAddr>,
+    client_ip: &ClientIpResolver,
+) {
+    let trusted_peer = peer.is_some_and(|peer| client_ip.is_trusted(peer));
+    let proto = incoming
+        .get("x-forwarded-proto")
+        .filter(|_| trusted_peer)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("http")
+        .to_string();
+    if let Ok(value) = reqwest::header::HeaderValue::from_str(&proto) {
+        upstream.insert("x-forwarded-proto", value);
+    }
+
+    let Some(peer) = peer.map(|peer| peer.to_canonical()) else {
+        return;
+    };
+    // IPv6 nodes of `Forwarded` are bracketed and quoted (RFC 7239)
+    let node = match peer {
+        IpAddr::V4(_) => peer.to_string(),
+        IpAddr::V6(_) => format!("\"[{}]\"", peer),
+    };
+    append_to_list(upstream, "x-forwarded-for", &peer.to_string());
+    append_to_list(
+        upstream,
+        "forwarded",
+        &format!("for={};proto={}", node, proto),
+    );
+}
+
+/// Append an element to a comma-separated list header, merging its instances
+fn append_to_list(headers: &mut reqwest::header::HeaderMap, name: &'static str, element: &str) {
+    let mut elements: Vec<&str> = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    elements.push(element);
+    if let Ok(value) = reqwest::header::HeaderValue::from_str(&elements.join(", ")) {
+        headers.insert(name, value);
+    }
+}
+
+/// Headers the `Connection` header names as hop-by-hop, lowercased
+fn connection_headers<'a>(values: impl Iterator<Item = &'a [u8]>) -> Vec<String> {
+    values
+        .filter_map(|value| std::str::from_utf8(value).ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+fn is_hop_by_hop(name: &str, connection: &[String]) -> bool {
+    HOP_BY_HOP_HEADERS.contains(&name) || connection.iter().any(|listed| listed == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PathRewrite;
+    use axum::http::HeaderValue;
+
+    fn target(strip_prefix: Option<&str>, rewrite: Option<(&str, &str)>) -> ProxyTarget {
+        ProxyTarget::new(
+            &ProxyConfig {
+                client_id: "backend".to_string(),
+                strip_prefix: strip_prefix.map(str::to_string),
+                rewrite: rewrite.map(|(pattern, replacement)| PathRewrite {
+                    pattern: pattern.to_string(),
+                    replacement: replacement.to_string(),
+                }),
+            },
+            ClientIpResolver::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_upstream_path_passthrough() {
+        let target = target(None, None);
+        assert_eq!(target.upstream_path("/users/1"), "/users/1");
+    }
+
+    #[test]
+    fn test_upstream_path_strip_prefix() {
+        let target = target(Some("/api"), None);
+        assert_eq!(target.upstream_path("/api/users/1"), "/users/1");
+        assert_eq!(target.upstream_path("/api"), "/");
+        assert_eq!(target.upstream_path("/other"), "/other");
+        // Prefixes match whole segments only
+        assert_eq!(target.upstream_path("/apiv2/users"), "/apiv2/users");
+    }
+
+    #[test]
+    fn test_upstream_path_strip_prefix_with_trailing_slash() {
+        let target = target(Some("/api/"), None);
+        assert_eq!(target.upstream_path("/api/users/1"), "/users/1");
+        assert_eq!(target.upstream_path("/apiv2/users"), "/apiv2/users");
+    }
+
+    #[test]
+    fn test_upstream_path_rewrite() {
+        let target = target(Some("/api"), Some(("^/v1/(.*)$", "/internal/$1")));
+        assert_eq!(target.upstream_path("/api/v1/users"), "/internal/users");
+        assert_eq!(target.upstream_path("/api/v2/users"), "/v2/users");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_streams_upstream_response() {
+        use crate::clients::ClientManager;
+        use crate::config::{
+            ClientConfig, Config, ExecutionMode, HttpClientConfig, RouteConfig, ServerConfig,
+        };
+        use axum::http::StatusCode;
+        use axum::response::IntoResponse;
+        use std::collections::HashMap;
+        use tower::ServiceExt;
+
+        // Backend echoing the path, forwarded client and request body as
+        // binary with a custom status, after failing the first call
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let backend = axum::Router::new().route(
+            "/files/*rest",
+            axum::routing::post({
+                let calls = calls.clone();
+                move |uri: axum::http::Uri, headers: HeaderMap, body: axum::body::Bytes| async move {
+                    if calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+                    }
+                    let mut payload = uri.to_string().into_bytes();
+                    payload.extend_from_slice(&body);
+                    (
+                        StatusCode::CREATED,
+                        [
+                            ("content-type", "application/octet-stream"),
+                            ("x-upstream", "yes"),
+                        ],
+                        [("x-forwarded-for", headers["x-forwarded-for"].clone())],
+                        payload,
+                    )
+                        .into_response()
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, backend).await.unwrap() });
+
+        let mut clients = HashMap::new();
+        clients.insert(
+            "backend".to_string(),
+            ClientConfig::Http(HttpClientConfig {
+                base_url: format!("http://{}", addr),
+                backends: vec![],
+                load_balance: None,
+                headers: HashMap::new(),
+                min_connections: 1,
+                max_connections: 10,
+                timeout: 5,
+                retry: Some(crate::config::RetryConfig {
+                    max_retries: 1,
+                    initial_backoff_ms: 10,
+                    max_backoff_ms: 100,
+                    retry_on_status: vec![503],
+                    respect_retry_after: true,
+                    jitter: Default::default(),
+                    idempotent_only: false,
+                    budget: None,
+                }),
+                circuit_breaker: None,
+                max_concurrent: None,
+            }),
+        );
+        let config = Config {
+            clients,
+            routes: vec![RouteConfig {
+                method: "POST".to_string(),
+                path: "/api/*rest".to_string(),
+                subrequests: vec![],
+                proxy: Some(ProxyConfig {
+                    client_id: "backend".to_string(),
+                    strip_prefix: Some("/api".to_string()),
+                    rewrite: None,
+                }),
+                response_transform: None,
+                execution_mode: ExecutionMode::Parallel,
+                traffic_split: None,
+                traffic_mirror: None,
+                response: None,
+                on_error: crate::config::ErrorPolicy::FailFast,
+                merge: None,
+                timeout_ms: None,
+                max_concurrency: None,
+                cache: None,
+                invalidate_tags: vec![],
+                rate_limit: None,
+                max_concurrent: None,
+                auth: None,
+                ip_filter: None,
+            }],
+            server: ServerConfig::default(),
+        };
+        let client_manager = ClientManager::from_config(&config).await.unwrap();
+        let router = crate::routes::build_router(AppState {
+            config: Arc::new(config),
+            client_manager: Arc::new(client_manager),
+            caches: Default::default(),
+            quotas: None,
+        });
+
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/api/files/a.bin?v=1")
+            .body(Body::from(vec![0u8, 159, 146, 150]))
+            .unwrap();
+        request.extensions_mut().insert(axum::extract::ConnectInfo(
+            "192.0.2.7:1234".parse::<std::net::SocketAddr>().unwrap(),
+        ));
+        let response = router.oneshot(request).await.unwrap();
+
+        // Small bodies are buffered, so the failed call was retried
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(response.headers()["x-upstream"], "yes");
+        assert_eq!(response.headers()["x-forwarded-for"], "192.0.2.7");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let mut expected = b"/files/a.bin?v=1".to_vec();
+        expected.extend_from_slice(&[0u8, 159, 146, 150]);
+        assert_eq!(body.to_vec(), expected);
+    }
+
+    #[test]
+    fn test_upstream_request_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", HeaderValue::from_static("gateway.local"));
+        headers.insert(
+            "connection",
+            HeaderValue::from_static("keep-alive, X-Client-Hop"),
+        );
+        headers.insert("x-client-hop", HeaderValue::from_static("1"));
+        headers.insert("authorization", HeaderValue::from_static("Bearer abc"));
+
+        let upstream = upstream_request_headers(&headers);
+        assert!(upstream.get("host").is_none());
+        assert!(upstream.get("connection").is_none());
+        // Headers named in Connection are hop-by-hop too
+        assert!(upstream.get("x-client-hop").is_none());
+        assert_eq!(upstream["authorization"], "Bearer abc");
+    }
+
+    #[test]
+    fn test_forwarded_headers() {
+        use crate::config::ClientIpConfig;
+
+        let client_ip = ClientIpResolver::new(&ClientIpConfig {
+            trusted_proxies: vec!["10.0.0.0/8".to_string()],
+            ..Default::default()
+        });
+        let mut incoming = HeaderMap::new();
+        incoming.insert("x-forwarded-for", HeaderValue::from_static("203.0.113.9"));
+        incoming.insert("x-forwarded-proto", HeaderValue::from_static("https"));
+        incoming.insert("forwarded", HeaderValue::from_static("for=203.0.113.9"));
+        let forwarded = |peer: &str| {
+            let mut upstream = upstream_request_headers(&incoming);
+            add_forwarded_headers(
+                &mut upstream,
+                &incoming,
+                Some(peer.parse().unwrap()),
+                &client_ip,
+            );
+            upstream
+        };
+
+        let upstream = forwarded("10.0.0.1");
+        assert_eq!(upstream["x-forwarded-for"], "203.0.113.9, 10.0.0.1");
+        assert_eq!(upstream["x-forwarded-proto"], "https");
+        assert_eq!(
+            upstream["forwarded"],
+            "for=203.0.113.9, for=10.0.0.1;proto=https"
+        );
+
+        // Only trusted proxies report the scheme
+        let upstream = forwarded("2001:db8::1");
+        assert_eq!(upstream["x-forwarded-for"], "203.0.113.9, 2001:db8::1");
+        assert_eq!(upstream["x-forwarded-proto"], "http");
+        assert_eq!(
+            upstream["forwarded"],
+            "for=203.0.113.9, for=\"[2001:db8::1]\";proto=http"
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use crate::config::ResponsePolicy;
+use crate::interpolation::InterpolationContext;
+use axum::{
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::Value;
+use tracing::debug;
+
+/// Headers that describe the upstream message framing and must never be forwarded
+const NON_FORWARDABLE_HEADERS: [HeaderName; 3] = [
+    header::CONTENT_LENGTH,
+    header::TRANSFER_ENCODING,
+    header::CONNECTION,
+];
+
+/// Build the route response, applying the route's response policy if configured
+pub fn build_response(
+    policy: Option<&ResponsePolicy>,
+    context: &InterpolationContext,
+    results: &[Value],
+    data: Value,
+) -> Response {
+    let Some(policy) = policy else {
+        return (StatusCode::OK, Json(data)).into_response();
+    };
+
+    let status = resolve_status(policy, context);
+    let mut response = (status, Json(data)).into_response();
+    forward_headers(policy, context, results, response.headers_mut());
+
+    response
+}
+
+/// Determine the response status from the configured subrequest and status mappings
+fn resolve_status(policy: &ResponsePolicy, context: &InterpolationContext) -> StatusCode {
+    let upstream = policy
+        .status_from
+        .as_ref()
+        .and_then(|name| context.subrequest_results.get(name))
+        .and_then(|result| result.get("status"))
+        .and_then(Value::as_u64)
+        .and_then(|status| u16::try_from(status).ok());
+
+    let Some(upstream) = upstream else {
+        return StatusCode::OK;
+    };
+
+    let status = policy
+        .status_map
+        .iter()
+        .find(|mapping| mapping.matches(upstream))
+        .map(|mapping| mapping.status)
+        .unwrap_or(upstream);
+
+    debug!(
+        "Resolved response status {} from upstream {}",
+        status, upstream
+    );
+
+    StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY)
+}
+
+/// Copy the selected upstream headers into the response
+fn forward_headers(
+    policy: &ResponsePolicy,
+    context: &InterpolationContext,
+    results: &[Value],
+    headers: &mut HeaderMap,
+) {
+    if policy.forward_headers.is_empty() {
+        return;
+    }
+
+    let sources: Vec<&Value> = if !policy.headers_from.is_empty() {
+        policy
+            .headers_from
+            .iter()
+            .filter_map(|name| context.subrequest_results.get(name))
+            .collect()
+    } else if let Some(name) = &policy.status_from {
+        context.subrequest_results.get(name).into_iter().collect()
+    } else {
+        results
+            .iter()
+            .filter(|result| result.get("type").and_then(Value::as_str) == Some("http"))
+            .collect()
+    };
+
+    for name in &policy.forward_headers {
+        let Ok(header_name) = HeaderName::from_bytes(name.to_lowercase().as_bytes()) else {
+            continue;
+        };
+        if NON_FORWARDABLE_HEADERS.contains(&header_name) {
+            continue;
+        }
+
+        for source in &sources {
+            let Some(value) = source
+                .get("headers")
+                .and_then(|h| h.get(header_name.as_str()))
+                .and_then(Value::as_str)
+            else {
+                continue;
+            };
+
+            // Cookies from every source are kept, one header line per cookie
+            if header_name == header::SET_COOKIE {
+                for cookie in value.split('\n') {
+                    if let Ok(header_value) = HeaderValue::from_str(cookie) {
+                        headers.append(header_name.clone(), header_value);
+                    }
+                }
+                continue;
+            }
+
+            // Other headers are taken from the first source that has them
+            if let Ok(header_value) = HeaderValue::from_str(value) {
+                headers.insert(header_name.clone(), header_value);
+            }
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StatusMapping;
+    use axum::http::Method;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn context_with(results: &[(&str, Value)]) -> InterpolationContext {
+        let mut context = InterpolationContext::new(
+            HeaderMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            None,
+            Method::GET,
+        );
+        for (name, result) in results {
+            context.add_subrequest_result(name.to_string(), result.clone());
+        }
+        context
+    }
+
+    fn http_result(status: u16, headers: Value) -> Value {
+        json!({"type": "http", "status": status, "body": "", "headers": headers})
+    }
+
+    #[test]
+    fn test_default_policy_returns_ok() {
+        let context = context_with(&[("user", http_result(500, json!({})))]);
+        let response = build_response(None, &context, &[], json!({}));
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_status_from_subrequest() {
+        let context = context_with(&[("user", http_result(404, json!({})))]);
+        let policy = ResponsePolicy {
+            status_from: Some("user".to_string()),
+            ..Default::default()
+        };
+
+        let response = build_response(Some(&policy), &context, &[], json!({}));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_status_mapping() {
+        let context = context_with(&[("user", http_result(503, json!({})))]);
+        let policy = ResponsePolicy {
+            status_from: Some("user".to_string()),
+            status_map: vec![StatusMapping {
+                upstream: "5xx".to_string(),
+                status: 502,
+            }],
+            ..Default::default()
+        };
+
+        let response = build_response(Some(&policy), &context, &[], json!({}));
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[test]
+    fn test_forward_headers() {
+        let user = http_result(
+            200,
+            json!({
+                "etag": "\"abc\"",
+                "cache-control": "max-age=60",
+                "content-length": "42",
+                "set-cookie": "a=1\nb=2"
+            }),
+        );
+        let context = context_with(&[("user", user.clone())]);
+        let policy = ResponsePolicy {
+            forward_headers: vec![
+                "ETag".to_string(),
+                "Cache-Control".to_string(),
+                "Content-Length".to_string(),
+                "Set-Cookie".to_string(),
+            ],
+            ..Default::default()
+        };
+
+        let response = build_response(Some(&policy), &context, &[user], json!({}));
+        let headers = response.headers();
+        assert_eq!(headers[header::ETAG], "\"abc\"");
+        assert_eq!(headers[header::CACHE_CONTROL], "max-age=60");
+        assert_ne!(
+            headers
+                .get(header::CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok()),
+            Some("42")
+        );
+        assert_eq!(headers.get_all(header::SET_COOKIE).iter().count(), 2);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::config::ResponseTransform;
+use crate::config::{MergeConfig, MergeStrategy, ResponseTransform};
 use crate::interpolation::InterpolationContext;
 use serde_json::{Map, Value};
 use std::collections::HashMap;
@@ -185,6 +185,84 @@ fn extract_field_value(data: &Value, path: &str) -> Value {
     current
 }
 
+/// Merge the results of the given subrequests into a single value
+pub fn merge_results<'a>(
+    merge: &MergeConfig,
+    names: impl IntoIterator<Item = &'a str>,
+    context: &InterpolationContext,
+) -> Value {
+    let payloads = names.into_iter().map(|name| {
+        let payload = context
+            .subrequest_results
+            .get(name)
+            .map(result_payload)
+            .unwrap_or(Value::Null);
+        (name, payload)
+    });
+
+    match merge.strategy {
+        MergeStrategy::Keyed => Value::Object(
+            payloads
+                .map(|(name, payload)| (name.to_string(), payload))
+                .collect(),
+        ),
+        // Only object results take part in a deep merge
+        MergeStrategy::Deep => payloads
+            .filter(|(_, payload)| payload.is_object())
+            .fold(Value::Object(Map::new()), |acc, (_, payload)| {
+                deep_merge(acc, payload)
+            }),
+        MergeStrategy::Concat => {
+            let mut items = Vec::new();
+            for (_, payload) in payloads {
+                match payload {
+                    Value::Array(values) => items.extend(values),
+                    Value::Null => {}
+                    other => items.push(other),
+                }
+            }
+            Value::Array(items)
+        }
+    }
+}
+
+/// Extract the payload of a subrequest result: the HTTP body, SQL rows,
+/// MongoDB documents or Redis value. Fan-out results are mapped element-wise
+/// and fallback values are used as-is.
+fn result_payload(result: &Value) -> Value {
+    if let Value::Array(items) = result {
+        return Value::Array(items.iter().map(result_payload).collect());
+    }
+
+    let field = match result.get("type").and_then(Value::as_str) {
+        Some("http") => "body",
+        Some("sql") => "rows",
+        Some("mongodb") => "documents",
+        Some("redis") => "value",
+        _ => return result.clone(),
+    };
+
+    result.get(field).cloned().unwrap_or(Value::Null)
+}
+
+/// Recursively merge `overlay` into `base`; non-object values replace the base value
+fn deep_merge(base: Value, overlay: Value) -> Value {
+    match (base, overlay) {
+        (Value::Object(mut base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                let merged = match base.remove(&key) {
+                    Some(existing) => deep_merge(existing, value),
+                    None => value,
+                };
+                base.insert(key, merged);
+            }
+            Value::Object(base)
+        }
+        (base, Value::Null) => base,
+        (_, overlay) => overlay,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -255,4 +333,73 @@ mod tests {
         assert!(result.get("field2").is_none());
         assert!(result.get("field3").is_some());
     }
+
+    fn merge_context() -> InterpolationContext {
+        let mut context = InterpolationContext::new(
+            axum::http::HeaderMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            None,
+            axum::http::Method::GET,
+        );
+        context.add_subrequest_result(
+            "user".to_string(),
+            json!({"type": "http", "status": 200, "body": {"id": 1, "profile": {"name": "Ada"}}}),
+        );
+        context.add_subrequest_result(
+            "prefs".to_string(),
+            json!({"type": "sql", "rows": {"profile": {"theme": "dark"}}}),
+        );
+        context.add_subrequest_result(
+            "orders".to_string(),
+            json!({"type": "http", "status": 200, "body": [{"id": 10}, {"id": 11}]}),
+        );
+        context.add_subrequest_result(
+            "archived".to_string(),
+            json!({"type": "mongodb", "documents": [{"id": 9}]}),
+        );
+        context
+    }
+
+    fn merge_config(strategy: MergeStrategy) -> MergeConfig {
+        MergeConfig {
+            strategy,
+            subrequests: vec![],
+        }
+    }
+
+    #[test]
+    fn test_merge_keyed() {
+        let result = merge_results(
+            &merge_config(MergeStrategy::Keyed),
+            ["user", "orders", "missing"],
+            &merge_context(),
+        );
+        assert_eq!(result["user"]["profile"]["name"], "Ada");
+        assert_eq!(result["orders"][1]["id"], 11);
+        assert!(result["missing"].is_null());
+    }
+
+    #[test]
+    fn test_merge_deep() {
+        let result = merge_results(
+            &merge_config(MergeStrategy::Deep),
+            ["user", "prefs", "orders", "missing"],
+            &merge_context(),
+        );
+        assert_eq!(
+            result,
+            json!({"id": 1, "profile": {"name": "Ada", "theme": "dark"}})
+        );
+    }
+
+    #[test]
+    fn test_merge_concat() {
+        let result = merge_results(
+            &merge_config(MergeStrategy::Concat),
+            ["orders", "archived", "missing"],
+            &merge_context(),
+        );
+        assert_eq!(result, json!([{"id": 10}, {"id": 11}, {"id": 9}]));
+    }
 }